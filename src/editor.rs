@@ -1,14 +1,48 @@
-use std::{collections::HashMap, rc::Rc};
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
 use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-mod graphics;
+pub mod graphics;
+pub mod input;
 pub mod keymaps;
-mod highlighter;
+pub mod macros;
+pub mod highlighter;
 pub mod buffer;
-mod fonts;
+pub mod buffer_list;
+pub mod clipboard;
+pub mod command_line;
+pub mod commands;
+pub mod config;
+pub mod ex;
+pub mod fonts;
+pub mod motions;
+pub mod operators;
+pub mod options;
+pub mod registers;
+pub mod repeat;
+pub mod search;
+pub mod substitute;
+pub mod textobjects;
+pub mod theme;
+pub mod visual;
 
 use buffer::Buffer;
+use buffer_list::BufferList;
+use command_line::CommandLine;
+use commands::CommandRegistry;
+use ex::ExRegistry;
 use fonts::{Font, FontDefinition};
+use highlighter::{Grammar, Highlighter, ScopeFonts};
+use macros::MacroRecording;
+use operators::PendingOperator;
+use options::Options;
+use registers::Registers;
+use repeat::LastChange;
+use search::{LastSearch, PendingSearch};
+use substitute::{PendingSubstitute, Substitution};
+use theme::UiColors;
 
 use keymaps::*;
 
@@ -19,6 +53,8 @@ pub enum EditMode {
     Insert,
     Visual,
     Command,
+    /// waiting for the motion or text object of an operator.
+    OperatorPending,
 }
 
 impl fmt::Display for EditMode {
@@ -28,20 +64,75 @@ impl fmt::Display for EditMode {
             Self::Insert => write!(formatter, "INSERT"),
             Self::Visual => write!(formatter, "VISUAL"),
             Self::Command => write!(formatter, "COMMAND"),
+            Self::OperatorPending => write!(formatter, "OPERATOR"),
         }
     }
 }
 
 pub struct EditorState {
     pub theme: HashMap<Font, FontDefinition>,
-    pub normal_chain: Vec<(KeyPress, Chain)>,
-    pub visual_chain: Vec<(KeyPress, Chain)>,
-    pub insert_chain: Vec<(KeyPress, Chain)>,
+    pub ui_colors: UiColors,
+    /// the name of the theme in use, see `set_colorscheme`.
+    pub colorscheme: String,
+    /// the languages buffers can be highlighted as.
+    pub grammars: Vec<Rc<Grammar>>,
+    /// the fonts scopes of TextMate and Sublime Text grammars are shown in.
+    pub scope_fonts: ScopeFonts,
+    /// where the config file says to load grammars from, if anywhere.
+    pub grammar_dir: Option<PathBuf>,
+    pub normal_chain: Rc<Chain>,
+    pub visual_chain: Rc<Chain>,
+    pub insert_chain: Rc<Chain>,
+    pub operator_chain: Rc<Chain>,
+    /// how far into the keymap of the current mode a sequence of keys has got.
+    pub pending_chain: Option<ChainCursor>,
+    /// when the pending sequence stops waiting for more keys, see `check_key_timeout`.
+    pub key_deadline: Option<Instant>,
 
     pub next_id: u32,
-    pub active_buffer: Buffer,
+    pub buffers: BufferList,
     pub mode: EditMode,
+    /// count typed so far in normal or visual mode.
+    pub pending_count: Option<usize>,
+    /// register picked with `"x` for the next command.
+    pub pending_register: Option<char>,
+    pub pending_operator: Option<PendingOperator>,
+    pub options: Options,
+    pub command_line: CommandLine,
+    /// the key `<leader>` stands for in `bind`.
+    pub leader: KeyPress,
+    /// the commands keys are bound to, by name.
+    pub commands: CommandRegistry,
+    pub ex_commands: ExRegistry,
+    pub last_search: Option<LastSearch>,
+    pub pending_search: Option<PendingSearch>,
+    /// set by `:nohlsearch` until the next search.
+    pub search_highlight_hidden: bool,
+    pub last_substitution: Option<Substitution>,
+    /// a `:s///c` waiting for its answer.
+    pub pending_substitute: Option<PendingSubstitute>,
+    pub registers: Registers,
+    /// the macro being recorded with `q`, if any.
+    pub recording: Option<MacroRecording>,
+    /// the register `@@` plays.
+    pub last_macro: Option<char>,
+    /// keys waiting to be processed as if they were typed, ie. a macro.
+    pub typeahead: VecDeque<KeyPress>,
+    /// the keys of the normal mode command being typed, from its first key.
+    /// None if the current mode wasn't entered through normal mode keys.
+    pub change_keys: Option<Vec<KeyPress>>,
+    /// set when the command being typed has changed the buffer.
+    pub change_finished: bool,
+    /// what `.` repeats.
+    pub last_change: Option<LastChange>,
+    /// set when a key fails to do anything, ie. a motion that can't move.
+    /// This stops the rest of the typeahead.
+    pub command_failed: bool,
+    /// set when the editor should close, ie. by `:q`.
+    pub should_quit: bool,
     pub status_line: String,
+    /// shown after the status line until the next keystroke.
+    pub message: String,
 }
 
 // The problem with this whole approach is that you have this EditorState struct, which is trying to manipulate itself.
@@ -51,96 +142,684 @@ impl EditorState {
         let mut default_buffer = Buffer::new(0);
         let mut normal_chain = Chain::new();
         let mut insert_chain = Chain::new();
-        let mut visual_chain = Chain::new();
 
-        normal_chain.insert('i'.into(), (|s: &mut EditorState| s.set_insert_mode()).into());
-        insert_chain.insert('\x1b'.into(), (|s: &mut EditorState| s.set_normal_mode()).into());
+        normal_chain.insert('i'.into(), "editor.insert_mode".into());
+        motions::insert_motions(&mut normal_chain);
+        operators::insert_operators(&mut normal_chain);
+        visual::insert_visual_toggles(&mut normal_chain);
+        search::insert_search(&mut normal_chain);
+        substitute::insert_substitute_repeats(&mut normal_chain);
+        macros::insert_macros(&mut normal_chain);
+        registers::insert_registers(&mut normal_chain);
+        repeat::insert_repeat(&mut normal_chain);
+        normal_chain.insert(':'.into(), "editor.command_line".into());
+        normal_chain.insert('u'.into(), "buffer.undo".into());
+        normal_chain.insert(KeyPress::control('r'), "buffer.redo".into());
+        normal_chain.insert(KeyPress::control('s'), "buffer.save".into());
+        // Ctrl-^ is Ctrl-6 on most keyboards.
+        for notation in ["<C-^>", "<C-6>"] {
+            bind(&mut normal_chain, notation, "buffer.alternate");
+        }
 
+        bind(&mut normal_chain, "]b", "buffer.next");
+        bind(&mut normal_chain, "[b", "buffer.previous");
+        insert_chain.insert(Key::Escape.into(), "editor.normal_mode".into());
+        motions::insert_arrow_keys(&mut insert_chain);
 
-        let mut theme: HashMap<Font, FontDefinition> = HashMap::new();
 
-        theme.insert(Font::Normal, Default::default());
+        let theme = theme::default_theme();
 
         EditorState {
-            theme,
-            normal_chain: vec![(' '.into(), normal_chain)],
-            visual_chain: vec![(' '.into(), visual_chain)],
-            insert_chain: vec![(' '.into(), insert_chain)],
+            theme: theme.fonts,
+            ui_colors: theme.ui,
+            colorscheme: theme.name,
+            grammars: highlighter::grammar::builtin_grammars().into_iter().map(Rc::new).collect(),
+            scope_fonts: ScopeFonts::default(),
+            grammar_dir: None,
+            normal_chain: Rc::new(normal_chain),
+            visual_chain: Rc::new(visual::visual_chain()),
+            insert_chain: Rc::new(insert_chain),
+            operator_chain: Rc::new(operators::operator_chain()),
+            pending_chain: None,
+            key_deadline: None,
             next_id: 1,
-            active_buffer: Buffer::new(0),
+            buffers: BufferList::new(Buffer::new(0)),
             mode: EditMode::Normal,
+            pending_count: None,
+            pending_register: None,
+            pending_operator: None,
+            options: Options::default(),
+            command_line: CommandLine::new(),
+            leader: KeyPress::from(DEFAULT_LEADER),
+            commands: CommandRegistry::default(),
+            ex_commands: ExRegistry::default(),
+            last_search: None,
+            pending_search: None,
+            search_highlight_hidden: false,
+            last_substitution: None,
+            pending_substitute: None,
+            registers: Registers::new(),
+            recording: None,
+            last_macro: None,
+            typeahead: VecDeque::new(),
+            change_keys: None,
+            change_finished: false,
+            last_change: None,
+            command_failed: false,
+            should_quit: false,
             status_line: String::new(),
+            message: String::new(),
+        }
+    }
+
+    pub fn active_buffer(&self) -> &Buffer {
+        self.buffers.active()
+    }
+
+    pub fn active_buffer_mut(&mut self) -> &mut Buffer {
+        self.buffers.active_mut()
+    }
+
+    fn allocate_buffer_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Opens an empty buffer and switches to it.
+    pub fn new_buffer(&mut self) -> u32 {
+        let id = self.allocate_buffer_id();
+        self.buffers.add(Buffer::new(id));
+        self.buffers.switch_to(id);
+        id
+    }
+
+    /// Switches to the buffer holding `path`, loading it first if it isn't open yet.
+    pub fn open_file<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        if let Some(id) = self.buffers.find_path(path) {
+            self.buffers.switch_to(id);
+            return;
+        }
+
+        let id = self.allocate_buffer_id();
+        match Buffer::open(id, path) {
+            Ok(mut buffer) => {
+                let grammar = self.grammars.iter().find(|grammar| grammar.matches_path(path));
+                buffer.set_highlighter(grammar.map(|grammar| Highlighter::new(grammar.clone())));
+                if let Some(notice) = open_notice(path, &buffer) {
+                    self.message = notice;
+                }
+                self.buffers.add(buffer);
+                self.buffers.switch_to(id);
+            }
+            Err(e) => self.message = format!("\"{}\" {}", path.display(), e),
+        }
+    }
+
+    pub fn save_file(&mut self) {
+        self.message = match self.active_buffer_mut().save() {
+            Ok(()) => match self.active_buffer().get_path() {
+                Some(path) => format!("\"{}\" written", path.display()),
+                None => String::new(),
+            },
+            Err(e) => format!("{}", e),
+        };
+    }
+
+    pub fn switch_buffer(&mut self, id: u32) {
+        if !self.buffers.switch_to(id) {
+            self.message = format!("no buffer {}", id);
+        }
+    }
+
+    pub fn alternate_buffer(&mut self) {
+        if !self.buffers.switch_alternate() {
+            self.message = String::from("no alternate buffer");
+        }
+    }
+
+    /// Closes a buffer. Buffers with unsaved changes are only closed when
+    /// `force` is set. Closing the last buffer leaves an empty one behind.
+    pub fn close_buffer(&mut self, id: u32, force: bool) {
+        let dirty = match self.buffers.get(id) {
+            Some(buffer) => buffer.is_dirty(),
+            None => {
+                self.message = format!("no buffer {}", id);
+                return;
+            }
+        };
+        if dirty && !force {
+            self.message = format!("buffer {} has unsaved changes", id);
+            return;
+        }
+
+        if self.buffers.len() == 1 {
+            self.new_buffer();
         }
+        self.buffers.remove(id);
     }
+
+    /// Lists the open buffers in the message area, marking the active (%) and
+    /// alternate (#) buffers.
+    pub fn list_buffers(&mut self) {
+        let active = self.active_buffer().get_id();
+        let alternate = self.buffers.alternate();
+
+        let entries: Vec<String> = self
+            .buffers
+            .iter()
+            .map(|buffer| {
+                let id = buffer.get_id();
+                let marker = if id == active {
+                    "%"
+                } else if Some(id) == alternate {
+                    "#"
+                } else {
+                    " "
+                };
+                let dirty = if buffer.is_dirty() { "+" } else { " " };
+                let name = match buffer.get_path() {
+                    Some(path) => path.display().to_string(),
+                    None => String::from("[No Name]"),
+                };
+                format!("{}{}{} {}", id, marker, dirty, name)
+            })
+            .collect();
+
+        self.message = entries.join(" | ");
+    }
+
+    /// The keymap used in `mode`. Command mode has none, keys there go to
+    /// the command line.
+    fn root_chain(&mut self, mode: EditMode) -> Option<&mut Rc<Chain>> {
+        match mode {
+            EditMode::Normal => Some(&mut self.normal_chain),
+            EditMode::Insert => Some(&mut self.insert_chain),
+            EditMode::Visual => Some(&mut self.visual_chain),
+            EditMode::OperatorPending => Some(&mut self.operator_chain),
+            EditMode::Command => None,
+        }
+    }
+
+    /// Binds a sequence of keys, written in key notation like `<leader>fs`
+    /// or `<C-x><C-s>`, to a command in the keymap of `mode`. The command can
+    /// be followed by its arguments, ie. `buffer.switch 2`. A sequence which
+    /// is being typed carries on in the keymap it started in.
+    pub fn bind(&mut self, mode: EditMode, notation: &str, command: &str) -> Result<(), String> {
+        self.parse_command_line(command)?;
+        let command = command.trim();
+        let keys = parse_keys(notation, self.leader)?;
+        match self.root_chain(mode) {
+            Some(chain) => insert_keys(Rc::make_mut(chain), &keys, command.into()),
+            None => Err(format!("No keymap for {} mode", mode)),
+        }
+    }
+
+    /// Removes the binding for a sequence of keys in the keymap of `mode`.
+    pub fn unbind(&mut self, mode: EditMode, notation: &str) -> Result<(), String> {
+        let keys = parse_keys(notation, self.leader)?;
+        match self.root_chain(mode) {
+            Some(chain) => remove_keys(Rc::make_mut(chain), &keys).map_err(|e| format!("{}: {}", e, notation)),
+            None => Err(format!("No keymap for {} mode", mode)),
+        }
+    }
+
+    /// Where the next key is looked up: partway through the sequence being
+    /// typed, or at the root of the current mode's keymap.
+    fn chain_cursor(&mut self) -> Option<ChainCursor> {
+        match &self.pending_chain {
+            Some(cursor) => Some(cursor.clone()),
+            None => self.root_chain(self.mode).map(|chain| ChainCursor::new(chain.clone())),
+        }
+    }
+
     /// takes a keystroke, processes it, and alters state according to internal state and
     /// the keystroke.
-    pub fn process_keystroke(&mut self, key: char) {
+    pub fn process_keystroke(&mut self, key: KeyPress) {
+        self.message.clear();
+
+        if let Some(recording) = &mut self.recording {
+            // a `q` on its own stops the recording, and isn't part of it.
+            let at_root = self.pending_chain.is_none() && self.pending_count.is_none();
+            if at_root && matches!(self.mode, EditMode::Normal) && key == KeyPress::from('q') {
+                self.stop_recording();
+                return;
+            }
+            recording.keys.push(key);
+        }
+
+        self.process_key(key);
+        while let Some(key) = self.typeahead.pop_front() {
+            self.process_key(key);
+        }
+    }
+
+    /// Processes a typed key, or one from the typeahead. If it fails, the
+    /// rest of the typeahead is thrown away.
+    fn process_key(&mut self, key: KeyPress) {
+        self.command_failed = false;
+        self.capture_change_key(key);
+        if self.pending_substitute.is_some() {
+            self.process_substitute_key(key);
+        } else {
+            self.dispatch_key(key);
+        }
+        self.record_change();
+
+        if self.command_failed {
+            self.typeahead.clear();
+        }
+    }
+
+    fn dispatch_key(&mut self, key: KeyPress) {
         match self.mode {
-            EditMode::Normal => {
-                if let Some(func) = Self::validate_chain(key.into(), &mut self.normal_chain) {
-                    (*func)(self);
+            EditMode::Normal | EditMode::Visual => {
+                if !self.process_counted_key(key) {
+                    self.command_failed = true;
+                }
+            }
+            EditMode::Insert => self.process_insert_key(key),
+            EditMode::OperatorPending => {
+                if !self.process_counted_key(key) {
+                    self.cancel_operator();
+                    self.command_failed = true;
                 }
             }
-            EditMode::Insert => {
-                if let Some(func) = Self::validate_chain(key.into(), &mut self.insert_chain) {
-                    (*func)(self);
+            EditMode::Command => self.process_command_line_key(key),
+        }
+    }
+
+    fn process_insert_key(&mut self, key: KeyPress) {
+        let step = match self.chain_cursor() {
+            Some(cursor) => cursor.step(key),
+            None => return,
+        };
+        match step {
+            ChainStep::Run(name) => {
+                self.clear_pending_keys();
+                self.run_bound_command(&name, ChainContext::default());
+            }
+            ChainStep::Pending(cursor) => self.set_pending_chain(cursor),
+            ChainStep::Unbound => {
+                if self.flush_pending_keys_before(key) {
+                    // the key didn't continue the sequence, but it may start a new one.
+                    self.dispatch_key(key);
                 } else {
-                    self.active_buffer.insert_at_cursor(key);
+                    self.insert_key(key);
+                }
+            }
+        }
+    }
+
+    /// Types a key which isn't bound in insert mode into the buffer.
+    fn insert_key(&mut self, key: KeyPress) {
+        let buffer = self.active_buffer_mut();
+        match key.key {
+            Key::Backspace if !key.modifiers.is_command() => buffer.insert_at_cursor('\x08'),
+            Key::Delete if !key.modifiers.is_command() => buffer.delete_at_cursor(),
+            _ => {
+                if let Some(c) = key.typed_char() {
+                    buffer.insert_at_cursor(c);
                 }
             }
-            EditMode::Visual => {}
-            EditMode::Command => {}
         }
     }
 
-    // returns true if key was a valid input for chain.
-    fn validate_chain(key: KeyPress, chain: &mut Vec<(KeyPress, Chain)>)
-                      -> Option<Box<dyn Fn(&mut EditorState)>> {
-        let end = chain.len()-1;
-        if let Some((key, entry)) = chain[end].1.remove_entry(&key) {
-            match entry {
-                ChainLink::Func(func) => {
-                    // fold the chain back into the root.
-                    for i in (1..=end).rev() {
-                        let subchain = chain.pop().expect("premature end of chain");
-                        chain[i-1].1.insert(subchain.0, ChainLink::SubChain(subchain.1));
-                    }
+    /// Feeds a key to the chain of the current mode. Digits typed before a
+    /// chain is started build up a count, which is handed to the function
+    /// the chain ends in.
+    ///
+    /// Returns false if the key didn't match anything in the chain.
+    fn process_counted_key(&mut self, key: KeyPress) -> bool {
+        let at_root = self.pending_chain.is_none();
+        if let Some(digit) = key.plain_char().and_then(|c| c.to_digit(10)) {
+            // a leading 0 is a motion, not a count.
+            if at_root && (digit != 0 || self.pending_count.is_some()) {
+                let count = self.pending_count.unwrap_or(0);
+                self.pending_count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+                return true;
+            }
+        }
 
-                    Some(func)
-                },
-                // not a function, so add this layer to the vector.
-                ChainLink::SubChain(subchain) => {
-                    chain.push((key, subchain));
-                    None
-                },
+        let step = match self.chain_cursor() {
+            Some(cursor) => cursor.step(key),
+            None => return false,
+        };
+        match step {
+            ChainStep::Run(name) => {
+                self.clear_pending_keys();
+                let context = self.take_chain_context();
+                self.run_bound_command(&name, context);
+                true
             }
+            ChainStep::Pending(cursor) => {
+                self.set_pending_chain(cursor);
+                true
+            }
+            ChainStep::Unbound if !at_root && key.is_abort() => {
+                // the sequence is dropped, even if the keys so far are bound.
+                self.clear_pending_keys();
+                self.pending_count = None;
+                self.pending_register = None;
+                if let EditMode::OperatorPending = self.mode {
+                    self.cancel_operator();
+                }
+                true
+            }
+            ChainStep::Unbound => {
+                if self.flush_pending_keys_before(key) {
+                    self.dispatch_key(key);
+                    return true;
+                }
+                // the key didn't go anywhere, so the count and register are thrown away.
+                self.pending_count = None;
+                self.pending_register = None;
+                false
+            }
+        }
+    }
+
+    /// The count and register typed for the command about to run.
+    fn take_chain_context(&mut self) -> ChainContext {
+        ChainContext {
+            count: self.pending_count.take(),
+            register: self.pending_register.take(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Waits for the rest of a sequence. If the keys so far are a binding
+    /// on their own, or are typed in insert mode, the wait can time out.
+    fn set_pending_chain(&mut self, cursor: ChainCursor) {
+        // in insert mode the keys are typed as text once the wait is over.
+        let waits = cursor.command().is_some() || matches!(self.mode, EditMode::Insert);
+        self.key_deadline = if waits && self.options.timeout {
+            Some(Instant::now() + Duration::from_millis(self.options.timeout_len as u64))
         } else {
             None
+        };
+        self.pending_chain = Some(cursor);
+    }
+
+    fn clear_pending_keys(&mut self) {
+        self.pending_chain = None;
+        self.key_deadline = None;
+    }
+
+    /// Gives up on the sequence being typed. If the keys so far are bound
+    /// on their own, that binding runs; in insert mode they are typed into
+    /// the buffer instead. Returns true if either happened.
+    fn flush_pending_keys(&mut self) -> bool {
+        let cursor = match self.pending_chain.take() {
+            Some(cursor) => cursor,
+            None => return false,
+        };
+        self.key_deadline = None;
+
+        if let Some(name) = cursor.command() {
+            let context = self.take_chain_context();
+            self.run_bound_command(name, context);
+        } else if let EditMode::Insert = self.mode {
+            for key in cursor.keys() {
+                self.insert_key(*key);
+            }
+        } else {
+            return false;
+        }
+        // the flushed keys are a command of their own, which may have
+        // finished a change.
+        self.record_change();
+        true
+    }
+
+    /// Flushes the sequence being typed because `key` didn't continue it.
+    /// `key` is captured as part of whatever comes after the flushed keys.
+    fn flush_pending_keys_before(&mut self, key: KeyPress) -> bool {
+        if let Some(keys) = &mut self.change_keys {
+            keys.pop();
+        }
+        let flushed = self.flush_pending_keys();
+        self.capture_change_key(key);
+        flushed
+    }
+
+    /// Settles the sequence being typed once its deadline has passed.
+    /// Returns true if it was settled.
+    pub fn check_key_timeout(&mut self, now: Instant) -> bool {
+        match self.key_deadline {
+            Some(deadline) if now >= deadline => {
+                self.flush_pending_keys();
+                true
+            }
+            _ => false,
         }
     }
 
     pub fn set_mode(&mut self, new_mode: EditMode) {
+        // everything typed during an insert session is undone as one change.
+        match (self.mode, new_mode) {
+            (EditMode::Insert, EditMode::Insert) => {}
+            (_, EditMode::Insert) => self.active_buffer_mut().begin_change(),
+            (EditMode::Insert, _) => {
+                self.active_buffer_mut().end_change();
+                self.finish_change();
+            }
+            _ => {}
+        }
+        // the selection only lives as long as visual mode.
+        if matches!(self.mode, EditMode::Visual) && !matches!(new_mode, EditMode::Visual) {
+            self.active_buffer_mut().clear_selection();
+        }
         self.mode = new_mode
     }
     pub fn set_insert_mode(&mut self) {
-        self.mode = EditMode::Insert
+        self.set_mode(EditMode::Insert)
     }
     pub fn set_normal_mode(&mut self) {
-        self.mode = EditMode::Normal
+        self.set_mode(EditMode::Normal)
     }
     pub fn set_visual_mode(&mut self) {
-        self.mode = EditMode::Visual
+        self.set_mode(EditMode::Visual)
     }
     pub fn set_command_mode(&mut self) {
-        self.mode = EditMode::Command
+        self.set_mode(EditMode::Command)
     }
 
     pub fn get_mode(&mut self) -> EditMode {
         self.mode
     }
 
+    /// Highlights the active buffer with the grammar called `name`, or
+    /// stops highlighting it if `name` is `off`.
+    pub fn set_syntax(&mut self, name: &str) -> Result<(), String> {
+        let highlighter = match name {
+            "off" => None,
+            name => match self.grammars.iter().find(|grammar| grammar.name == name) {
+                Some(grammar) => Some(Highlighter::new(grammar.clone())),
+                None => return Err(format!("No grammar called {}", name)),
+            },
+        };
+        self.active_buffer_mut().set_highlighter(highlighter);
+        Ok(())
+    }
+
+    /// Loads the TextMate and Sublime Text grammars in `dir`, ahead of the
+    /// grammars already loaded so they are picked first for a file. Returns
+    /// the problems with the files which couldn't be loaded.
+    pub fn load_grammars(&mut self, dir: &Path) -> Vec<String> {
+        let (grammars, errors) = highlighter::grammar::load_grammars(dir, &self.scope_fonts);
+        self.grammars.splice(0..0, grammars.into_iter().map(Rc::new));
+        errors
+    }
+
     pub fn update(&mut self) {
-        let (line, col) = self.active_buffer.get_cursor_pos();
-        self.status_line = format!("[{}] [{}:{}]", self.mode, line, col);
+        self.active_buffer_mut().update_highlighting(highlighter::CHUNK_LINES, highlighter::CHUNK_TIME);
+        let buffer = self.active_buffer();
+        let (line, col) = buffer.get_cursor_pos();
+        let name = match buffer.get_path() {
+            Some(path) => path.display().to_string(),
+            None => String::from("[No Name]"),
+        };
+        let dirty = if buffer.is_dirty() { " [+]" } else { "" };
+        let keys = match &self.pending_chain {
+            Some(cursor) => keys_to_notation(cursor.keys()),
+            None => String::new(),
+        };
+        let register = match self.pending_register {
+            Some(register) => format!("\"{}", register),
+            None => String::new(),
+        };
+        let count = self.pending_count.map(|count| count.to_string()).unwrap_or_default();
+        let pending = format!("{}{}{}", register, count, keys);
+        let pending = if pending.is_empty() { pending } else { format!(" {}", pending) };
+
+        let recording = match &self.recording {
+            Some(recording) => format!(" recording @{}", recording.register),
+            None => String::new(),
+        };
+
+        self.status_line = match self.mode {
+            EditMode::Command => self.command_line.display(),
+            _ => format!(
+                "[{}]{}{} [{}:{}] {}: {}{} {}",
+                self.mode, recording, pending, line, col, buffer.get_id(), name, dirty, self.message
+            ),
+        };
+    }
+}
+
+/// What to tell the user about a file just loaded from `path`, if anything.
+fn open_notice(path: &Path, buffer: &Buffer) -> Option<String> {
+    if !path.exists() {
+        Some(format!("\"{}\" [New]", path.display()))
+    } else {
+        buffer.mixed_line_endings().map(|ending| {
+            format!("\"{}\" has mixed line endings, it will be written with {}", path.display(), ending.name())
+        })
+    }
+}
+
+#[cfg(test)]
+impl EditorState {
+    /// An editor whose buffer holds `text`, with the cursor at its start.
+    pub fn with_text(text: &str) -> Self {
+        let mut state = Self::new();
+        let buffer = state.active_buffer_mut();
+        buffer.replace_range(0..0, text);
+        buffer.set_cursor_pos((1, 0));
+        state
+    }
+
+    /// Types the keys written in `notation`, ie. `d2w` or `<Esc>`.
+    pub fn type_keys(&mut self, notation: &str) {
+        for key in parse_keys(notation, self.leader).expect("invalid key notation") {
+            self.process_keystroke(key);
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.active_buffer().get_text().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abort_a_bound_prefix() {
+        for abort in ["<Esc>", "<C-c>"] {
+            let mut state = EditorState::with_text("one two\nthree");
+            state.bind(EditMode::Normal, "g", "motion.line_end").unwrap();
+            state.type_keys("lg");
+            state.update();
+            assert!(state.status_line.contains(" g "), "{}", state.status_line);
+
+            state.type_keys(abort);
+            assert_eq!(state.active_buffer().get_cursor_pos(), (1, 1));
+            assert!(state.pending_chain.is_none() && state.key_deadline.is_none());
+            state.update();
+            assert!(!state.status_line.contains(" g "), "{}", state.status_line);
+
+            // back at the root, where the prefix starts again.
+            state.type_keys("gg");
+            assert_eq!(state.active_buffer().get_cursor_pos(), (1, 0));
+        }
+    }
+
+    #[test]
+    fn abort_drops_the_count_and_operator() {
+        let mut state = EditorState::with_text("1\n2\n3\n4");
+        state.bind(EditMode::Normal, "<leader>", "buffer.undo").unwrap();
+        state.bind(EditMode::Normal, "<leader>x", "buffer.undo").unwrap();
+        state.type_keys("2\\<Esc>j");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 0));
+
+        state.bind(EditMode::OperatorPending, "g", "motion.line_end").unwrap();
+        state.type_keys("dg<Esc>");
+        assert!(matches!(state.mode, EditMode::Normal));
+        assert_eq!(state.text(), "1\n2\n3\n4");
+        state.type_keys("w");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (3, 0));
+    }
+
+    #[test]
+    fn unknown_key_flushes_a_bound_prefix() {
+        let mut state = EditorState::with_text("one two\nthree");
+        state.bind(EditMode::Normal, "g", "motion.line_end").unwrap();
+        state.type_keys("gj");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 4));
+    }
+
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    #[test]
+    fn counts_repeat_motions() {
+        let mut state = EditorState::with_text("a b c d e\n1\n2\n3\n4");
+        state.type_keys("3w");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (1, 6));
+        state.type_keys("10l");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (1, 8));
+        // a leading 0 is a motion, but not after other digits.
+        state.type_keys("0");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (1, 0));
+        state.type_keys("20j");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (5, 0));
+    }
+
+    #[test]
+    fn counts_before_and_after_operators_multiply() {
+        let text = "1 2 3 4 5 6 7 8";
+        assert_eq!(after(text, "3dw"), "4 5 6 7 8");
+        assert_eq!(after(text, "d3w"), "4 5 6 7 8");
+        assert_eq!(after(text, "2d3w"), "7 8");
+        assert_eq!(after("1\n2\n3\n4\n5", "2dd"), "3\n4\n5");
+        assert_eq!(after("1\n2\n3\n4\n5", "2d2d"), "5");
+    }
+
+    #[test]
+    fn count_shows_while_typed() {
+        let mut state = EditorState::with_text("one");
+        state.type_keys("12");
+        state.update();
+        assert!(state.status_line.contains(" 12 "), "{}", state.status_line);
+        state.type_keys("d3");
+        state.update();
+        assert!(state.status_line.contains(" 3 "), "{}", state.status_line);
+        state.type_keys("<Esc>");
+        state.update();
+        assert!(!state.status_line.contains(" 3 "), "{}", state.status_line);
+    }
+
+    #[test]
+    fn count_is_dropped_by_an_unbound_key() {
+        let mut state = EditorState::with_text("1\n2\n3\n4");
+        state.type_keys("3<F5>j");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 0));
     }
 }
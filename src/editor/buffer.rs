@@ -1,55 +1,139 @@
 use std::char;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use super::fonts::Font;
+use ropey::{Rope, RopeSlice};
 
-// when a font is inserted into the buffer, that font will be applied to any text which succeeds it.
-#[derive(Clone)]
-pub enum BufferEntry {
-    Text(char),
-    Font(Font),
+use super::highlighter::{Highlight, Highlighter};
+
+pub mod history;
+use history::{Edit, History};
+
+pub mod file;
+use file::LineEnding;
+
+pub mod search;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionKind {
+    Charwise,
+    Linewise,
+    Blockwise,
 }
 
-impl From<char> for BufferEntry {
-    fn from(c: char) -> Self {
-        BufferEntry::Text(c)
+/// The lines an edit touched: lines `start..old_end` of the text before
+/// the edit are lines `start..new_end` after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EditEvent {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+impl EditEvent {
+    /// The lines `edit` touches in `text`, before it is applied.
+    fn new(text: &Rope, edit: &Edit) -> Self {
+        let start = text.char_to_line(edit.char_index);
+        Self {
+            start,
+            old_end: start + edit.removed.matches('\n').count() + 1,
+            new_end: start + edit.inserted.matches('\n').count() + 1,
+        }
     }
 }
 
+/// A visual selection. It runs from `anchor` to wherever the cursor is, so
+/// moving the cursor extends it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: (usize, usize),
+    pub kind: SelectionKind,
+}
+
 /// A text buffer with cached metadata.
 ///
-/// Usage:
-/// ```
-/// let buffer = Buffer::new(0);
-/// buffer.insert_at_cursor('a');
-/// buffer.delete_at_cursor();
+/// The text itself lives in a rope, so inserts and deletes are O(log n) and
+/// cloning a buffer shares the underlying text instead of copying it. Fonts
+/// are layered on when the buffer is displayed.
 ///
-/// let line = Line::new();
-/// let line.insert_str("Hello There!!!");
+/// Buffers can be loaded from and saved to a file. The line ending and
+/// byte order mark of the file are kept, see `Buffer::open`.
 ///
-/// // put three lines into the buffer
-/// buffer.insert_line_below(line.clone());
-/// buffer.insert_line_below(line.clone());
-/// buffer.insert_line_below(line.clone());
+/// Every change to the text is recorded in an undo tree. Edits made between
+/// `begin_change` and `end_change` are undone as one unit.
 ///
-/// assert_eq!(buffer.to_string(),
-/// "Hello There!!!
-/// Hello There!!!
-/// Hello There!!!");
+/// Usage:
+/// ```
+/// let mut buffer = Buffer::new(0);
+/// buffer.insert_at_cursor('a');
+/// buffer.insert_at_cursor('b');
+/// buffer.insert_at_cursor('\x08'); // backspace
+/// buffer.insert_at_cursor('\n');
+/// buffer.insert_at_cursor('c');
 ///
-/// assert_eq!(buffer.move_cursor_down(1), 2);
-/// assert_eq!(buffer.move_cursor_down(1), 3);
+/// assert_eq!(buffer.to_string(), "a\nc");
 /// ```
 #[derive(Clone)]
 pub struct Buffer {
+    id: u32,
     cursor_pos: (usize, usize),
-    lines: Vec<Vec<BufferEntry>>,
+    preferred_column: Option<usize>,
+    /// the line (0 indexed) at the top of the screen.
+    first_visible_line: usize,
+    selection: Option<Selection>,
+    /// bounds of the last selection, for the `'<` and `'>` marks.
+    last_selection: Option<((usize, usize), (usize, usize))>,
+    text: Rope,
+    history: History,
+
+    path: Option<PathBuf>,
+    line_ending: LineEnding,
+    has_bom: bool,
+    mixed_line_endings: bool,
+    /// the history revision that matches what is on disk.
+    saved_revision: usize,
+    highlighter: Option<Highlighter>,
 }
 
 impl Buffer {
     pub fn new(id: u32) -> Self {
         Self {
-            lines: vec![vec![]],
+            id,
+            text: Rope::new(),
             cursor_pos: (1, 0),
+            preferred_column: None,
+            first_visible_line: 0,
+            selection: None,
+            last_selection: None,
+            history: History::new(),
+            path: None,
+            line_ending: LineEnding::default(),
+            has_bom: false,
+            mixed_line_endings: false,
+            saved_revision: 0,
+            highlighter: None,
+        }
+    }
+
+    /// A copy of the text and cursor without the undo history. This is
+    /// cheap, and is what the display buffer is built from.
+    pub fn snapshot(&self) -> Self {
+        Self {
+            id: self.id,
+            text: self.text.clone(),
+            cursor_pos: self.cursor_pos,
+            preferred_column: self.preferred_column,
+            first_visible_line: self.first_visible_line,
+            selection: self.selection,
+            last_selection: self.last_selection,
+            history: History::new(),
+            path: self.path.clone(),
+            line_ending: self.line_ending,
+            has_bom: self.has_bom,
+            mixed_line_endings: self.mixed_line_endings,
+            saved_revision: 0,
+            highlighter: None,
         }
     }
 
@@ -57,74 +141,448 @@ impl Buffer {
         // cursor_pos holds a line number and column index. lines start at 1.
         let (mut line_index, mut column_index) = self.cursor_pos;
         line_index -= 1;
+        let char_index = self.text.line_to_char(line_index) + column_index;
 
         match c {
             '\n' | '\r' => {
-                self.lines[line_index].insert(column_index, '\n'.into());
-                column_index += 1;
-
-                let newline = self.lines[line_index].split_off(column_index);
-                self.lines.insert(line_index + 1, newline);
-                column_index = 0;
-                line_index += 1;
+                self.edit(char_index..char_index, "\n", (line_index + 2, 0));
             }
 
             '\x08' | '\x7f' => {
-                if self.lines[line_index].is_empty() && self.lines.len() > 1 {
-                    self.lines.remove(line_index);
+                if column_index > 0 {
+                    column_index -= 1;
+                } else if line_index > 0 {
+                    // join with the line above by removing its trailing newline.
                     line_index -= 1;
-                    self.lines[line_index].pop(); // remove trailing newline
-                    column_index = self.lines[line_index].len();
-                } else if self.lines[line_index].is_empty() {
-                    // we are on the first line. and its empty.
-                    // do nothing.
+                    column_index = self.line_len(line_index);
                 } else {
-                    self.lines[line_index].remove(column_index - 1);
-                    column_index -= 1;
+                    // we are at the very start of the buffer.
+                    // do nothing.
+                    return;
                 }
+                self.edit(char_index - 1..char_index, "", (line_index + 1, column_index));
             }
 
             '\t' => {
-                for _ in 0..3 {
-                    self.lines[line_index].push(' '.into());
-                    column_index += 1;
-                }
+                self.edit(char_index..char_index, "   ", (line_index + 1, column_index + 3));
             }
 
             _ => {
-                self.lines[line_index].insert(column_index, c.into());
-                column_index += 1;
+                let mut encoded = [0; 4];
+                self.edit(
+                    char_index..char_index,
+                    c.encode_utf8(&mut encoded),
+                    (line_index + 1, column_index + 1),
+                );
             }
         }
+    }
 
-        self.cursor_pos = (line_index+1, column_index);
+    pub fn delete_at_cursor(&mut self) {
+        let (line, column) = self.cursor_pos;
+        if column < self.line_len(line - 1) {
+            let char_index = self.text.line_to_char(line - 1) + column;
+            self.edit(char_index..char_index + 1, "", self.cursor_pos);
+        }
     }
 
-    pub fn insert_line_above(&mut self, line: Vec<BufferEntry>) {
-        self.lines.insert(self.cursor_pos.0-1, line);
-        self.cursor_pos.1 += 1;
+    /// Overwrites the character under the cursor. When the cursor is past the
+    /// end of the line, the character is inserted instead.
+    pub fn replace_at_cursor(&mut self, c: char) {
+        let (line, column) = self.cursor_pos;
+        let char_index = self.text.line_to_char(line - 1) + column;
+        let end = if column < self.line_len(line - 1) {
+            char_index + 1
+        } else {
+            char_index
+        };
+
+        let mut encoded = [0; 4];
+        self.edit(char_index..end, c.encode_utf8(&mut encoded), self.cursor_pos);
     }
-    pub fn insert_line_below(&mut self, line: Vec<BufferEntry>) {
-        self.lines.insert(self.cursor_pos.0, line);
+
+    /// Replaces a range of characters with `text`, leaving the cursor at the
+    /// start of the range.
+    pub fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        let cursor_after = self.char_to_pos(range.start);
+        self.edit(range, text, cursor_after);
     }
 
-    pub fn delete_at_cursor(&mut self) {
-        self.lines[self.cursor_pos.0 - 1].remove(self.cursor_pos.1);
+    /// Deletes a range of characters, leaving the cursor at the start of the range.
+    pub fn delete_range(&mut self, range: Range<usize>) {
+        self.replace_range(range, "");
+    }
+
+    /// Copies a range of characters out of the buffer.
+    pub fn get_range(&self, range: Range<usize>) -> String {
+        self.text.slice(range).to_string()
     }
 
-    pub fn get_current_line(&self) -> &Vec<BufferEntry> {
-        &self.lines[self.cursor_pos.0 - 1]
+    /// Groups every edit until `end_change` into a single undo step.
+    pub fn begin_change(&mut self) {
+        self.history.begin_group(self.cursor_pos);
     }
 
-    pub fn get_current_line_mut(&mut self) -> &mut Vec<BufferEntry> {
-        &mut self.lines[self.cursor_pos.0 - 1]
+    pub fn end_change(&mut self) {
+        self.history.end_group();
+    }
+
+    /// Reverts the last change. Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo() {
+            Some(change) => {
+                for edit in change.edits.iter().rev() {
+                    Self::apply_edit(&mut self.text, &mut self.highlighter, &edit.inverted());
+                }
+                self.cursor_pos = change.cursor_before;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapplies the last undone change. Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo() {
+            Some(change) => {
+                for edit in change.edits.iter() {
+                    Self::apply_edit(&mut self.text, &mut self.highlighter, edit);
+                }
+                self.cursor_pos = change.cursor_after;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// True if the text differs from the last time it was loaded or saved.
+    pub fn is_dirty(&self) -> bool {
+        self.history.revision() != self.saved_revision || self.history.has_open_edits()
+    }
+
+    fn mark_saved(&mut self) {
+        // commit whatever is being typed, so the saved revision is exact.
+        if self.history.has_open_edits() {
+            self.history.end_group();
+            self.history.begin_group(self.cursor_pos);
+        }
+        self.saved_revision = self.history.revision();
+    }
+
+    pub fn get_id(&self) -> u32 {
+        self.id
     }
 
     pub fn get_cursor_pos(&self) -> (usize, usize) {
         self.cursor_pos
     }
 
-    pub fn get_lines(&self) -> &Vec<Vec<BufferEntry>> {
-        &self.lines
+    /// Moves the cursor, clamping it to the text.
+    pub fn set_cursor_pos(&mut self, (line, column): (usize, usize)) {
+        let line = line.clamp(1, self.line_count());
+        self.cursor_pos = (line, column.min(self.line_len(line - 1)));
+    }
+
+    /// The column vertical motions try to return to, if one is remembered.
+    pub fn get_preferred_column(&self) -> Option<usize> {
+        self.preferred_column
+    }
+
+    pub fn set_preferred_column(&mut self, column: Option<usize>) {
+        self.preferred_column = column;
+    }
+
+    /// Starts a selection anchored at the cursor.
+    pub fn start_selection(&mut self, kind: SelectionKind) {
+        self.selection = Some(Selection {
+            anchor: self.cursor_pos,
+            kind,
+        });
+    }
+
+    pub fn set_selection_kind(&mut self, kind: SelectionKind) {
+        if let Some(selection) = &mut self.selection {
+            selection.kind = kind;
+        }
+    }
+
+    pub fn set_selection_anchor(&mut self, anchor: (usize, usize)) {
+        if let Some(selection) = &mut self.selection {
+            selection.anchor = anchor;
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        if self.selection.is_some() {
+            self.last_selection = self.selection_bounds();
+        }
+        self.selection = None;
+    }
+
+    /// Looks up a mark. Only `<` and `>`, the ends of the last visual
+    /// selection, exist so far.
+    pub fn get_mark(&self, mark: char) -> Option<(usize, usize)> {
+        let (start, end) = self.last_selection?;
+        let (line, column) = match mark {
+            '<' => start,
+            '>' => end,
+            _ => return None,
+        };
+        let line = line.min(self.line_count());
+        Some((line, column.min(self.line_len(line - 1))))
+    }
+
+    pub fn get_selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    /// Moves the cursor to the other end of the selection.
+    pub fn swap_selection_ends(&mut self) {
+        if let Some(selection) = &mut self.selection {
+            std::mem::swap(&mut selection.anchor, &mut self.cursor_pos);
+        }
+    }
+
+    /// The first and last positions of the selection, in buffer order.
+    pub fn selection_bounds(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.selection?.anchor;
+        if anchor <= self.cursor_pos {
+            Some((anchor, self.cursor_pos))
+        } else {
+            Some((self.cursor_pos, anchor))
+        }
+    }
+
+    /// The columns of `line` (1 indexed) covered by the selection, if any.
+    pub fn selected_columns(&self, line: usize) -> Option<Range<usize>> {
+        let selection = self.selection?;
+        let (start, end) = self.selection_bounds()?;
+        if line < start.0 || line > end.0 {
+            return None;
+        }
+
+        let len = self.line_len(line - 1);
+        let columns = match selection.kind {
+            SelectionKind::Linewise => 0..len,
+            SelectionKind::Charwise => {
+                let first = if line == start.0 { start.1 } else { 0 };
+                let last = if line == end.0 { (end.1 + 1).min(len) } else { len };
+                first..last
+            }
+            SelectionKind::Blockwise => {
+                let left = selection.anchor.1.min(self.cursor_pos.1);
+                let right = selection.anchor.1.max(self.cursor_pos.1) + 1;
+                left.min(len)..right.min(len)
+            }
+        };
+        Some(columns)
+    }
+
+    /// Converts a (line, column) position to an index into the text.
+    pub fn pos_to_char(&self, (line, column): (usize, usize)) -> usize {
+        self.text.line_to_char(line - 1) + column
+    }
+
+    /// Converts an index into the text to a (line, column) position.
+    pub fn char_to_pos(&self, char_index: usize) -> (usize, usize) {
+        let line_index = self.text.char_to_line(char_index);
+        (line_index + 1, char_index - self.text.line_to_char(line_index))
+    }
+
+    /// Returns lines `lines` (0 indexed) of the buffer, each including its
+    /// trailing newline.
+    pub fn get_lines(&self, lines: Range<usize>) -> impl Iterator<Item = RopeSlice<'_>> {
+        lines.map(move |line_index| self.text.line(line_index))
+    }
+
+    /// Scrolls so the cursor's line is one of the `rows` lines on screen,
+    /// and returns the lines (0 indexed) which are.
+    pub fn scroll_to_cursor(&mut self, rows: usize) -> Range<usize> {
+        let cursor_line = self.cursor_pos.0 - 1;
+        let rows = rows.max(1);
+        if cursor_line < self.first_visible_line {
+            self.first_visible_line = cursor_line;
+        } else if cursor_line >= self.first_visible_line + rows {
+            self.first_visible_line = cursor_line + 1 - rows;
+        }
+        self.first_visible_line..(self.first_visible_line + rows).min(self.line_count())
+    }
+
+    pub fn get_highlighter(&self) -> Option<&Highlighter> {
+        self.highlighter.as_ref()
+    }
+
+    /// Highlights the buffer with `highlighter` from now on, or stops
+    /// highlighting it. The whole buffer is highlighted again.
+    pub fn set_highlighter(&mut self, mut highlighter: Option<Highlighter>) {
+        if let Some(highlighter) = &mut highlighter {
+            highlighter.invalidate_all(self.text.len_lines());
+        }
+        self.highlighter = highlighter;
+    }
+
+    /// Highlights at most `max_lines` of the lines edited since the last
+    /// update, for no longer than `max_time`. Returns true once the
+    /// highlighting is up to date.
+    pub fn update_highlighting(&mut self, max_lines: usize, max_time: Duration) -> bool {
+        match &mut self.highlighter {
+            Some(highlighter) => highlighter.update(&self.text, max_lines, max_time),
+            None => true,
+        }
+    }
+
+    /// True if some lines still need highlighting, see `update_highlighting`.
+    pub fn is_highlighting(&self) -> bool {
+        self.highlighter.as_ref().is_some_and(|highlighter| !highlighter.is_up_to_date())
+    }
+
+    /// The highlights of a line (0 indexed), empty if the buffer isn't highlighted.
+    pub fn line_highlights(&self, line_index: usize) -> &[Highlight] {
+        self.highlighter.as_ref().map_or(&[], |highlighter| highlighter.line(line_index))
+    }
+
+    pub fn get_text(&self) -> &Rope {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    /// Length of a line (0 indexed), not counting its trailing newline.
+    pub fn line_len(&self, line_index: usize) -> usize {
+        let line = self.text.line(line_index);
+        let len = line.len_chars();
+        if len > 0 && line.char(len - 1) == '\n' {
+            len - 1
+        } else {
+            len
+        }
+    }
+
+    /// The text of a line (0 indexed), without its trailing newline.
+    pub fn line_text(&self, line_index: usize) -> String {
+        self.text.line(line_index).chars().take(self.line_len(line_index)).collect()
+    }
+
+    /// Replaces a range of characters with `text`, and records the edit so it
+    /// can be undone.
+    fn edit(&mut self, range: Range<usize>, text: &str, cursor_after: (usize, usize)) {
+        let edit = Edit {
+            char_index: range.start,
+            removed: self.text.slice(range).to_string(),
+            inserted: text.to_string(),
+        };
+        Self::apply_edit(&mut self.text, &mut self.highlighter, &edit);
+
+        self.history.record(edit, self.cursor_pos, cursor_after);
+        self.cursor_pos = cursor_after;
+        self.preferred_column = None;
+    }
+
+    /// Applies an edit to the text, and tells the highlighter which lines
+    /// it touched.
+    fn apply_edit(text: &mut Rope, highlighter: &mut Option<Highlighter>, edit: &Edit) {
+        let event = EditEvent::new(text, edit);
+        edit.apply(text);
+        if let Some(highlighter) = highlighter {
+            highlighter.edited(&event);
+        }
+    }
+}
+
+impl std::fmt::Display for Buffer {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(text: &str) -> Buffer {
+        let mut buffer = Buffer::new(0);
+        buffer.replace_range(0..0, text);
+        buffer
+    }
+
+    fn type_chars(buffer: &mut Buffer, chars: &str) {
+        for c in chars.chars() {
+            buffer.insert_at_cursor(c);
+        }
+    }
+
+    #[test]
+    fn insert_and_newline() {
+        let mut buffer = Buffer::new(0);
+        type_chars(&mut buffer, "ab\ncd\te");
+        assert_eq!(buffer.to_string(), "ab\ncd   e");
+        assert_eq!(buffer.get_cursor_pos(), (2, 6));
+
+        buffer.set_cursor_pos((1, 1));
+        type_chars(&mut buffer, "\n");
+        assert_eq!(buffer.to_string(), "a\nb\ncd   e");
+        assert_eq!(buffer.get_cursor_pos(), (2, 0));
+        assert_eq!(buffer.line_count(), 3);
+    }
+
+    #[test]
+    fn backspace_joins_lines() {
+        let mut buffer = buffer("ab\ncd");
+        buffer.set_cursor_pos((2, 0));
+        type_chars(&mut buffer, "\x08");
+        assert_eq!(buffer.to_string(), "abcd");
+        assert_eq!(buffer.get_cursor_pos(), (1, 2));
+
+        type_chars(&mut buffer, "\x7f\x08\x08\x08");
+        assert_eq!(buffer.to_string(), "cd");
+        assert_eq!(buffer.get_cursor_pos(), (1, 0));
+    }
+
+    #[test]
+    fn replace_at_cursor_past_the_end() {
+        let mut buffer = buffer("ab\n");
+        buffer.replace_at_cursor('x');
+        assert_eq!(buffer.to_string(), "xb\n");
+        buffer.set_cursor_pos((1, 2));
+        buffer.replace_at_cursor('y');
+        assert_eq!(buffer.to_string(), "xby\n");
+        assert_eq!(buffer.line_len(0), 3);
+    }
+
+    #[test]
+    fn snapshots_share_the_text() {
+        let mut original = buffer("one\ntwo");
+        let snapshot = original.snapshot();
+        original.insert_at_cursor('x');
+        assert_eq!(snapshot.to_string(), "one\ntwo");
+        assert_eq!(original.to_string(), "xone\ntwo");
+    }
+
+    #[test]
+    fn lines_and_positions() {
+        let buffer = buffer("one\ntwo\n\nfour");
+        let lines: Vec<String> = buffer.get_lines(1..3).map(String::from).collect();
+        assert_eq!(lines, ["two\n", "\n"]);
+        assert_eq!(buffer.line_text(3), "four");
+        assert_eq!(buffer.line_len(2), 0);
+        assert_eq!(buffer.pos_to_char((2, 1)), 5);
+        assert_eq!(buffer.char_to_pos(9), (4, 0));
+    }
+
+    #[test]
+    fn scroll_follows_the_cursor() {
+        let text: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+        let mut buffer = buffer(&text.join("\n"));
+        assert_eq!(buffer.scroll_to_cursor(4), 0..4);
+        buffer.set_cursor_pos((6, 0));
+        assert_eq!(buffer.scroll_to_cursor(4), 2..6);
+        buffer.set_cursor_pos((4, 0));
+        assert_eq!(buffer.scroll_to_cursor(4), 2..6);
+        buffer.set_cursor_pos((10, 0));
+        assert_eq!(buffer.scroll_to_cursor(20), 2..10);
+        buffer.set_cursor_pos((1, 0));
+        assert_eq!(buffer.scroll_to_cursor(0), 0..1);
     }
 }
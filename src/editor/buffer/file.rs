@@ -0,0 +1,296 @@
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use ropey::Rope;
+
+use super::Buffer;
+
+const UTF8_BOM: &str = "\u{feff}";
+
+/// The line ending a file was read with. Internally every line ends in `\n`,
+/// the original ending is put back when the file is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+
+    /// Picks whichever ending the first line break in `text` uses.
+    fn detect(text: &str) -> Self {
+        match text.find('\n') {
+            Some(i) if i > 0 && text.as_bytes()[i - 1] == b'\r' => Self::CrLf,
+            _ => Self::Lf,
+        }
+    }
+
+    /// True if `text` has line breaks other than the ones `detect` picked.
+    fn is_mixed(self, text: &str) -> bool {
+        let crlf = text.matches("\r\n").count();
+        match self {
+            Self::Lf => crlf > 0,
+            Self::CrLf => text.matches('\n').count() > crlf,
+        }
+    }
+}
+
+impl Buffer {
+    /// Loads a file into a new buffer. Only UTF-8 (with or without a BOM) is
+    /// supported, anything else is reported as `InvalidData`. A file that
+    /// doesn't exist yet gives an empty buffer, which creates it when saved.
+    ///
+    /// A file with both kinds of line ending is written back with the one
+    /// its first line break uses, see `mixed_line_endings`.
+    pub fn open<P: AsRef<Path>>(id: u32, path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut buffer = Self::new(id);
+                buffer.path = Some(path.to_path_buf());
+                return Ok(buffer);
+            }
+            Err(e) => return Err(e),
+        };
+        let contents = String::from_utf8(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let (has_bom, contents) = match contents.strip_prefix(UTF8_BOM) {
+            Some(rest) => (true, rest),
+            None => (false, contents.as_str()),
+        };
+
+        // every line break becomes `\n` whatever the file uses, the rope
+        // only knows that one.
+        let line_ending = LineEnding::detect(contents);
+        let text = Rope::from_str(&contents.replace("\r\n", "\n"));
+
+        let mut buffer = Self::new(id);
+        buffer.text = text;
+        buffer.path = Some(path.to_path_buf());
+        buffer.line_ending = line_ending;
+        buffer.has_bom = has_bom;
+        buffer.mixed_line_endings = line_ending.is_mixed(contents);
+        Ok(buffer)
+    }
+
+    /// Writes the buffer back to the file it was loaded from.
+    pub fn save(&mut self) -> io::Result<()> {
+        match self.path.clone() {
+            Some(path) => self.save_as(path),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "buffer has no file name")),
+        }
+    }
+
+    /// Writes the buffer to `path`, and makes that the buffer's file.
+    pub fn save_as<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        self.write_file(path, 0..self.text.len_chars())?;
+        self.path = Some(path.to_path_buf());
+        self.mark_saved();
+        Ok(())
+    }
+
+    /// Writes a copy of the buffer, or of lines `first..=last` of it, to
+    /// `path`. The buffer's own file stays what it was.
+    pub fn write_copy<P: AsRef<Path>>(&self, path: P, lines: Option<(usize, usize)>) -> io::Result<()> {
+        let range = match lines {
+            Some((first, last)) => {
+                let end = if last < self.text.len_lines() {
+                    self.text.line_to_char(last)
+                } else {
+                    self.text.len_chars()
+                };
+                self.text.line_to_char(first - 1)..end
+            }
+            None => 0..self.text.len_chars(),
+        };
+        self.write_file(path.as_ref(), range)
+    }
+
+    /// Writes the characters in `range` to `path`.
+    ///
+    /// The text is written to a temporary file next to `path` which is then
+    /// renamed over it, so a failed write never leaves a half written file.
+    /// The new file gets the permissions of the old one, and if `path` is a
+    /// symlink the file it points to is replaced, not the link.
+    fn write_file(&self, path: &Path, range: Range<usize>) -> io::Result<()> {
+        let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let temp_path = Self::temp_path(&target);
+
+        let result = self
+            .write_to(&temp_path, range)
+            .and_then(|_| match fs::metadata(&target) {
+                Ok(metadata) => fs::set_permissions(&temp_path, metadata.permissions()),
+                Err(_) => Ok(()),
+            })
+            .and_then(|_| fs::rename(&temp_path, &target));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    pub fn get_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// If the file had both `\n` and `\r\n` line breaks when it was loaded,
+    /// the one they all become when it is written.
+    pub fn mixed_line_endings(&self) -> Option<LineEnding> {
+        if self.mixed_line_endings {
+            Some(self.line_ending)
+        } else {
+            None
+        }
+    }
+
+    fn write_to(&self, path: &Path, range: Range<usize>) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        if self.has_bom {
+            file.write_all(UTF8_BOM.as_bytes())?;
+        }
+
+        let ending = self.line_ending.as_str();
+        for chunk in self.text.slice(range).chunks() {
+            match self.line_ending {
+                LineEnding::Lf => file.write_all(chunk.as_bytes())?,
+                LineEnding::CrLf => file.write_all(chunk.replace('\n', ending).as_bytes())?,
+            }
+        }
+
+        file.into_inner()?.sync_all()
+    }
+
+    fn temp_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+
+        let mut hidden = std::ffi::OsString::from(".");
+        hidden.push(name);
+        path.with_file_name(hidden)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for a test to put its files in.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text_ed_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn open_missing_file_gives_empty_buffer() {
+        let path = test_dir("missing").join("new.txt");
+        let mut buffer = Buffer::open(0, &path).unwrap();
+        assert_eq!(buffer.get_text().len_chars(), 0);
+        assert_eq!(buffer.get_path(), Some(path.as_path()));
+
+        buffer.insert_at_cursor('a');
+        buffer.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a");
+    }
+
+    #[test]
+    fn line_endings_and_bom_are_kept() {
+        let path = test_dir("crlf").join("file.txt");
+        fs::write(&path, "\u{feff}one\r\ntwo\r\n").unwrap();
+        let mut buffer = Buffer::open(0, &path).unwrap();
+        assert_eq!(buffer.get_text().to_string(), "one\ntwo\n");
+        assert_eq!(buffer.mixed_line_endings(), None);
+
+        buffer.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\u{feff}one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn mixed_line_endings_are_written_as_the_first() {
+        let path = test_dir("mixed").join("file.txt");
+        fs::write(&path, "one\r\ntwo\nthree\r\n").unwrap();
+        let mut buffer = Buffer::open(0, &path).unwrap();
+        assert_eq!(buffer.mixed_line_endings(), Some(LineEnding::CrLf));
+        buffer.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\r\ntwo\r\nthree\r\n");
+
+        fs::write(&path, "one\ntwo\r\nthree").unwrap();
+        let mut buffer = Buffer::open(0, &path).unwrap();
+        assert_eq!(buffer.mixed_line_endings(), Some(LineEnding::Lf));
+        assert_eq!(buffer.get_text().to_string(), "one\ntwo\nthree");
+        assert_eq!(buffer.line_len(1), 3);
+        buffer.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = test_dir("permissions").join("script.sh");
+        fs::write(&path, "echo\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+        let mut buffer = Buffer::open(0, &path).unwrap();
+        buffer.insert_at_cursor('#');
+        buffer.save().unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_writes_through_symlinks() {
+        let dir = test_dir("symlink");
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old\n").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let mut buffer = Buffer::open(0, &link).unwrap();
+        buffer.insert_at_cursor('x');
+        buffer.save().unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "xold\n");
+        assert_eq!(buffer.get_path(), Some(link.as_path()));
+    }
+
+    #[test]
+    fn write_copy_keeps_the_buffer_file() {
+        let dir = test_dir("copy");
+        let path = dir.join("file.txt");
+        let copy = dir.join("copy.txt");
+        fs::write(&path, "one\r\ntwo\r\nthree").unwrap();
+        let buffer = Buffer::open(0, &path).unwrap();
+
+        buffer.write_copy(&copy, None).unwrap();
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "one\r\ntwo\r\nthree");
+        buffer.write_copy(&copy, Some((2, 3))).unwrap();
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "two\r\nthree");
+        buffer.write_copy(&copy, Some((1, 1))).unwrap();
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "one\r\n");
+        assert_eq!(buffer.get_path(), Some(path.as_path()));
+    }
+}
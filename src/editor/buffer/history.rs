@@ -0,0 +1,312 @@
+use ropey::Rope;
+
+/// A single invertible change to the text: `removed` was taken out at
+/// `char_index` and `inserted` was put in its place.
+#[derive(Clone, Debug)]
+pub struct Edit {
+    pub char_index: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    pub fn inverted(&self) -> Self {
+        Self {
+            char_index: self.char_index,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+
+    pub fn apply(&self, text: &mut Rope) {
+        let removed_len = self.removed.chars().count();
+        text.remove(self.char_index..self.char_index + removed_len);
+        text.insert(self.char_index, &self.inserted);
+    }
+}
+
+/// A group of edits which are undone and redone together, along with where
+/// the cursor was on either side of it.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub edits: Vec<Edit>,
+    pub cursor_before: (usize, usize),
+    pub cursor_after: (usize, usize),
+}
+
+#[derive(Clone)]
+struct Node {
+    parent: usize,
+    change: Change,
+    /// the child that redo will walk into. this is the most recently
+    /// created or undone branch.
+    redo_child: Option<usize>,
+}
+
+/// An undo tree. Undoing and then making a new edit starts a new branch
+/// instead of throwing the undone changes away.
+///
+/// Node 0 is the root, the state of the buffer before any edits.
+#[derive(Clone)]
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    group: Option<Change>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        let root = Node {
+            parent: 0,
+            change: Change {
+                edits: Vec::new(),
+                cursor_before: (1, 0),
+                cursor_after: (1, 0),
+            },
+            redo_child: None,
+        };
+
+        Self {
+            nodes: vec![root],
+            current: 0,
+            group: None,
+        }
+    }
+
+    /// Starts grouping edits together until `end_group` is called.
+    pub fn begin_group(&mut self, cursor: (usize, usize)) {
+        if self.group.is_none() {
+            self.group = Some(Change {
+                edits: Vec::new(),
+                cursor_before: cursor,
+                cursor_after: cursor,
+            });
+        }
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(change) = self.group.take() {
+            if !change.edits.is_empty() {
+                self.commit(change);
+            }
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit, cursor_before: (usize, usize), cursor_after: (usize, usize)) {
+        match &mut self.group {
+            Some(change) => {
+                change.edits.push(edit);
+                change.cursor_after = cursor_after;
+            }
+            None => self.commit(Change {
+                edits: vec![edit],
+                cursor_before,
+                cursor_after,
+            }),
+        }
+    }
+
+    /// Identifies the state the text is in. Undoing back to an earlier state
+    /// gives back the same revision.
+    pub fn revision(&self) -> usize {
+        self.current
+    }
+
+    /// True while a group has collected edits which are not committed yet.
+    pub fn has_open_edits(&self) -> bool {
+        self.group.as_ref().is_some_and(|change| !change.edits.is_empty())
+    }
+
+    /// Steps back to the parent of the current change, returning the change
+    /// that has to be reverted.
+    pub fn undo(&mut self) -> Option<&Change> {
+        self.end_group();
+        if self.current == 0 {
+            return None;
+        }
+
+        let undone = self.current;
+        self.current = self.nodes[undone].parent;
+        self.nodes[self.current].redo_child = Some(undone);
+        Some(&self.nodes[undone].change)
+    }
+
+    /// Steps forward into the most recently used branch, returning the
+    /// change that has to be reapplied.
+    pub fn redo(&mut self) -> Option<&Change> {
+        self.end_group();
+        let next = self.nodes[self.current].redo_child?;
+        self.current = next;
+        Some(&self.nodes[next].change)
+    }
+
+    fn commit(&mut self, change: Change) {
+        let parent = self.current;
+        self.nodes.push(Node {
+            parent,
+            change,
+            redo_child: None,
+        });
+        self.current = self.nodes.len() - 1;
+        self.nodes[parent].redo_child = Some(self.current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::EditorState;
+
+    fn insert(char_index: usize, text: &str) -> Edit {
+        Edit {
+            char_index,
+            removed: String::new(),
+            inserted: text.to_string(),
+        }
+    }
+
+    /// Records `edit` on its own and applies it to `text`.
+    fn record(history: &mut History, text: &mut Rope, edit: Edit) {
+        edit.apply(text);
+        history.record(edit, (1, 0), (1, 0));
+    }
+
+    fn undo(history: &mut History, text: &mut Rope) -> bool {
+        match history.undo() {
+            Some(change) => {
+                for edit in change.edits.iter().rev() {
+                    edit.inverted().apply(text);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn redo(history: &mut History, text: &mut Rope) -> bool {
+        match history.redo() {
+            Some(change) => {
+                for edit in &change.edits {
+                    edit.apply(text);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[test]
+    fn edits_are_inverted() {
+        let mut text = Rope::from_str("one two");
+        let edit = Edit {
+            char_index: 4,
+            removed: String::from("two"),
+            inserted: String::from("2"),
+        };
+        edit.apply(&mut text);
+        assert_eq!(text.to_string(), "one 2");
+        edit.inverted().apply(&mut text);
+        assert_eq!(text.to_string(), "one two");
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let (mut history, mut text) = (History::new(), Rope::new());
+        assert!(!undo(&mut history, &mut text));
+        record(&mut history, &mut text, insert(0, "a"));
+        record(&mut history, &mut text, insert(1, "b"));
+
+        assert!(undo(&mut history, &mut text));
+        assert_eq!(text.to_string(), "a");
+        assert!(undo(&mut history, &mut text));
+        assert_eq!(text.to_string(), "");
+        assert_eq!(history.revision(), 0);
+        assert!(!undo(&mut history, &mut text));
+
+        assert!(redo(&mut history, &mut text));
+        assert!(redo(&mut history, &mut text));
+        assert_eq!(text.to_string(), "ab");
+        assert_eq!(history.revision(), 2);
+        assert!(!redo(&mut history, &mut text));
+    }
+
+    #[test]
+    fn editing_after_an_undo_starts_a_branch() {
+        let (mut history, mut text) = (History::new(), Rope::new());
+        record(&mut history, &mut text, insert(0, "a"));
+        record(&mut history, &mut text, insert(1, "b"));
+        undo(&mut history, &mut text);
+        record(&mut history, &mut text, insert(1, "c"));
+        assert_eq!(text.to_string(), "ac");
+        assert_eq!(history.revision(), 3);
+        // the undone branch is kept, not overwritten.
+        assert_eq!(history.nodes.len(), 4);
+
+        // redo follows the newest branch.
+        undo(&mut history, &mut text);
+        undo(&mut history, &mut text);
+        redo(&mut history, &mut text);
+        redo(&mut history, &mut text);
+        assert_eq!(text.to_string(), "ac");
+    }
+
+    #[test]
+    fn redo_follows_the_branch_last_undone() {
+        let (mut history, mut text) = (History::new(), Rope::new());
+        record(&mut history, &mut text, insert(0, "a"));
+        undo(&mut history, &mut text);
+        record(&mut history, &mut text, insert(0, "b"));
+        assert_eq!(history.nodes[0].redo_child, Some(2));
+
+        undo(&mut history, &mut text);
+        assert_eq!(history.nodes[0].redo_child, Some(2));
+        redo(&mut history, &mut text);
+        assert_eq!(text.to_string(), "b");
+    }
+
+    #[test]
+    fn groups_are_one_change() {
+        let (mut history, mut text) = (History::new(), Rope::new());
+        history.begin_group((1, 0));
+        for (i, c) in ["a", "b", "c"].into_iter().enumerate() {
+            let edit = insert(i, c);
+            edit.apply(&mut text);
+            history.record(edit, (1, i), (1, i + 1));
+        }
+        assert!(history.has_open_edits());
+        history.end_group();
+        assert!(!history.has_open_edits());
+        assert_eq!(history.revision(), 1);
+
+        let change = history.undo().unwrap();
+        assert_eq!(change.edits.len(), 3);
+        assert_eq!((change.cursor_before, change.cursor_after), ((1, 0), (1, 3)));
+
+        // a group with nothing in it isn't a change.
+        history.begin_group((1, 0));
+        history.end_group();
+        assert_eq!(history.revision(), 0);
+    }
+
+    #[test]
+    fn an_insert_session_is_undone_at_once() {
+        let mut state = EditorState::with_text("one\n");
+        state.type_keys("jiab<Esc>u");
+        assert_eq!(state.text(), "one\n");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 0));
+        state.type_keys("<C-r>");
+        assert_eq!(state.text(), "one\nab");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 2));
+    }
+
+    #[test]
+    fn undo_puts_the_cursor_back() {
+        let mut state = EditorState::with_text("one two three");
+        state.type_keys("wdwu");
+        assert_eq!(state.text(), "one two three");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (1, 4));
+        state.type_keys("0<C-r>");
+        assert_eq!(state.text(), "one three");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (1, 4));
+    }
+}
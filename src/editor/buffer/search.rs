@@ -0,0 +1,108 @@
+use std::ops::Range;
+
+use regex::Regex;
+
+use super::Buffer;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::Forward => Self::Backward,
+            Self::Backward => Self::Forward,
+        }
+    }
+}
+
+/// Builds a regex from a search pattern. With `ignore_case` the pattern
+/// matches either case, unless `smart_case` is set and the pattern has an
+/// uppercase letter in it. `\c` and `\C` anywhere in the pattern force the
+/// case to be ignored or respected.
+pub fn compile_pattern(pattern: &str, ignore_case: bool, smart_case: bool) -> Result<Regex, String> {
+    let mut ignore_case = ignore_case && !(smart_case && pattern.chars().any(char::is_uppercase));
+    let mut cleaned = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('c')) => ignore_case = true,
+            ('\\', Some('C')) => ignore_case = false,
+            ('\\', Some(next)) => {
+                cleaned.push(c);
+                cleaned.push(next);
+            }
+            _ => {
+                cleaned.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+
+    let cleaned = if ignore_case { format!("(?i){}", cleaned) } else { cleaned };
+    Regex::new(&cleaned).map_err(|e| format!("Invalid pattern: {}", e))
+}
+
+/// Regex matches are made one line at a time, a match never spans a line break.
+impl Buffer {
+    /// The matches of `regex` on a line (0 indexed), as column ranges.
+    pub fn line_matches(&self, regex: &Regex, line_index: usize) -> Vec<Range<usize>> {
+        let line = self.line_text(line_index);
+
+        let mut columns = Vec::new();
+        let mut column = 0;
+        let mut byte = 0;
+        let mut to_column = |target: usize| {
+            column += line[byte..target].chars().count();
+            byte = target;
+            column
+        };
+        for found in regex.find_iter(&line) {
+            let start = to_column(found.start());
+            let end = to_column(found.end());
+            columns.push(start..end);
+        }
+        columns
+    }
+
+    /// Finds the nearest match of `regex` which starts after (or before,
+    /// searching backward) the position `from`. With `wrap`, the search
+    /// carries on from the other end of the buffer. Returns the match as a
+    /// range of chars.
+    pub fn search(&self, regex: &Regex, from: (usize, usize), direction: Direction, wrap: bool) -> Option<Range<usize>> {
+        let line_count = self.line_count();
+        let (from_line, from_column) = (from.0 - 1, from.1);
+
+        // every line is visited once, the starting line twice: once for the
+        // part after `from`, and once (when wrapping) for the part before it.
+        for step in 0..=line_count {
+            let (line_index, wrapped) = match direction {
+                Direction::Forward => ((from_line + step) % line_count, from_line + step >= line_count),
+                Direction::Backward => ((from_line + line_count - step) % line_count, step > from_line),
+            };
+            if wrapped && !wrap {
+                return None;
+            }
+
+            let matches = self.line_matches(regex, line_index);
+            let found = match (direction, step) {
+                (Direction::Forward, 0) => matches.into_iter().find(|m| m.start > from_column),
+                (Direction::Forward, s) if s == line_count => matches.into_iter().find(|m| m.start <= from_column),
+                (Direction::Forward, _) => matches.into_iter().next(),
+                (Direction::Backward, 0) => matches.into_iter().rev().find(|m| m.start < from_column),
+                (Direction::Backward, s) if s == line_count => matches.into_iter().rev().find(|m| m.start >= from_column),
+                (Direction::Backward, _) => matches.into_iter().next_back(),
+            };
+
+            if let Some(columns) = found {
+                let start = self.pos_to_char((line_index + 1, columns.start));
+                return Some(start..start + columns.len());
+            }
+        }
+        None
+    }
+}
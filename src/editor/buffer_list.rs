@@ -0,0 +1,235 @@
+use std::path::Path;
+
+use crate::editor::buffer::Buffer;
+
+/// Every buffer the editor has open, in the order they were opened.
+///
+/// There is always at least one buffer, and exactly one of them is active.
+/// The alternate buffer is whichever buffer was active before the last switch.
+pub struct BufferList {
+    buffers: Vec<Buffer>,
+    active: usize,
+    alternate: Option<u32>,
+}
+
+impl BufferList {
+    pub fn new(initial: Buffer) -> Self {
+        Self {
+            buffers: vec![initial],
+            active: 0,
+            alternate: None,
+        }
+    }
+
+    pub fn active(&self) -> &Buffer {
+        &self.buffers[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.active]
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Buffer> {
+        self.buffers.iter().find(|b| b.get_id() == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Buffer> {
+        self.buffers.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn alternate(&self) -> Option<u32> {
+        self.alternate
+    }
+
+    /// Finds an open buffer whose file is `path`.
+    pub fn find_path(&self, path: &Path) -> Option<u32> {
+        self.buffers
+            .iter()
+            .find(|b| b.get_path() == Some(path))
+            .map(|b| b.get_id())
+    }
+
+    /// Adds a buffer to the end of the list without switching to it.
+    pub fn add(&mut self, buffer: Buffer) -> u32 {
+        let id = buffer.get_id();
+        self.buffers.push(buffer);
+        id
+    }
+
+    /// Makes `id` the active buffer. Returns false if there is no such buffer.
+    pub fn switch_to(&mut self, id: u32) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                if index != self.active {
+                    self.alternate = Some(self.active().get_id());
+                    self.active = index;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn switch_alternate(&mut self) -> bool {
+        match self.alternate {
+            Some(id) => self.switch_to(id),
+            None => false,
+        }
+    }
+
+    /// Switches to the buffer `offset` places after the active one, wrapping
+    /// around at either end.
+    pub fn cycle(&mut self, offset: isize) {
+        let len = self.buffers.len() as isize;
+        let index = (self.active as isize + offset).rem_euclid(len) as usize;
+        let id = self.buffers[index].get_id();
+        self.switch_to(id);
+    }
+
+    /// Removes a buffer from the list. The last buffer can't be removed, and
+    /// closing the active buffer switches to the alternate (or a neighbour).
+    pub fn remove(&mut self, id: u32) -> Option<Buffer> {
+        if self.buffers.len() == 1 {
+            return None;
+        }
+        let index = self.index_of(id)?;
+
+        if index == self.active {
+            let fallback = self
+                .alternate
+                .filter(|&alt| alt != id)
+                .and_then(|alt| self.index_of(alt))
+                .unwrap_or(if index == 0 { 1 } else { index - 1 });
+            self.active = fallback;
+            self.alternate = None;
+        }
+
+        let active_id = self.active().get_id();
+        let buffer = self.buffers.remove(index);
+        self.active = self.index_of(active_id).expect("active buffer went missing");
+        if self.alternate == Some(id) {
+            self.alternate = None;
+        }
+
+        Some(buffer)
+    }
+
+    fn index_of(&self, id: u32) -> Option<usize> {
+        self.buffers.iter().position(|b| b.get_id() == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::EditorState;
+
+    /// A list of buffers with the ids given, the first one active.
+    fn list(ids: &[u32]) -> BufferList {
+        let mut list = BufferList::new(Buffer::new(ids[0]));
+        for &id in &ids[1..] {
+            list.add(Buffer::new(id));
+        }
+        list
+    }
+
+    fn active_id(list: &BufferList) -> u32 {
+        list.active().get_id()
+    }
+
+    #[test]
+    fn switch_and_alternate() {
+        let mut list = list(&[1, 2, 3]);
+        assert_eq!(list.alternate(), None);
+        assert!(!list.switch_alternate());
+
+        assert!(list.switch_to(3));
+        assert_eq!((active_id(&list), list.alternate()), (3, Some(1)));
+        // switching to the active buffer leaves the alternate alone.
+        assert!(list.switch_to(3));
+        assert_eq!(list.alternate(), Some(1));
+        assert!(!list.switch_to(7));
+
+        assert!(list.switch_alternate());
+        assert_eq!((active_id(&list), list.alternate()), (1, Some(3)));
+    }
+
+    #[test]
+    fn cycle_wraps_around() {
+        let mut list = list(&[1, 2, 3]);
+        list.cycle(-1);
+        assert_eq!(active_id(&list), 3);
+        list.cycle(1);
+        assert_eq!(active_id(&list), 1);
+        list.cycle(5);
+        assert_eq!(active_id(&list), 3);
+    }
+
+    #[test]
+    fn remove_buffers() {
+        let mut list = list(&[1, 2, 3]);
+        list.switch_to(3);
+        list.switch_to(2);
+        // the active buffer falls back to the alternate.
+        assert_eq!(list.remove(2).map(|buffer| buffer.get_id()), Some(2));
+        assert_eq!((active_id(&list), list.alternate()), (3, None));
+
+        // or else a neighbour.
+        assert!(list.remove(3).is_some());
+        assert_eq!(active_id(&list), 1);
+        assert!(list.remove(4).is_none());
+        // the last buffer stays.
+        assert!(list.remove(1).is_none());
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn removing_the_alternate_forgets_it() {
+        let mut list = list(&[1, 2, 3]);
+        list.switch_to(2);
+        list.remove(1);
+        assert_eq!((active_id(&list), list.alternate()), (2, None));
+    }
+
+    #[test]
+    fn buffers_keep_their_cursor() {
+        let mut state = EditorState::with_text("one\ntwo");
+        let first = state.active_buffer().get_id();
+        state.type_keys("jl:enew<CR>");
+        let second = state.active_buffer().get_id();
+        assert_ne!(first, second);
+        assert_eq!(state.text(), "");
+
+        state.type_keys("ix<Esc>:bn<CR>");
+        assert_eq!(state.active_buffer().get_id(), first);
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 1));
+        state.type_keys("<C-^>");
+        assert_eq!(state.text(), "x");
+        state.type_keys(&format!(":b {}<CR>", first));
+        assert_eq!(state.text(), "one\ntwo");
+    }
+
+    #[test]
+    fn close_buffers() {
+        let mut state = EditorState::with_text("one");
+        let first = state.active_buffer().get_id();
+        state.type_keys(":enew<CR>:bd<CR>");
+        assert_eq!(state.active_buffer().get_id(), first);
+        assert_eq!(state.buffers.len(), 1);
+
+        // unsaved changes need a !.
+        state.type_keys(":bd<CR>");
+        assert_eq!(state.message, format!("buffer {} has unsaved changes", first));
+        state.type_keys(":bd!<CR>");
+        assert_eq!(state.buffers.len(), 1);
+        assert_ne!(state.active_buffer().get_id(), first);
+        assert_eq!(state.text(), "");
+
+        state.type_keys(":bd 99<CR>");
+        assert_eq!(state.message, "no buffer 99");
+    }
+}
@@ -0,0 +1,52 @@
+/// Where the `+` and `*` registers keep their text. Both registers share
+/// one clipboard.
+pub trait ClipboardProvider {
+    fn get_text(&mut self) -> Result<String, String>;
+    fn set_text(&mut self, text: &str) -> Result<(), String>;
+}
+
+/// A clipboard which only lives as long as the editor, for tests and for
+/// when the system clipboard can't be reached.
+#[derive(Default)]
+pub struct MemoryClipboard {
+    text: String,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ClipboardProvider for MemoryClipboard {
+    fn get_text(&mut self) -> Result<String, String> {
+        Ok(self.text.clone())
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<(), String> {
+        self.text = text.to_string();
+        Ok(())
+    }
+}
+
+/// The clipboard of the operating system.
+pub struct SystemClipboard {
+    clipboard: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self, String> {
+        let clipboard = arboard::Clipboard::new().map_err(|e| e.to_string())?;
+        Ok(Self { clipboard })
+    }
+}
+
+impl ClipboardProvider for SystemClipboard {
+    fn get_text(&mut self) -> Result<String, String> {
+        self.clipboard.get_text().map_err(|e| e.to_string())
+    }
+
+    fn set_text(&mut self, text: &str) -> Result<(), String> {
+        self.clipboard.set_text(text.to_string()).map_err(|e| e.to_string())
+    }
+}
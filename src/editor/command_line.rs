@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use crate::editor::keymaps::{Key, KeyPress};
+use crate::editor::{EditMode, EditorState};
+
+/// The line typed after `:`, `/` or `?`, with a cursor and a history of
+/// earlier lines.
+#[derive(Default)]
+pub struct CommandLine {
+    /// `:` for ex commands, `/` and `?` for searches.
+    prompt: char,
+    text: String,
+    /// cursor position, in chars.
+    cursor: usize,
+    /// earlier lines, kept apart for commands and searches.
+    histories: HashMap<char, Vec<String>>,
+    /// which history entry is being shown while walking the history.
+    history_index: Option<usize>,
+}
+
+impl CommandLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_search(&self) -> bool {
+        matches!(self.prompt, '/' | '?')
+    }
+
+    /// Both search directions share one history.
+    fn history(&mut self) -> &mut Vec<String> {
+        let key = if self.is_search() { '/' } else { self.prompt };
+        self.histories.entry(key).or_default()
+    }
+
+    /// Starts a new line after `prompt`, optionally with some text already typed.
+    pub fn open(&mut self, prompt: char, initial: &str) {
+        self.prompt = prompt;
+        self.text = initial.to_string();
+        self.cursor = self.text.chars().count();
+        self.history_index = None;
+    }
+
+    /// Finishes the line, adding it to the history.
+    pub fn take(&mut self) -> String {
+        let text = std::mem::take(&mut self.text);
+        self.cursor = 0;
+        self.history_index = None;
+
+        let history = self.history();
+        if !text.is_empty() && history.last() != Some(&text) {
+            history.push(text.clone());
+        }
+        text
+    }
+
+    fn byte_index(&self, char_index: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(char_index)
+            .map_or(self.text.len(), |(i, _)| i)
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let i = self.byte_index(self.cursor);
+        self.text.insert(i, c);
+        self.cursor += 1;
+    }
+
+    /// Deletes the character before the cursor.
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            let i = self.byte_index(self.cursor);
+            self.text.remove(i);
+        }
+    }
+
+    /// Deletes the word before the cursor, like Ctrl-w.
+    pub fn delete_word(&mut self) {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut start = self.cursor;
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+
+        let (from, to) = (self.byte_index(start), self.byte_index(self.cursor));
+        self.text.replace_range(from..to, "");
+        self.cursor = start;
+    }
+
+    /// Deletes everything before the cursor, like Ctrl-u.
+    pub fn delete_to_start(&mut self) {
+        let to = self.byte_index(self.cursor);
+        self.text.replace_range(..to, "");
+        self.cursor = 0;
+    }
+
+    pub fn move_cursor(&mut self, offset: isize) {
+        let len = self.text.chars().count() as isize;
+        self.cursor = (self.cursor as isize + offset).clamp(0, len) as usize;
+    }
+
+    pub fn move_to_start(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_to_end(&mut self) {
+        self.cursor = self.text.chars().count();
+    }
+
+    /// Replaces the line with an older (`offset` < 0) or newer entry from the history.
+    pub fn walk_history(&mut self, offset: isize) {
+        let len = self.history().len() as isize;
+        if len == 0 {
+            return;
+        }
+
+        let current = self.history_index.map_or(len, |i| i as isize);
+        let next = current + offset;
+        if next < 0 {
+            return;
+        }
+
+        if next >= len {
+            self.history_index = None;
+            self.text.clear();
+        } else {
+            self.history_index = Some(next as usize);
+            self.text = self.history()[next as usize].clone();
+        }
+        self.move_to_end();
+    }
+
+    /// The line as it is shown in the status area, with a block for the cursor.
+    pub fn display(&self) -> String {
+        let mut display = String::from(self.prompt);
+        display.push_str(&self.text[..self.byte_index(self.cursor)]);
+        display.push('\u{2588}');
+        display.push_str(&self.text[self.byte_index(self.cursor)..]);
+        display
+    }
+}
+
+impl EditorState {
+    /// Opens the command line. From visual mode the selection is passed on
+    /// as the `'<,'>` range.
+    pub fn start_command_line(&mut self) {
+        let initial = match self.mode {
+            EditMode::Visual => "'<,'>",
+            _ => "",
+        };
+        self.command_line.open(':', initial);
+        self.set_command_mode();
+    }
+
+    /// Closes the command line without running it.
+    fn cancel_command_line(&mut self) {
+        if self.command_line.is_search() {
+            self.cancel_search();
+        }
+        self.set_normal_mode();
+    }
+
+    pub fn process_command_line_key(&mut self, key: KeyPress) {
+        let control = key.modifiers.control;
+        match key.key {
+            Key::Escape => {
+                self.cancel_command_line();
+                return;
+            }
+            Key::Enter => {
+                let line = self.command_line.take();
+                self.set_normal_mode();
+                if self.command_line.is_search() {
+                    self.finish_search(&line);
+                } else {
+                    self.execute_ex(&line);
+                }
+                return;
+            }
+            Key::Backspace => {
+                // backspacing over an empty line closes it.
+                if self.command_line.get_text().is_empty() {
+                    self.cancel_command_line();
+                    return;
+                }
+                self.command_line.backspace();
+            }
+            Key::Left => self.command_line.move_cursor(-1),
+            Key::Right => self.command_line.move_cursor(1),
+            Key::Up => self.command_line.walk_history(-1),
+            Key::Down => self.command_line.walk_history(1),
+            Key::Home => self.command_line.move_to_start(),
+            Key::End => self.command_line.move_to_end(),
+            Key::Char('w') if control => self.command_line.delete_word(),
+            Key::Char('u') if control => self.command_line.delete_to_start(),
+            Key::Char('b') if control => self.command_line.move_to_start(),
+            Key::Char('e') if control => self.command_line.move_to_end(),
+            Key::Char('p') if control => self.command_line.walk_history(-1),
+            Key::Char('n') if control => self.command_line.walk_history(1),
+            _ => {
+                if let Some(c) = key.plain_char() {
+                    self.command_line.insert(c);
+                }
+            }
+        }
+
+        if self.command_line.is_search() {
+            self.update_incremental_search();
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::editor::keymaps::ChainContext;
+use crate::editor::{macros, motions, operators, registers, repeat, search, substitute, textobjects, visual};
+use crate::editor::EditorState;
+
+pub type CommandFunc = Rc<dyn Fn(&mut EditorState, &ChainContext)>;
+
+/// What kind of value an argument takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    /// a whole number, ie. a buffer id.
+    Number,
+    /// any text. As the last argument, it takes the rest of the line.
+    Text,
+}
+
+/// An argument a command takes when it is run by name.
+#[derive(Clone, Copy, Debug)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+impl ArgSpec {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        Self { name, kind, optional: false }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        Self { name, kind, optional: true }
+    }
+}
+
+/// A named action of the editor. Keys are bound to commands by name, and
+/// the same commands can be run from the command line with `:call`.
+pub struct Command {
+    pub name: String,
+    pub description: String,
+    pub args: Vec<ArgSpec>,
+    pub func: CommandFunc,
+}
+
+impl Command {
+    /// Splits `text` into the command's arguments, checking there are
+    /// enough of them and that numbers are numbers.
+    pub fn parse_args(&self, text: &str) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        let mut rest = text.trim();
+        for (i, spec) in self.args.iter().enumerate() {
+            let arg = if spec.kind == ArgKind::Text && i == self.args.len() - 1 {
+                std::mem::take(&mut rest)
+            } else {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                let (arg, after) = rest.split_at(end);
+                rest = after.trim_start();
+                arg
+            };
+            if !arg.is_empty() {
+                args.push(arg.to_string());
+            }
+        }
+        if !rest.is_empty() {
+            return Err(format!("Trailing characters: {}", rest));
+        }
+        self.check_args(&args)?;
+        Ok(args)
+    }
+
+    pub fn check_args(&self, args: &[String]) -> Result<(), String> {
+        if args.len() > self.args.len() {
+            return Err(format!("Too many arguments for {}", self.name));
+        }
+        for (i, spec) in self.args.iter().enumerate() {
+            match args.get(i) {
+                None if !spec.optional => return Err(format!("{} needs an argument: {}", self.name, spec.name)),
+                Some(arg) if spec.kind == ArgKind::Number && arg.parse::<usize>().is_err() => {
+                    return Err(format!("{} should be a number: {}", spec.name, arg));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The command as it is listed, ie. `buffer.switch {id}`.
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for spec in &self.args {
+            if spec.optional {
+                usage.push_str(&format!(" [{}]", spec.name));
+            } else {
+                usage.push_str(&format!(" {{{}}}", spec.name));
+            }
+        }
+        usage
+    }
+}
+
+/// Every command the editor knows, by name. Names are grouped by what they
+/// act on, ie. `editor.insert_mode`, `motion.word_forward`, `buffer.next`.
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Rc<Command>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self { commands: BTreeMap::new() }
+    }
+
+    /// Adds a command, replacing any command with the same name.
+    pub fn register<N, D, F>(&mut self, name: N, description: D, args: &[ArgSpec], func: F)
+    where
+        N: Into<String>,
+        D: Into<String>,
+        F: Fn(&mut EditorState, &ChainContext) + 'static,
+    {
+        let name = name.into();
+        let command = Command {
+            name: name.clone(),
+            description: description.into(),
+            args: args.to_vec(),
+            func: Rc::new(func),
+        };
+        self.commands.insert(name, Rc::new(command));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rc<Command>> {
+        self.commands.get(name)
+    }
+
+    /// The commands in order of their names.
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<Command>> {
+        self.commands.values()
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        register_editor_commands(&mut registry);
+        motions::register_commands(&mut registry);
+        operators::register_commands(&mut registry);
+        textobjects::register_commands(&mut registry);
+        visual::register_commands(&mut registry);
+        search::register_commands(&mut registry);
+        substitute::register_commands(&mut registry);
+        macros::register_commands(&mut registry);
+        registers::register_commands(&mut registry);
+        repeat::register_commands(&mut registry);
+        registry
+    }
+}
+
+fn register_editor_commands(registry: &mut CommandRegistry) {
+    registry.register("editor.insert_mode", "switch to insert mode", &[], |s, _| s.set_insert_mode());
+    registry.register("editor.normal_mode", "switch to normal mode", &[], |s, _| s.set_normal_mode());
+    registry.register("editor.command_line", "open the command line", &[], |s, _| s.start_command_line());
+    registry.register(
+        "editor.ex",
+        "run a command line, ie. `write` or `%s/a/b/g`",
+        &[ArgSpec::required("line", ArgKind::Text)],
+        |s, ctx| s.execute_ex(ctx.arg(0).unwrap_or("")),
+    );
+
+    registry.register("buffer.undo", "undo the last change", &[], |s, ctx| {
+        for _ in 0..ctx.count() {
+            s.active_buffer_mut().undo();
+        }
+    });
+    registry.register("buffer.redo", "redo the last undone change", &[], |s, ctx| {
+        for _ in 0..ctx.count() {
+            s.active_buffer_mut().redo();
+        }
+    });
+    registry.register("buffer.save", "write the buffer to its file", &[], |s, _| s.save_file());
+    registry.register(
+        "buffer.open",
+        "open a file in a buffer",
+        &[ArgSpec::required("path", ArgKind::Text)],
+        |s, ctx| s.open_file(ctx.arg(0).unwrap_or("")),
+    );
+    registry.register(
+        "buffer.switch",
+        "switch to a buffer by number",
+        &[ArgSpec::required("id", ArgKind::Number)],
+        |s, ctx| {
+            if let Some(id) = ctx.arg(0).and_then(|id| id.parse().ok()) {
+                s.switch_buffer(id);
+            }
+        },
+    );
+    registry.register(
+        "buffer.close",
+        "close a buffer, by default the active one",
+        &[ArgSpec::optional("id", ArgKind::Number)],
+        |s, ctx| {
+            let id = match ctx.arg(0) {
+                Some(id) => id.parse().unwrap_or_default(),
+                None => s.active_buffer().get_id(),
+            };
+            s.close_buffer(id, false);
+        },
+    );
+    registry.register("buffer.alternate", "switch to the alternate buffer", &[], |s, _| s.alternate_buffer());
+    registry.register("buffer.next", "switch to the next buffer", &[], |s, ctx| {
+        s.buffers.cycle(ctx.count() as isize)
+    });
+    registry.register("buffer.previous", "switch to the previous buffer", &[], |s, ctx| {
+        s.buffers.cycle(-(ctx.count() as isize))
+    });
+}
+
+impl EditorState {
+    /// Runs a command by name, with its arguments already split up in `context`.
+    pub fn run_command(&mut self, name: &str, context: ChainContext) -> Result<(), String> {
+        let command = match self.commands.get(name) {
+            Some(command) => command.clone(),
+            None => return Err(format!("Unknown command: {}", name)),
+        };
+        command.check_args(&context.args)?;
+        (*command.func)(self, &context);
+        Ok(())
+    }
+
+    /// Runs a command written out with its arguments, ie. `buffer.switch 2`.
+    /// The count and register come from `context`.
+    pub fn run_command_line(&mut self, line: &str, context: ChainContext) -> Result<(), String> {
+        let (name, args) = self.parse_command_line(line)?;
+        self.run_command(name, ChainContext { args, ..context })
+    }
+
+    /// Splits a command line into the command's name and its arguments,
+    /// checking both.
+    pub fn parse_command_line<'a>(&self, line: &'a str) -> Result<(&'a str, Vec<String>), String> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if name.is_empty() {
+            return Err(String::from("Argument required"));
+        }
+        match self.commands.get(name) {
+            Some(command) => Ok((name, command.parse_args(rest)?)),
+            None => Err(format!("Unknown command: {}", name)),
+        }
+    }
+
+    /// Runs a command bound to keys, reporting any problem in the message area.
+    pub fn run_bound_command(&mut self, line: &str, context: ChainContext) {
+        if let Err(e) = self.run_command_line(line, context) {
+            self.message = e;
+            self.command_failed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_args(args: &[ArgSpec]) -> Command {
+        Command {
+            name: String::from("test.command"),
+            description: String::new(),
+            args: args.to_vec(),
+            func: Rc::new(|_, _| {}),
+        }
+    }
+
+    #[test]
+    fn parse_args() {
+        let command = with_args(&[ArgSpec::required("id", ArgKind::Number), ArgSpec::optional("name", ArgKind::Text)]);
+        assert_eq!(command.parse_args(" 2 "), Ok(vec![String::from("2")]));
+        // the last text argument takes the rest of the line.
+        assert_eq!(command.parse_args("2  a b  c "), Ok(vec![String::from("2"), String::from("a b  c")]));
+        assert_eq!(command.parse_args(""), Err(String::from("test.command needs an argument: id")));
+        assert_eq!(command.parse_args("two"), Err(String::from("id should be a number: two")));
+        assert_eq!(command.parse_args("-1"), Err(String::from("id should be a number: -1")));
+    }
+
+    #[test]
+    fn trailing_and_extra_args() {
+        let command = with_args(&[ArgSpec::optional("id", ArgKind::Number)]);
+        assert_eq!(command.parse_args(""), Ok(Vec::new()));
+        assert_eq!(command.parse_args("1 2"), Err(String::from("Trailing characters: 2")));
+        assert_eq!(
+            command.check_args(&[String::from("1"), String::from("2")]),
+            Err(String::from("Too many arguments for test.command"))
+        );
+
+        let command = with_args(&[ArgSpec::required("word", ArgKind::Text), ArgSpec::required("id", ArgKind::Number)]);
+        assert_eq!(command.parse_args("a 1"), Ok(vec![String::from("a"), String::from("1")]));
+        assert_eq!(command.parse_args("a b 1"), Err(String::from("Trailing characters: 1")));
+    }
+
+    #[test]
+    fn from_the_command_line() {
+        let mut state = EditorState::with_text("one");
+        state.type_keys(":call buffer.switch two<CR>");
+        assert_eq!(state.message, "id should be a number: two");
+        state.type_keys(":call buffer.switch<CR>");
+        assert_eq!(state.message, "buffer.switch needs an argument: id");
+        state.type_keys(":call buffer.undo 1<CR>");
+        assert_eq!(state.message, "Trailing characters: 1");
+        state.type_keys(":call no.such.command<CR>");
+        assert_eq!(state.message, "Unknown command: no.such.command");
+
+        assert_eq!(
+            state.run_command("buffer.switch", ChainContext { args: vec![String::from("x")], ..ChainContext::default() }),
+            Err(String::from("id should be a number: x"))
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::editor::fonts::Font;
+use crate::editor::keymaps::parse_keys;
+use crate::editor::{EditMode, EditorState};
+
+/// Key bindings for one mode, from key notation to command name.
+type Bindings = BTreeMap<String, Spanned<String>>;
+
+/// The user's config file, ie. `~/.config/text_ed/config.toml`:
+///
+/// ```toml
+/// leader = "<Space>"
+/// colorscheme = "light"
+///
+/// [normal]
+/// "<leader>w" = "buffer.save"
+/// "<leader>b" = "editor.ex ls"
+///
+/// [unbind]
+/// normal = ["<C-s>"]
+///
+/// [scopes]
+/// "entity.name.function" = "function"
+/// "keyword.operator" = "keyword"
+/// ```
+///
+/// Bindings are made on top of the defaults, after the keys under `unbind`
+/// have been removed. Likewise `scopes` adds to the fonts grammar scopes
+/// are shown in, see `ScopeFonts`.
+///
+/// TextMate and Sublime Text grammars are loaded from the `grammars`
+/// directory next to the config file, or from the directory `grammars`
+/// names. A relative `grammars` is taken from the config file's directory.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    leader: Option<Spanned<String>>,
+    colorscheme: Option<Spanned<String>>,
+    grammars: Option<Spanned<String>>,
+    scopes: BTreeMap<String, Spanned<String>>,
+    normal: Bindings,
+    insert: Bindings,
+    visual: Bindings,
+    operator: Bindings,
+    unbind: Unbindings,
+}
+
+/// The default bindings to remove, as key notation for each mode.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Unbindings {
+    normal: Vec<Spanned<String>>,
+    insert: Vec<Spanned<String>>,
+    visual: Vec<Spanned<String>>,
+    operator: Vec<Spanned<String>>,
+}
+
+/// A problem with the config file, and the line it is on if it is known.
+#[derive(Clone, Debug)]
+pub struct ConfigError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(formatter, "line {}: {}", line, self.message),
+            None => write!(formatter, "{}", self.message),
+        }
+    }
+}
+
+/// The line (1 indexed) of the byte at `offset` in `text`.
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// Where the config file is looked for.
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("text_ed").join("config.toml"))
+}
+
+/// Where grammars are loaded from when the config file doesn't say.
+pub fn default_grammar_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("text_ed").join("grammars"))
+}
+
+/// Where themes are looked for, see `find_theme`.
+pub fn theme_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("text_ed").join("themes"))
+}
+
+/// A path from the config file, with a leading `~` standing for the home
+/// directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// A path from the config file, relative paths being relative to
+/// `config_dir` rather than to wherever the editor was started.
+fn resolve_path(path: &str, config_dir: Option<&Path>) -> PathBuf {
+    let path = expand_home(path);
+    match config_dir {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
+
+impl EditorState {
+    /// Loads the config file from the user's config directory, if there is
+    /// one, and then the grammars. Any problems with them end up in the
+    /// message area.
+    pub fn load_user_config(&mut self) {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return,
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let errors = self.load_config(&text);
+                self.report_config_errors(&path, errors);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => self.message = format!("\"{}\" {}", path.display(), e),
+        }
+
+        let dir = match self.grammar_dir.clone() {
+            Some(dir) => dir,
+            // a missing default directory just means there are no grammars.
+            None => match default_grammar_dir() {
+                Some(dir) if dir.is_dir() => dir,
+                _ => return,
+            },
+        };
+        let mut errors = self.load_grammars(&dir);
+        if !errors.is_empty() {
+            // after any problems with the config file itself.
+            if !self.message.is_empty() {
+                errors.insert(0, std::mem::take(&mut self.message));
+            }
+            self.message = errors.join(" | ");
+        }
+    }
+
+    fn report_config_errors(&mut self, path: &Path, errors: Vec<ConfigError>) {
+        if errors.is_empty() {
+            return;
+        }
+        let errors: Vec<String> = errors.iter().map(ConfigError::to_string).collect();
+        self.message = format!("\"{}\" {}", path.display(), errors.join(" | "));
+    }
+
+    /// Applies the bindings in the text of a config file. Bad entries are
+    /// skipped and returned, the rest are still applied.
+    pub fn load_config(&mut self, text: &str) -> Vec<ConfigError> {
+        let config: ConfigFile = match toml::from_str(text) {
+            Ok(config) => config,
+            Err(e) => {
+                return vec![ConfigError {
+                    line: e.span().map(|span| line_at(text, span.start)),
+                    message: e.message().to_string(),
+                }]
+            }
+        };
+
+        let mut errors = Vec::new();
+        let mut check = |result: Result<(), String>, offset: usize| {
+            if let Err(message) = result {
+                errors.push(ConfigError {
+                    line: Some(line_at(text, offset)),
+                    message,
+                });
+            }
+        };
+
+        if let Some(leader) = &config.leader {
+            check(self.set_leader(leader.get_ref()), leader.span().start);
+        }
+
+        let modes = [
+            (EditMode::Normal, &config.unbind.normal, &config.normal),
+            (EditMode::Insert, &config.unbind.insert, &config.insert),
+            (EditMode::Visual, &config.unbind.visual, &config.visual),
+            (EditMode::OperatorPending, &config.unbind.operator, &config.operator),
+        ];
+        for (mode, unbindings, bindings) in modes {
+            for notation in unbindings {
+                check(self.unbind(mode, notation.get_ref()), notation.span().start);
+            }
+            for (notation, command) in bindings {
+                check(self.bind(mode, notation, command.get_ref()), command.span().start);
+            }
+        }
+        if let Some(name) = &config.colorscheme {
+            check(self.set_colorscheme(name.get_ref()), name.span().start);
+        }
+        if let Some(dir) = &config.grammars {
+            let config_path = config_path();
+            self.grammar_dir = Some(resolve_path(dir.get_ref(), config_path.as_deref().and_then(Path::parent)));
+        }
+        for (scope, font) in &config.scopes {
+            check(self.set_scope_font(scope, font.get_ref()), font.span().start);
+        }
+        errors.sort_by_key(|error| error.line);
+        errors
+    }
+
+    /// Shows `scope` in the font called `font` in grammars loaded after this.
+    pub fn set_scope_font(&mut self, scope: &str, font: &str) -> Result<(), String> {
+        let font = Font::from_name(font).ok_or_else(|| format!("Unknown font: {}", font))?;
+        self.scope_fonts.set(scope, font);
+        Ok(())
+    }
+
+    /// Changes the key `<leader>` stands for in later bindings.
+    pub fn set_leader(&mut self, notation: &str) -> Result<(), String> {
+        match parse_keys(notation, self.leader)?.as_slice() {
+            [key] => {
+                self.leader = *key;
+                Ok(())
+            }
+            _ => Err(format!("The leader should be a single key: {}", notation)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(errors: &[ConfigError]) -> Vec<String> {
+        errors.iter().map(ConfigError::to_string).collect()
+    }
+
+    #[test]
+    fn bindings_and_leader() {
+        let mut state = EditorState::with_text("one two");
+        let errors = state.load_config(
+            r#"
+leader = "<Space>"
+
+[normal]
+"<leader>u" = "buffer.undo"
+
+[unbind]
+normal = ["u"]
+"#,
+        );
+        assert!(errors.is_empty(), "{:?}", messages(&errors));
+
+        state.type_keys("dwu");
+        assert_eq!(state.text(), "two");
+        state.type_keys("<Space>u");
+        assert_eq!(state.text(), "one two");
+    }
+
+    #[test]
+    fn bad_entries_are_skipped() {
+        let mut state = EditorState::with_text("one two");
+        let errors = state.load_config(
+            r#"leader = "ab"
+
+[normal]
+"<Nope>" = "buffer.undo"
+"Q" = "no.such_command"
+"<C-z>" = "buffer.undo"
+
+[scopes]
+"comment" = "shouty"
+"#,
+        );
+        assert_eq!(
+            messages(&errors),
+            [
+                "line 1: The leader should be a single key: ab",
+                "line 4: Unknown key: <Nope>",
+                "line 5: Unknown command: no.such_command",
+                "line 9: Unknown font: shouty",
+            ]
+        );
+
+        // the good binding is still made.
+        state.type_keys("dw<C-z>");
+        assert_eq!(state.text(), "one two");
+    }
+
+    #[test]
+    fn syntax_errors_have_a_line() {
+        let mut state = EditorState::new();
+        let errors = state.load_config("[normal]\n\"a\" = \"buffer.undo\"\nnot toml\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, Some(3));
+
+        let errors = state.load_config("[normals]\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, Some(1));
+    }
+
+    #[test]
+    fn home_is_expanded() {
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(expand_home("~/grammars"), home.join("grammars"));
+        }
+        assert_eq!(expand_home("/etc/grammars"), PathBuf::from("/etc/grammars"));
+    }
+
+    #[test]
+    fn relative_paths_are_from_the_config_dir() {
+        let dir = Path::new("/home/user/.config/text_ed");
+        assert_eq!(resolve_path("grammars", Some(dir)), dir.join("grammars"));
+        assert_eq!(resolve_path("../grammars", Some(dir)), dir.join("../grammars"));
+        assert_eq!(resolve_path("/etc/grammars", Some(dir)), PathBuf::from("/etc/grammars"));
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(resolve_path("~/grammars", Some(dir)), home.join("grammars"));
+        }
+        assert_eq!(resolve_path("grammars", None), PathBuf::from("grammars"));
+
+        let mut state = EditorState::with_text("");
+        assert!(state.load_config("grammars = \"grammars\"").is_empty());
+        if let Some(path) = config_path() {
+            assert_eq!(state.grammar_dir, path.parent().map(|dir| dir.join("grammars")));
+        }
+    }
+}
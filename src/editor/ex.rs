@@ -0,0 +1,656 @@
+use crate::editor::buffer::Buffer;
+use crate::editor::keymaps::ChainContext;
+use crate::editor::macros;
+use crate::editor::motions::Motion;
+use crate::editor::operators::Operator;
+use crate::editor::open_notice;
+use crate::editor::registers::Registers;
+use crate::editor::substitute;
+use crate::editor::theme;
+use crate::editor::EditorState;
+
+/// One end of a line range, as it was typed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Address {
+    /// `.`, or nothing before an offset.
+    Current,
+    /// `$`
+    Last,
+    Line(usize),
+    /// `'x`, ie. `'<` and `'>` for the last visual selection.
+    Mark(char),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineSpec {
+    pub address: Address,
+    pub offset: isize,
+}
+
+/// The lines an ex command acts on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineRange {
+    /// `%`
+    Whole,
+    /// `a`, `a,b`, or `a;b` where `b` is counted from `a` rather than the cursor.
+    Lines {
+        start: LineSpec,
+        end: Option<LineSpec>,
+        relative: bool,
+    },
+}
+
+/// A parsed command line: `[range]name[!] [args]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExCommand {
+    pub range: Option<LineRange>,
+    pub name: String,
+    pub bang: bool,
+    pub args: String,
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.position..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        self.input[start..self.position].parse().ok()
+    }
+
+    fn line_spec(&mut self) -> Result<Option<LineSpec>, String> {
+        let address = match self.peek() {
+            Some('.') => {
+                self.bump();
+                Some(Address::Current)
+            }
+            Some('$') => {
+                self.bump();
+                Some(Address::Last)
+            }
+            Some('\'') => {
+                self.bump();
+                match self.bump() {
+                    Some(mark) => Some(Address::Mark(mark)),
+                    None => return Err(String::from("Missing mark name")),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => self.number().map(Address::Line),
+            _ => None,
+        };
+
+        let mut offset = 0;
+        let mut has_offset = false;
+        while let Some(sign @ ('+' | '-')) = self.peek() {
+            self.bump();
+            let n = self.number().unwrap_or(1) as isize;
+            offset += if sign == '+' { n } else { -n };
+            has_offset = true;
+        }
+
+        Ok(match (address, has_offset) {
+            (Some(address), _) => Some(LineSpec { address, offset }),
+            (None, true) => Some(LineSpec { address: Address::Current, offset }),
+            (None, false) => None,
+        })
+    }
+
+    fn range(&mut self) -> Result<Option<LineRange>, String> {
+        if self.peek() == Some('%') {
+            self.bump();
+            return Ok(Some(LineRange::Whole));
+        }
+
+        let start = self.line_spec()?;
+        let relative = match self.peek() {
+            Some(',') => false,
+            Some(';') => true,
+            _ => return Ok(start.map(|start| LineRange::Lines { start, end: None, relative: false })),
+        };
+        self.bump();
+
+        let start = start.unwrap_or(LineSpec { address: Address::Current, offset: 0 });
+        let end = self.line_spec()?.unwrap_or(start);
+        Ok(Some(LineRange::Lines { start, end: Some(end), relative }))
+    }
+}
+
+/// Parses a command line, without the leading `:`.
+pub fn parse(line: &str) -> Result<ExCommand, String> {
+    let mut parser = Parser { input: line, position: 0 };
+    parser.skip_whitespace();
+    let range = parser.range()?;
+    parser.skip_whitespace();
+
+    let start = parser.position;
+    match parser.peek() {
+        Some(c) if c.is_alphabetic() => {
+            while parser.peek().is_some_and(char::is_alphabetic) {
+                parser.bump();
+            }
+        }
+        // single character commands, like `:&` or `:>`
+        Some(c) if !c.is_whitespace() => {
+            parser.bump();
+        }
+        _ => {}
+    }
+    let name = line[start..parser.position].to_string();
+
+    let bang = parser.peek() == Some('!');
+    if bang {
+        parser.bump();
+    }
+    let args = line[parser.position..].trim().to_string();
+
+    Ok(ExCommand { range, name, bang, args })
+}
+
+impl LineSpec {
+    fn resolve(&self, buffer: &Buffer, current: usize) -> Result<usize, String> {
+        let line = match self.address {
+            Address::Current => current,
+            Address::Last => buffer.line_count(),
+            Address::Line(line) => line,
+            Address::Mark(mark) => match buffer.get_mark(mark) {
+                Some((line, _)) => line,
+                None => return Err(format!("Mark not set: {}", mark)),
+            },
+        };
+
+        let line = line as isize + self.offset;
+        if line < 0 || line as usize > buffer.line_count() {
+            return Err(String::from("Invalid range"));
+        }
+        // line 0 means "before the first line", which is the first line for us.
+        Ok((line as usize).max(1))
+    }
+}
+
+impl LineRange {
+    /// Turns the range into first and last line numbers (1 indexed) in `buffer`.
+    pub fn resolve(&self, buffer: &Buffer) -> Result<(usize, usize), String> {
+        let current = buffer.get_cursor_pos().0;
+        match self {
+            Self::Whole => Ok((1, buffer.line_count())),
+            Self::Lines { start, end, relative } => {
+                let first = start.resolve(buffer, current)?;
+                let last = match end {
+                    Some(end) => end.resolve(buffer, if *relative { first } else { current })?,
+                    None => first,
+                };
+                Ok((first.min(last), first.max(last)))
+            }
+        }
+    }
+}
+
+pub type ExHandler = fn(&mut EditorState, &ExCommand, Option<(usize, usize)>) -> Result<(), String>;
+
+/// A command that can be run from the command line.
+pub struct ExCommandSpec {
+    pub name: &'static str,
+    /// how many characters of the name have to be typed.
+    pub abbreviation: usize,
+    pub description: &'static str,
+    pub handler: ExHandler,
+}
+
+/// The commands known to the command line, looked up by (abbreviated) name.
+pub struct ExRegistry {
+    commands: Vec<ExCommandSpec>,
+}
+
+impl ExRegistry {
+    pub fn new() -> Self {
+        Self { commands: Vec::new() }
+    }
+
+    pub fn register(&mut self, name: &'static str, abbreviation: usize, description: &'static str, handler: ExHandler) {
+        self.commands.push(ExCommandSpec {
+            name,
+            abbreviation,
+            description,
+            handler,
+        });
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&ExCommandSpec> {
+        self.commands
+            .iter()
+            .find(|spec| name.len() >= spec.abbreviation && spec.name.starts_with(name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ExCommandSpec> {
+        self.commands.iter()
+    }
+}
+
+impl Default for ExRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register("write", 1, "write the buffer to its file, or a copy of it to the file given", ex_write);
+        registry.register("wq", 2, "write the buffer and quit", ex_write_quit);
+        registry.register("xit", 1, "write the buffer if it changed, and quit", ex_xit);
+        registry.register("quit", 1, "quit the editor", ex_quit);
+        registry.register("qall", 2, "quit the editor", ex_quit);
+        registry.register("edit", 1, "open a file, or reload the buffer with !", ex_edit);
+        registry.register("enew", 3, "open an empty buffer", ex_enew);
+        registry.register("set", 2, "change or show options", ex_set);
+        registry.register("delete", 1, "delete lines, into the register given", ex_delete);
+        registry.register("yank", 1, "yank lines, into the register given", ex_yank);
+        registry.register("buffers", 7, "list the open buffers", ex_buffers);
+        registry.register("ls", 2, "list the open buffers", ex_buffers);
+        registry.register("buffer", 1, "switch to a buffer by number", ex_buffer);
+        registry.register("bnext", 2, "switch to the next buffer", ex_bnext);
+        registry.register("bprevious", 2, "switch to the previous buffer", ex_bprevious);
+        registry.register("bdelete", 2, "close a buffer", ex_bdelete);
+        registry.register("undo", 1, "undo the last change", ex_undo);
+        registry.register("redo", 3, "redo the last undone change", ex_redo);
+        registry.register("substitute", 1, "replace matches of a pattern", substitute::ex_substitute);
+        registry.register("&", 1, "repeat the last substitution", substitute::ex_substitute);
+        registry.register("syntax", 2, "highlight the buffer as a language, or `off`, or show which it is", ex_syntax);
+        registry.register("colorscheme", 4, "switch to a theme, or show which is in use", theme::ex_colorscheme);
+        registry.register("nohlsearch", 3, "hide the search highlighting until the next search", ex_nohlsearch);
+        registry.register("let", 3, "set a register, ie. `:let @a = 0dw`", macros::ex_let);
+        registry.register("registers", 3, "show the contents of the registers", macros::ex_registers);
+        registry.register("display", 2, "show the contents of the registers", macros::ex_registers);
+        registry.register("call", 3, "run a named command, ie. `:call buffer.switch 2`", ex_call);
+        registry.register("commands", 3, "list the named commands, or those starting with the text given", ex_commands);
+        registry.register("help", 1, "list the ex commands, or those starting with the text given", ex_help);
+        registry
+    }
+}
+
+impl EditorState {
+    /// Parses and runs a command line. Errors end up in the message area.
+    pub fn execute_ex(&mut self, line: &str) {
+        let line = line.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+        if line.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.try_execute_ex(line) {
+            self.message = e;
+            self.command_failed = true;
+        }
+    }
+
+    fn try_execute_ex(&mut self, line: &str) -> Result<(), String> {
+        let command = parse(line)?;
+        let range = match &command.range {
+            Some(range) => Some(range.resolve(self.active_buffer())?),
+            None => None,
+        };
+
+        // a range on its own jumps to its last line.
+        if command.name.is_empty() {
+            if let Some((_, last)) = range {
+                self.goto_line(last);
+            }
+            return Ok(());
+        }
+
+        let handler = match self.ex_commands.lookup(&command.name) {
+            Some(spec) => spec.handler,
+            None => return Err(format!("Not an editor command: {}", line)),
+        };
+        handler(self, &command, range)
+    }
+
+    pub fn goto_line(&mut self, line: usize) {
+        let buffer = self.active_buffer_mut();
+        buffer.set_cursor_pos((line, 0));
+        if let Some(target) = Motion::FirstNonBlank.target(buffer, buffer.get_cursor_pos(), None) {
+            buffer.set_cursor_pos(target);
+        }
+    }
+
+    fn ensure_saved(&self, bang: bool) -> Result<(), String> {
+        if !bang && self.buffers.iter().any(|b| b.is_dirty()) {
+            Err(String::from("No write since last change (add ! to override)"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes the buffer for `:w`. Given a file name, it writes a copy of
+    /// the buffer (or of the lines in `range`) there, unless the buffer has
+    /// no file yet, in which case that becomes its file.
+    fn write_buffer(&mut self, command: &ExCommand, range: Option<(usize, usize)>) -> Result<(), String> {
+        let buffer = self.active_buffer_mut();
+        let result = match (command.args.as_str(), range) {
+            ("", None) => buffer.save(),
+            ("", Some(_)) => return Err(String::from("Give a file name to write part of the buffer")),
+            (path, None) if buffer.get_path().is_none() => buffer.save_as(path),
+            (path, range) => buffer.write_copy(path, range),
+        };
+        result.map_err(|e| e.to_string())?;
+
+        let written = match command.args.as_str() {
+            "" => self.active_buffer().get_path().map(|path| path.display().to_string()),
+            path => Some(path.to_string()),
+        };
+        if let Some(path) = written {
+            self.message = format!("\"{}\" written", path);
+        }
+        Ok(())
+    }
+
+    /// Runs a linewise operator over a range, defaulting to the cursor line.
+    fn operate_on_range(&mut self, operator: Operator, range: Option<(usize, usize)>, register: Option<char>) {
+        let current = self.active_buffer().get_cursor_pos().0;
+        let (first, last) = range.unwrap_or((current, current));
+        self.active_buffer_mut().set_cursor_pos((first, 0));
+        let range = self.line_range(first, last);
+        self.apply_operator(operator, range, true, register);
+    }
+}
+
+fn ex_write(s: &mut EditorState, command: &ExCommand, range: Option<(usize, usize)>) -> Result<(), String> {
+    s.write_buffer(command, range)
+}
+
+fn ex_write_quit(s: &mut EditorState, command: &ExCommand, range: Option<(usize, usize)>) -> Result<(), String> {
+    s.write_buffer(command, range)?;
+    s.ensure_saved(command.bang)?;
+    s.should_quit = true;
+    Ok(())
+}
+
+fn ex_xit(s: &mut EditorState, command: &ExCommand, range: Option<(usize, usize)>) -> Result<(), String> {
+    if s.active_buffer().is_dirty() {
+        s.write_buffer(command, range)?;
+    }
+    s.ensure_saved(command.bang)?;
+    s.should_quit = true;
+    Ok(())
+}
+
+fn ex_quit(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.ensure_saved(command.bang)?;
+    s.should_quit = true;
+    Ok(())
+}
+
+fn ex_edit(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    if !command.args.is_empty() {
+        s.open_file(&command.args);
+        return Ok(());
+    }
+
+    // `:e` on its own reloads the file, `:e!` throws away changes to do so.
+    let buffer = s.active_buffer();
+    if buffer.is_dirty() && !command.bang {
+        return Err(String::from("No write since last change (add ! to override)"));
+    }
+    let path = match buffer.get_path() {
+        Some(path) => path.to_path_buf(),
+        None => return Err(String::from("No file name")),
+    };
+
+    let mut reloaded = Buffer::open(buffer.get_id(), &path).map_err(|e| e.to_string())?;
+    reloaded.set_highlighter(buffer.get_highlighter().cloned());
+    let cursor = buffer.get_cursor_pos();
+    if let Some(notice) = open_notice(&path, &reloaded) {
+        s.message = notice;
+    }
+    let buffer = s.active_buffer_mut();
+    *buffer = reloaded;
+    buffer.set_cursor_pos(cursor);
+    Ok(())
+}
+
+fn ex_enew(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.new_buffer();
+    Ok(())
+}
+
+fn ex_set(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    let mut shown = Vec::new();
+    for argument in command.args.split_whitespace() {
+        if let Some(value) = s.options.set(argument)? {
+            shown.push(value);
+        }
+    }
+    if !shown.is_empty() {
+        s.message = shown.join(" ");
+    }
+    Ok(())
+}
+
+/// The register named in the arguments of `:delete x` or `:yank x`, if any.
+fn register_arg(command: &ExCommand) -> Result<Option<char>, String> {
+    let mut chars = command.args.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Ok(None),
+        (Some(name), None) if Registers::is_writable(name) => Ok(Some(name)),
+        _ => Err(format!("Invalid register name: {}", command.args)),
+    }
+}
+
+fn ex_delete(s: &mut EditorState, command: &ExCommand, range: Option<(usize, usize)>) -> Result<(), String> {
+    let register = register_arg(command)?;
+    s.operate_on_range(Operator::Delete, range, register);
+    Ok(())
+}
+
+fn ex_yank(s: &mut EditorState, command: &ExCommand, range: Option<(usize, usize)>) -> Result<(), String> {
+    let register = register_arg(command)?;
+    s.operate_on_range(Operator::Yank, range, register);
+    Ok(())
+}
+
+fn ex_buffers(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.list_buffers();
+    Ok(())
+}
+
+fn ex_buffer(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    if command.args.is_empty() {
+        return Ok(());
+    }
+    let id = command
+        .args
+        .parse()
+        .map_err(|_| format!("Invalid buffer number: {}", command.args))?;
+    s.switch_buffer(id);
+    Ok(())
+}
+
+fn ex_bnext(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.buffers.cycle(1);
+    Ok(())
+}
+
+fn ex_bprevious(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.buffers.cycle(-1);
+    Ok(())
+}
+
+fn ex_bdelete(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    let id = if command.args.is_empty() {
+        s.active_buffer().get_id()
+    } else {
+        command
+            .args
+            .parse()
+            .map_err(|_| format!("Invalid buffer number: {}", command.args))?
+    };
+    s.close_buffer(id, command.bang);
+    Ok(())
+}
+
+fn ex_undo(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.active_buffer_mut().undo();
+    Ok(())
+}
+
+fn ex_redo(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.active_buffer_mut().redo();
+    Ok(())
+}
+
+fn ex_syntax(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    if !command.args.is_empty() {
+        return s.set_syntax(&command.args);
+    }
+    let current = s.active_buffer().get_highlighter().map(|highlighter| highlighter.grammar().name.clone());
+    let names: Vec<&str> = s.grammars.iter().map(|grammar| grammar.name.as_str()).collect();
+    s.message = format!("syntax={} ({})", current.as_deref().unwrap_or("off"), names.join(", "));
+    Ok(())
+}
+
+fn ex_nohlsearch(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.search_highlight_hidden = true;
+    Ok(())
+}
+
+fn ex_call(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    s.run_command_line(&command.args, ChainContext::default())
+}
+
+fn ex_commands(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    let entries: Vec<String> = s
+        .commands
+        .iter()
+        .filter(|spec| spec.name.starts_with(&command.args))
+        .map(|spec| format!("{}: {}", spec.usage(), spec.description))
+        .collect();
+    if entries.is_empty() {
+        return Err(format!("No commands starting with {}", command.args));
+    }
+    s.message = entries.join(" | ");
+    Ok(())
+}
+
+fn ex_help(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    let entries: Vec<String> = s
+        .ex_commands
+        .iter()
+        .filter(|spec| spec.name.starts_with(&command.args))
+        .map(|spec| format!("{}: {}", spec.name, spec.description))
+        .collect();
+    if entries.is_empty() {
+        return Err(format!("No ex commands starting with {}", command.args));
+    }
+    s.message = entries.join(" | ");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(start: LineSpec, end: Option<LineSpec>, relative: bool) -> Option<LineRange> {
+        Some(LineRange::Lines { start, end, relative })
+    }
+
+    fn spec(address: Address, offset: isize) -> LineSpec {
+        LineSpec { address, offset }
+    }
+
+    /// The lines `range` covers in a buffer of ten lines, with the cursor on line 4.
+    fn resolve(range: &str) -> Result<(usize, usize), String> {
+        let text: Vec<String> = (1..=10).map(|n| n.to_string()).collect();
+        let mut buffer = Buffer::new(0);
+        buffer.replace_range(0..0, &text.join("\n"));
+        buffer.set_cursor_pos((4, 0));
+        let command = parse(range)?;
+        command.range.expect("no range").resolve(&buffer)
+    }
+
+    #[test]
+    fn parse_commands() {
+        let command = parse("w! some file ").unwrap();
+        assert_eq!(command.range, None);
+        assert_eq!(command.name, "w");
+        assert!(command.bang);
+        assert_eq!(command.args, "some file");
+
+        let command = parse("&&").unwrap();
+        assert_eq!((command.name.as_str(), command.args.as_str()), ("&", "&"));
+        assert_eq!(parse("s/a/b/").unwrap().args, "/a/b/");
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(parse("%d").unwrap().range, Some(LineRange::Whole));
+        assert_eq!(parse("3d").unwrap().range, lines(spec(Address::Line(3), 0), None, false));
+        assert_eq!(
+            parse(".,$d").unwrap().range,
+            lines(spec(Address::Current, 0), Some(spec(Address::Last, 0)), false)
+        );
+        assert_eq!(
+            parse("'<,'>s/a/b/").unwrap().range,
+            lines(spec(Address::Mark('<'), 0), Some(spec(Address::Mark('>'), 0)), false)
+        );
+        assert_eq!(
+            parse("2;+3y").unwrap().range,
+            lines(spec(Address::Line(2), 0), Some(spec(Address::Current, 3)), true)
+        );
+        assert_eq!(parse("-2").unwrap().range, lines(spec(Address::Current, -2), None, false));
+        assert_eq!(parse("$-1--").unwrap().range, lines(spec(Address::Last, -3), None, false));
+        assert!(parse("'").is_err());
+    }
+
+    #[test]
+    fn resolve_ranges() {
+        assert_eq!(resolve("%"), Ok((1, 10)));
+        assert_eq!(resolve("."), Ok((4, 4)));
+        assert_eq!(resolve("$"), Ok((10, 10)));
+        assert_eq!(resolve("2,5"), Ok((2, 5)));
+        assert_eq!(resolve("5,2"), Ok((2, 5)));
+        assert_eq!(resolve(",+2"), Ok((4, 6)));
+        assert_eq!(resolve("2,+2"), Ok((2, 6)));
+        assert_eq!(resolve("2;+2"), Ok((2, 4)));
+        assert_eq!(resolve("0"), Ok((1, 1)));
+        assert_eq!(resolve("11"), Err(String::from("Invalid range")));
+        assert_eq!(resolve("1-1"), Ok((1, 1)));
+        assert_eq!(resolve("1-2"), Err(String::from("Invalid range")));
+        assert_eq!(resolve("'a"), Err(String::from("Mark not set: a")));
+    }
+
+    #[test]
+    fn write_a_copy() {
+        let dir = std::env::temp_dir().join(format!("text_ed_ex_write_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        let copy = dir.join("copy.txt");
+
+        let mut state = EditorState::with_text("one\ntwo\nthree\n");
+        state.type_keys(&format!(":w {}<CR>", path.display()));
+        assert_eq!(state.active_buffer().get_path(), Some(path.as_path()));
+
+        state.type_keys(&format!(":2,3w {}<CR>", copy.display()));
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "two\nthree\n");
+        state.type_keys(&format!(":w {}<CR>", copy.display()));
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "one\ntwo\nthree\n");
+        assert_eq!(state.active_buffer().get_path(), Some(path.as_path()));
+
+        state.type_keys(":2w<CR>");
+        assert_eq!(state.message, "Give a file name to write part of the buffer");
+    }
+}
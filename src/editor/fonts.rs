@@ -1,10 +1,71 @@
-struct Color(f32, f32, f32);
+/// An opaque color, each part from 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color(pub f32, pub f32, pub f32);
 impl From<(f32, f32, f32)> for Color {
     fn from(o: (f32, f32, f32)) -> Self {
         Self(o.0, o.1, o.2)
     }
 }
 
+impl From<Color> for [f32; 4] {
+    fn from(color: Color) -> Self {
+        [color.0, color.1, color.2, 1.0]
+    }
+}
+
+/// The colors which can be given by name, as in CSS.
+const NAMED_COLORS: [(&str, u32); 20] = [
+    ("black", 0x000000),
+    ("white", 0xffffff),
+    ("gray", 0x808080),
+    ("grey", 0x808080),
+    ("silver", 0xc0c0c0),
+    ("red", 0xff0000),
+    ("maroon", 0x800000),
+    ("orange", 0xffa500),
+    ("yellow", 0xffff00),
+    ("olive", 0x808000),
+    ("lime", 0x00ff00),
+    ("green", 0x008000),
+    ("teal", 0x008080),
+    ("cyan", 0x00ffff),
+    ("aqua", 0x00ffff),
+    ("blue", 0x0000ff),
+    ("navy", 0x000080),
+    ("purple", 0x800080),
+    ("magenta", 0xff00ff),
+    ("fuchsia", 0xff00ff),
+];
+
+impl Color {
+    fn from_rgb(rgb: u32) -> Self {
+        let part = |shift: u32| ((rgb >> shift) & 0xff) as f32 / 255.0;
+        Self(part(16), part(8), part(0))
+    }
+
+    /// Reads a color written as `#rrggbb`, `#rgb` or a name like `navy`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let error = || format!("Unknown color: {}", text);
+        let hex = match text.strip_prefix('#') {
+            Some(hex) => hex,
+            None => {
+                let name = text.to_ascii_lowercase();
+                let (_, rgb) = NAMED_COLORS.iter().find(|(named, _)| *named == name).ok_or_else(error)?;
+                return Ok(Self::from_rgb(*rgb));
+            }
+        };
+        // each digit of the short form is doubled, ie. `#f80` is `#ff8800`.
+        let hex: String = match hex.len() {
+            3 => hex.chars().flat_map(|c| [c, c]).collect(),
+            _ => hex.to_string(),
+        };
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(error());
+        }
+        Ok(Self::from_rgb(u32::from_str_radix(&hex, 16).map_err(|_| error())?))
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct FontDefinition {
     pub bold: bool,
@@ -14,6 +75,8 @@ pub struct FontDefinition {
 
     pub typeface: TypeFace,
     pub color: [f32; 4],
+    /// drawn behind the text, if set.
+    pub background: Option<[f32; 4]>,
     pub size: f32,
 }
 
@@ -27,6 +90,7 @@ impl Default for FontDefinition {
 
             typeface: TypeFace::Monospace,
             color: [1.0, 1.0, 1.0, 1.0],
+            background: None,
             size: 30.0,
         }
     }
@@ -39,7 +103,19 @@ pub enum TypeFace {
     SansSerif,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+impl TypeFace {
+    /// The typeface with a name as it is written in a theme, ie. `serif`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "monospace" => Some(Self::Monospace),
+            "serif" => Some(Self::Serif),
+            "sans_serif" => Some(Self::SansSerif),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Font {
     Normal,
     Bold,
@@ -52,5 +128,72 @@ pub enum Font {
     Variable,
     Function,
     Structure,
+    /// matches of the current search.
+    Search,
     // ... etc.
 }
+
+impl Font {
+    /// Every font, in the order they are declared.
+    pub const ALL: [Font; 12] = [
+        Self::Normal,
+        Self::Bold,
+        Self::Italic,
+        Self::BoldItalic,
+        Self::Comment,
+        Self::Number,
+        Self::String,
+        Self::Keyword,
+        Self::Variable,
+        Self::Function,
+        Self::Structure,
+        Self::Search,
+    ];
+
+    /// The font with a name as it is written in a grammar, ie. `comment`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let font = match name {
+            "normal" => Self::Normal,
+            "bold" => Self::Bold,
+            "italic" => Self::Italic,
+            "bold_italic" => Self::BoldItalic,
+            "comment" => Self::Comment,
+            "number" => Self::Number,
+            "string" => Self::String,
+            "keyword" => Self::Keyword,
+            "variable" => Self::Variable,
+            "function" => Self::Function,
+            "structure" => Self::Structure,
+            "search" => Self::Search,
+            _ => return None,
+        };
+        Some(font)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_colors() {
+        assert_eq!(Color::parse("#ff8000"), Ok(Color(1.0, 128.0 / 255.0, 0.0)));
+        assert_eq!(Color::parse("#FF8000"), Color::parse("#ff8000"));
+        assert_eq!(Color::parse("#f80"), Color::parse("#ff8800"));
+        assert_eq!(Color::parse("#000"), Ok(Color(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn parse_named_colors() {
+        assert_eq!(Color::parse("navy"), Color::parse("#000080"));
+        assert_eq!(Color::parse("White"), Ok(Color(1.0, 1.0, 1.0)));
+        assert_eq!(Color::parse("grey"), Color::parse("gray"));
+    }
+
+    #[test]
+    fn parse_bad_colors() {
+        for text in ["", "#", "#ff80", "#ff80001", "#gg0000", "#+f0000", "ff8000", "nope"] {
+            assert_eq!(Color::parse(text), Err(format!("Unknown color: {}", text)));
+        }
+    }
+}
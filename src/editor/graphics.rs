@@ -1,6 +1,67 @@
+use std::ops::Range;
+
 use crate::editor::*;
-use crate::editor::buffer::BufferEntry;
+use crate::editor::fonts::TypeFace;
 use wgpu_glyph::{OwnedText, ab_glyph::PxScale};
+use wgpu_glyph::ab_glyph::{Font as _, ScaleFont as _};
+use wgpu_glyph::{FontId, GlyphBrush, GlyphCruncher, Section, SectionGlyph, Text};
+
+/// How far faked italics lean, as a fraction of their height.
+const OBLIQUE_SLANT: f32 = 0.2;
+
+/// Things drawn for a run of text which the glyph brush can't draw itself.
+/// There is one of these for each `OwnedText` in a section.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Decoration {
+    pub background: Option<[f32; 4]>,
+    /// the color of the text, which the lines and faked styles are drawn in.
+    pub color: [f32; 4],
+    pub underline: bool,
+    pub strikethrough: bool,
+    /// the face the run is drawn in has no bold of its own, so it is faked.
+    pub synthetic_bold: bool,
+    /// likewise for italic. These runs are left out of the section, see
+    /// `synthetic_runs`.
+    pub synthetic_italic: bool,
+}
+
+/// The faces the glyph brush was built with, in order, so the typeface and
+/// style of a font can be turned into the `FontId` of a face.
+#[derive(Default)]
+pub struct FontFaces {
+    faces: Vec<(TypeFace, bool, bool)>,
+}
+
+impl FontFaces {
+    /// Notes which face the next font given to the glyph brush is.
+    pub fn add(&mut self, typeface: TypeFace, bold: bool, italic: bool) {
+        self.faces.push((typeface, bold, italic));
+    }
+
+    fn find(&self, typeface: TypeFace, bold: bool, italic: bool) -> Option<FontId> {
+        self.faces.iter().position(|face| *face == (typeface, bold, italic)).map(FontId)
+    }
+
+    /// The face to draw `font` in, and whether it is still missing the bold
+    /// and italic the font asks for. A face of the font's own typeface is
+    /// picked over a monospace one, then the face with the most of its style.
+    pub fn select(&self, font: &FontDefinition) -> (FontId, bool, bool) {
+        let styles = [
+            (font.bold, font.italic),
+            (font.bold, false),
+            (false, font.italic),
+            (false, false),
+        ];
+        for typeface in [font.typeface, TypeFace::Monospace] {
+            for (bold, italic) in styles {
+                if let Some(id) = self.find(typeface, bold, italic) {
+                    return (id, font.bold && !bold, font.italic && !italic);
+                }
+            }
+        }
+        (FontId(0), font.bold, font.italic)
+    }
+}
 
 // always assume the text buffer starts at 0,0 in the pixel buffer.
 //pub fn draw_cursor<'a>(editor_state: EditorState, window_buffer: &'a [u8], window_size: PhysicalSize<u32>) -> &'a[u8]{
@@ -17,46 +78,394 @@ impl EditorState{
 
         // other plugins / systems will have a chance to alter the text before it is displayed.
 
-        let mut display_buffer = self.active_buffer.clone();
-        //draw the cursor
-        let cursor_pos = display_buffer.get_cursor_pos();
-        let current_line = display_buffer.get_current_line_mut();
-        if current_line.len() == cursor_pos.1 {
-            current_line.push('\u{2588}'.into());
-        } else {
-            current_line[cursor_pos.1] = '\u{2588}'.into();
+        // the snapshot only copies the root of the rope, the text itself is shared.
+        let mut display_buffer = self.active_buffer().snapshot();
+        // the cursor is drawn behind the character under it, so past the
+        // end of a line there has to be a character to draw it behind.
+        let (line, column) = display_buffer.get_cursor_pos();
+        if column >= display_buffer.line_len(line - 1) {
+            display_buffer.replace_at_cursor(' ');
         }
 
         display_buffer
     }
 
-    pub fn get_section_text<'a>(&self, display_buffer: &'a Buffer) -> Vec<OwnedText> {
+    /// Splits lines `lines` (0 indexed) of the display buffer into runs of
+    /// text, one per change of font or decoration. Only those lines are
+    /// looked at, so a frame costs the same however long the buffer is. The
+    /// decorations line up with the returned text.
+    pub fn get_section_text(
+        &self,
+        display_buffer: &Buffer,
+        lines: Range<usize>,
+        faces: &FontFaces,
+    ) -> (Vec<OwnedText>, Vec<Decoration>) {
         // now I have a vector with all the strings, and their styles.
-        let editor_theme = self.theme.clone();
+        let editor_theme = &self.theme;
 
         let mut v: Vec<OwnedText> = Vec::new();
+        let mut decorations: Vec<Decoration> = Vec::new();
 
         let font = editor_theme.get(&Font::Normal).unwrap();
-        let new_text = OwnedText::new(String::new())
-            .with_color(font.color)
-            .with_scale(font.size)
-            .with_font_id(wgpu_glyph::FontId(0));
-        v.push(new_text);
-
-        for item in display_buffer.get_lines().into_iter().flatten() {
-            if let BufferEntry::Font(font) = item {
-                let font = editor_theme.get(font).unwrap();
-                let new_text = OwnedText::new(String::new())
-                    .with_color(font.color)
-                    .with_scale(font.size)
-                    .with_font_id(wgpu_glyph::FontId(0));
-                v.push(new_text);
+        let search_font = editor_theme.get(&Font::Search).unwrap();
+        let search = self.search_highlight();
+        let mut in_match = false;
+        let mut in_cursor = false;
+        let cursor = display_buffer.get_cursor_pos();
+        // the font of the syntax highlight the last run was in, if any.
+        let mut syntax_font: Option<Font> = None;
+        let (run, mut decoration) = new_run(font, None, faces);
+        v.push(run);
+        decorations.push(decoration);
+
+        for (line_index, line) in lines.clone().zip(display_buffer.get_lines(lines)) {
+            let selected = display_buffer.selected_columns(line_index + 1);
+            // matched against the real buffer, the display one may have a space added for the cursor.
+            let matches = match &search {
+                Some(regex) if line_index < self.active_buffer().line_count() => {
+                    self.active_buffer().line_matches(regex, line_index)
+                }
+                _ => Vec::new(),
+            };
+            // both are in order, so each is stepped through alongside the columns.
+            let mut matches = matches.iter().peekable();
+            let mut highlights = self.active_buffer().line_highlights(line_index).iter().peekable();
+
+            for (column, c) in line.chars().enumerate() {
+                while matches.next_if(|columns| columns.end <= column).is_some() {}
+                while highlights.next_if(|highlight| highlight.range.end <= column).is_some() {}
+                let is_selected = selected.as_ref().is_some_and(|columns| columns.contains(&column));
+                let is_match = matches.peek().is_some_and(|columns| columns.contains(&column));
+                let is_cursor = (line_index + 1, column) == cursor;
+                let highlight = highlights
+                    .peek()
+                    .filter(|highlight| highlight.range.contains(&column))
+                    .map(|highlight| highlight.font);
+                let background = if is_cursor {
+                    Some(self.ui_colors.cursor)
+                } else if is_selected {
+                    Some(self.ui_colors.selection)
+                } else if is_match {
+                    search_font.background
+                } else {
+                    None
+                };
+                if background != decoration.background
+                    || is_match != in_match
+                    || is_cursor != in_cursor
+                    || highlight != syntax_font
+                {
+                    in_match = is_match;
+                    in_cursor = is_cursor;
+                    syntax_font = highlight;
+                    let mut run_font = *match highlight.and_then(|highlight| editor_theme.get(&highlight)) {
+                        _ if is_match => search_font,
+                        Some(highlighted) => highlighted,
+                        None => font,
+                    };
+                    // the character under the cursor shows through it.
+                    if is_cursor {
+                        run_font.color = self.ui_colors.background;
+                    }
+                    let (run, run_decoration) = new_run(&run_font, background, faces);
+                    decoration = run_decoration;
+                    v.push(run);
+                    decorations.push(decoration);
+                }
+
+                v.last_mut().unwrap().text.push(c);
             }
-            else if let BufferEntry::Text(c) = item {
-                v.last_mut().unwrap().text.push(*c);
+        }
+
+        (v, decorations)
+    }
+}
+
+/// Starts a run of text in `font`, and the decoration drawn with it.
+fn new_run(font: &FontDefinition, background: Option<[f32; 4]>, faces: &FontFaces) -> (OwnedText, Decoration) {
+    let (font_id, synthetic_bold, synthetic_italic) = faces.select(font);
+    let decoration = Decoration {
+        background,
+        color: font.color,
+        underline: font.underline,
+        strikethrough: font.strikethrough,
+        synthetic_bold,
+        synthetic_italic,
+    };
+    // still laid out, so everything after it stays in place, but drawn
+    // sheared on its own.
+    let color = if synthetic_italic { [0.0; 4] } else { font.color };
+    let run = OwnedText::new(String::new())
+        .with_color(color)
+        .with_scale(font.size)
+        .with_font_id(font_id);
+    (run, decoration)
+}
+
+/// Draws the decorations of a laid out section into the pixel buffer, which
+/// is `frame_width` pixels wide and in the BGRA format the window uses:
+/// backgrounds behind the glyphs, and lines under and through them.
+pub fn draw_decorations(
+    glyph_brush: &mut GlyphBrush<()>,
+    section: &Section,
+    decorations: &[Decoration],
+    frame: &mut [u8],
+    frame_width: u32,
+) {
+    let glyphs: Vec<SectionGlyph> = glyph_brush.glyphs(section).cloned().collect();
+    let fonts = glyph_brush.fonts();
+
+    for SectionGlyph { section_index, glyph, font_id, .. } in glyphs {
+        let decoration = match decorations.get(section_index) {
+            Some(decoration) => decoration,
+            None => continue,
+        };
+
+        let font = fonts[font_id.0].as_scaled(glyph.scale);
+        let left = glyph.position.x;
+        let right = left + font.h_advance(glyph.id);
+        let baseline = glyph.position.y;
+        if let Some(background) = decoration.background {
+            let (top, bottom) = (baseline - font.ascent(), baseline - font.descent());
+            fill_rect(frame, frame_width, (left, top, right, bottom), background);
+        }
+
+        // ab_glyph doesn't give the lines' metrics, so they are placed by
+        // the ascent and descent.
+        let thickness = (glyph.scale.y / 16.0).max(1.0);
+        if decoration.underline {
+            let top = baseline - font.descent() * 0.4;
+            fill_rect(frame, frame_width, (left, top, right, top + thickness), decoration.color);
+        }
+        if decoration.strikethrough {
+            let top = baseline - font.ascent() * 0.3 - thickness / 2.0;
+            fill_rect(frame, frame_width, (left, top, right, top + thickness), decoration.color);
+        }
+    }
+}
+
+/// A piece of a run on one line whose face is missing its bold or italic,
+/// laid out on its own so it can be drawn again with the style faked.
+pub struct SyntheticRun {
+    pub text: OwnedText,
+    /// where the top left of the piece is.
+    pub position: (f32, f32),
+    pub baseline: f32,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl SyntheticRun {
+    /// Queues what faking the style takes: the piece itself if it is
+    /// italic, as it was left out of the section, and a copy a little to
+    /// the right if it is bold.
+    pub fn queue(&self, glyph_brush: &mut GlyphBrush<()>) {
+        let text = vec![Text::from(&self.text)];
+        if self.italic {
+            glyph_brush.queue(Section {
+                screen_position: self.position,
+                text: text.clone(),
+                ..Section::default()
+            });
+        }
+        if self.bold {
+            let offset = (self.text.scale.x / 30.0).max(1.0);
+            glyph_brush.queue(Section {
+                screen_position: (self.position.0 + offset, self.position.1),
+                text,
+                ..Section::default()
+            });
+        }
+    }
+}
+
+/// Splits the runs of a laid out section which need a style faked into
+/// pieces, one for each line a run is on. The pieces are in the order of
+/// the lines.
+pub fn synthetic_runs(glyph_brush: &mut GlyphBrush<()>, section: &Section, decorations: &[Decoration]) -> Vec<SyntheticRun> {
+    let glyphs: Vec<SectionGlyph> = glyph_brush.glyphs(section).cloned().collect();
+    let fonts = glyph_brush.fonts();
+    let mut runs: Vec<(usize, SyntheticRun)> = Vec::new();
+
+    for SectionGlyph { section_index, byte_index, glyph, font_id } in glyphs {
+        let decoration = match decorations.get(section_index) {
+            Some(decoration) if decoration.synthetic_bold || decoration.synthetic_italic => decoration,
+            _ => continue,
+        };
+        let c = match section.text[section_index].text[byte_index..].chars().next() {
+            Some(c) => c,
+            None => continue,
+        };
+
+        match runs.last_mut() {
+            Some((index, run)) if *index == section_index && run.baseline == glyph.position.y => run.text.text.push(c),
+            _ => {
+                let ascent = fonts[font_id.0].as_scaled(glyph.scale).ascent();
+                let text = OwnedText::new(c.to_string())
+                    .with_color(decoration.color)
+                    .with_scale(glyph.scale)
+                    .with_font_id(font_id);
+                let run = SyntheticRun {
+                    text,
+                    position: (glyph.position.x, glyph.position.y - ascent),
+                    baseline: glyph.position.y,
+                    bold: decoration.synthetic_bold,
+                    italic: decoration.synthetic_italic,
+                };
+                runs.push((section_index, run));
             }
         }
+    }
+    runs.into_iter().map(|(_, run)| run).collect()
+}
+
+/// The transform to draw a line of faked italics with: the window's
+/// projection, sheared so glyphs lean right, pivoting on the line's
+/// baseline so it stays in place.
+pub fn oblique_transform(width: u32, height: u32, baseline: f32) -> [f32; 16] {
+    let mut transform = wgpu_glyph::orthographic_projection(width, height);
+    // x' = x - slant * (y - baseline), before projecting.
+    transform[4] = -OBLIQUE_SLANT * transform[0];
+    transform[12] += OBLIQUE_SLANT * baseline * transform[0];
+    transform
+}
+
+/// How far apart lines of text in the face `font_id` at `scale` are.
+pub fn line_height(glyph_brush: &GlyphBrush<()>, font_id: FontId, scale: f32) -> f32 {
+    let font = glyph_brush.fonts()[font_id.0].as_scaled(scale);
+    font.height() + font.line_gap()
+}
+
+/// Fills a rectangle of the pixel buffer with `color`, clipped to the frame.
+pub fn fill_rect(frame: &mut [u8], frame_width: u32, (left, top, right, bottom): (f32, f32, f32, f32), color: [f32; 4]) {
+    let frame_width = frame_width as usize;
+    let frame_height = frame.len() / 4 / frame_width.max(1);
+    let to_byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let pixel = [to_byte(color[2]), to_byte(color[1]), to_byte(color[0]), to_byte(color[3])];
+
+    let (left, right) = (left.max(0.0) as usize, (right.max(0.0) as usize).min(frame_width));
+    let (top, bottom) = (top.max(0.0) as usize, (bottom.max(0.0) as usize).min(frame_height));
+    for y in top..bottom {
+        for x in left..right {
+            let i = (y * frame_width + x) * 4;
+            frame[i..i + 4].copy_from_slice(&pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of each run the display buffer is split into, with its
+    /// background.
+    fn runs(s: &EditorState) -> Vec<(String, Option<[f32; 4]>)> {
+        visible_runs(s, 0..s.active_buffer().line_count())
+    }
+
+    fn visible_runs(s: &EditorState, lines: Range<usize>) -> Vec<(String, Option<[f32; 4]>)> {
+        let display_buffer = s.get_display_buffer();
+        let (text, decorations) = s.get_section_text(&display_buffer, lines, &FontFaces::default());
+        text.into_iter()
+            .zip(decorations)
+            .filter(|(run, _)| !run.text.is_empty())
+            .map(|(run, decoration)| (run.text, decoration.background))
+            .collect()
+    }
+
+    #[test]
+    fn cursor_is_drawn_behind_its_character() {
+        let s = EditorState::with_text("abc\n");
+        let cursor = Some(s.ui_colors.cursor);
+        assert_eq!(runs(&s), [("a".to_string(), cursor), ("bc\n".to_string(), None)]);
+    }
+
+    #[test]
+    fn cursor_is_drawn_past_the_end_of_a_line() {
+        let mut s = EditorState::with_text("ab\n\ncd");
+        let cursor = Some(s.ui_colors.cursor);
+        s.type_keys("j");
+        assert_eq!(runs(&s), [("ab\n".to_string(), None), (" ".to_string(), cursor), ("\ncd".to_string(), None)]);
+
+        // as after `a` at the end of a line, in insert mode.
+        s.active_buffer_mut().set_cursor_pos((3, 2));
+        assert_eq!(runs(&s), [("ab\n\ncd".to_string(), None), (" ".to_string(), cursor)]);
+        // the buffer itself is left alone.
+        assert_eq!(s.text(), "ab\n\ncd");
+    }
+
+    #[test]
+    fn only_visible_lines_are_laid_out() {
+        let text: Vec<String> = (1..=100).map(|n| n.to_string()).collect();
+        let mut s = EditorState::with_text(&text.join("\n"));
+        let cursor = Some(s.ui_colors.cursor);
+        s.type_keys("50G");
+        let lines = s.active_buffer_mut().scroll_to_cursor(3);
+        assert_eq!(lines, 47..50);
+        assert_eq!(
+            visible_runs(&s, lines),
+            [("48\n49\n".to_string(), None), ("5".to_string(), cursor), ("0\n".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn search_matches_and_highlights_line_up() {
+        let mut s = EditorState::with_text("ab ab\nab");
+        s.type_keys("/b<CR>");
+        let search = s.theme[&Font::Search].background;
+        let cursor = Some(s.ui_colors.cursor);
+        assert_eq!(
+            runs(&s),
+            [
+                ("a".to_string(), None),
+                ("b".to_string(), cursor),
+                (" a".to_string(), None),
+                ("b".to_string(), search),
+                ("\na".to_string(), None),
+                ("b".to_string(), search),
+            ]
+        );
+    }
+
+    fn font(typeface: TypeFace, bold: bool, italic: bool) -> FontDefinition {
+        FontDefinition { typeface, bold, italic, ..FontDefinition::default() }
+    }
+
+    /// The faces `main` loads, with Helvetica missing its italics.
+    fn faces() -> FontFaces {
+        let mut faces = FontFaces::default();
+        faces.add(TypeFace::Monospace, false, false);
+        faces.add(TypeFace::SansSerif, false, false);
+        faces.add(TypeFace::Monospace, true, false);
+        faces.add(TypeFace::Monospace, false, true);
+        faces.add(TypeFace::Monospace, true, true);
+        faces.add(TypeFace::SansSerif, true, false);
+        faces
+    }
+
+    #[test]
+    fn select_a_face_with_the_style() {
+        let faces = faces();
+        assert_eq!(faces.select(&font(TypeFace::Monospace, false, false)), (FontId(0), false, false));
+        assert_eq!(faces.select(&font(TypeFace::Monospace, true, false)), (FontId(2), false, false));
+        assert_eq!(faces.select(&font(TypeFace::Monospace, false, true)), (FontId(3), false, false));
+        assert_eq!(faces.select(&font(TypeFace::Monospace, true, true)), (FontId(4), false, false));
+        assert_eq!(faces.select(&font(TypeFace::SansSerif, false, false)), (FontId(1), false, false));
+        assert_eq!(faces.select(&font(TypeFace::SansSerif, true, false)), (FontId(5), false, false));
+    }
+
+    #[test]
+    fn select_fakes_missing_styles() {
+        let faces = faces();
+        // the typeface is kept over the style.
+        assert_eq!(faces.select(&font(TypeFace::SansSerif, false, true)), (FontId(1), false, true));
+        assert_eq!(faces.select(&font(TypeFace::SansSerif, true, true)), (FontId(5), false, true));
+        // a typeface without faces is drawn in monospace.
+        assert_eq!(faces.select(&font(TypeFace::Serif, true, true)), (FontId(4), false, false));
 
-        v
+        let mut regular = FontFaces::default();
+        regular.add(TypeFace::Monospace, false, false);
+        assert_eq!(regular.select(&font(TypeFace::Monospace, true, true)), (FontId(0), true, true));
+        assert_eq!(FontFaces::default().select(&font(TypeFace::Serif, true, false)), (FontId(0), true, false));
     }
 }
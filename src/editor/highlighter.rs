@@ -1,28 +1,363 @@
+use std::iter;
 use std::ops::Range;
-use crate::editor::fonts;
-use crate::editor::buffer::Buffer;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-struct Highlight {
-    range: Range<usize>,
-    font: fonts::Font,
+use regex::Regex;
+use ropey::Rope;
+
+use crate::editor::buffer::EditEvent;
+use crate::editor::fonts::Font;
+
+pub mod grammar;
+pub mod scopes;
+mod sublime;
+mod textmate;
+pub use grammar::Grammar;
+pub use scopes::ScopeFonts;
+
+/// The font of part of a line, by column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Highlight {
+    pub range: Range<usize>,
+    pub font: Font,
 }
 
 type Line = Vec<Highlight>;
 
-enum Method {
+/// How many lines are highlighted per update, so a large file is
+/// highlighted a piece at a time between frames.
+pub const CHUNK_LINES: usize = 1000;
+
+/// How long an update can take, for grammars slow enough that `CHUNK_LINES`
+/// lines would hold up a frame.
+pub const CHUNK_TIME: Duration = Duration::from_millis(8);
+
+/// How a rule of a grammar finds the text it highlights.
+#[derive(Clone, Debug)]
+pub enum Method {
+    /// whole words out of a list, compiled into one regex.
+    Keywords(Regex),
+    /// a regex, matched within a line. If it has a capture group, only the
+    /// text of the first group is highlighted.
+    Pattern(Regex),
+    /// text from a match of `start` up to a match of `end`, which can be on
+    /// a later line, ie. a block comment. Matches of `skip` inside it, ie.
+    /// escaped quotes, don't end it.
+    Region {
+        start: Regex,
+        end: Regex,
+        skip: Option<Regex>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+    pub method: Method,
+    pub font: Font,
+}
+
+/// The region rule still open at the end of a line, by index, if any.
+type State = Option<usize>;
+
+/// Finds where a region ends in `line`, looking from `from`. Returns the
+/// end of the match of `end`, or None if the region carries on past the line.
+fn region_end(line: &str, from: usize, end: &Regex, skip: Option<&Regex>) -> Option<usize> {
+    let mut position = from;
+    loop {
+        let found = end.find_at(line, position)?;
+        match skip.and_then(|skip| skip.find_at(line, position)) {
+            Some(skipped) if skipped.start() < found.start() && skipped.end() > position => position = skipped.end(),
+            _ => return Some(found.end()),
+        }
+    }
+}
+
+/// Highlights one line of text, which has no newline, starting in `state`.
+/// Returns the highlighted byte ranges and the state at the end of the line.
+///
+/// At each point the rule whose match starts first wins, and the earlier
+/// rule wins a tie.
+fn highlight_text(rules: &[Rule], line: &str, state: State) -> (Vec<(Range<usize>, Font)>, State) {
+    let mut highlights = Vec::new();
+    let mut position = 0;
 
+    if let Some(index) = state {
+        if let Method::Region { end, skip, .. } = &rules[index].method {
+            match region_end(line, 0, end, skip.as_ref()) {
+                Some(end) => {
+                    highlights.push((0..end, rules[index].font));
+                    position = end;
+                }
+                None => {
+                    highlights.push((0..line.len(), rules[index].font));
+                    return (highlights, state);
+                }
+            }
+        }
+    }
+
+    while position < line.len() {
+        // (rule index, start of the match, range to highlight)
+        let mut best: Option<(usize, usize, Range<usize>)> = None;
+        for (index, rule) in rules.iter().enumerate() {
+            let found = match &rule.method {
+                Method::Keywords(regex) => regex.find_at(line, position).map(|m| (m.start(), m.range())),
+                Method::Pattern(regex) => regex.captures_at(line, position).map(|captures| {
+                    let whole = captures.get(0).unwrap();
+                    let shown = captures.get(1).unwrap_or(whole);
+                    (whole.start(), shown.range())
+                }),
+                Method::Region { start, .. } => start.find_at(line, position).map(|m| (m.start(), m.range())),
+            };
+            if let Some((start, range)) = found {
+                if best.as_ref().is_none_or(|(_, best_start, _)| start < *best_start) {
+                    best = Some((index, start, range));
+                }
+            }
+        }
+
+        let (index, _, range) = match best {
+            Some(best) => best,
+            None => break,
+        };
+        let rule = &rules[index];
+        let end = match &rule.method {
+            Method::Region { end, skip, .. } => match region_end(line, range.end, end, skip.as_ref()) {
+                Some(end) => end,
+                None => {
+                    highlights.push((range.start..line.len(), rule.font));
+                    return (highlights, Some(index));
+                }
+            },
+            _ => range.end,
+        };
+        if end > range.start {
+            highlights.push((range.start..end, rule.font));
+        }
+        // empty matches still have to move on.
+        position = if end > position {
+            end
+        } else {
+            position + line[position..].chars().next().map_or(1, char::len_utf8)
+        };
+    }
+    (highlights, None)
 }
 
-struct Highlighter {
+/// Converts byte ranges in `line` to column ranges.
+fn to_columns(line: &str, highlights: Vec<(Range<usize>, Font)>) -> Line {
+    let column = |byte: usize| line[..byte].chars().count();
+    highlights
+        .into_iter()
+        .map(|(range, font)| Highlight {
+            range: column(range.start)..column(range.end),
+            font,
+        })
+        .collect()
+}
+
+/// Highlights the text of a buffer with a grammar, keeping the highlights
+/// of each line.
+///
+/// Edits mark the lines they touch as stale. Updating highlights the stale
+/// lines, and a line whose end state changed makes the next line stale too,
+/// so highlighting carries on down from an edit until the states line up
+/// with what they were before it.
+#[derive(Clone)]
+pub struct Highlighter {
+    grammar: Rc<Grammar>,
     cache: Vec<Line>,
+    /// the state at the end of each line in the cache.
+    states: Vec<State>,
+    /// lines whose highlights are out of date.
+    stale: Vec<bool>,
+    /// how many lines are stale, so updating stops at the last of them.
+    stale_count: usize,
+    /// no line before this one is stale.
+    first_stale: usize,
 }
 
 impl Highlighter {
-    /// Runs the highlighter on the entire buffer
-    pub fn highlight_buffer(&mut self, buffer: &Buffer) {
+    pub fn new(grammar: Rc<Grammar>) -> Self {
+        Self {
+            grammar,
+            cache: Vec::new(),
+            states: Vec::new(),
+            stale: Vec::new(),
+            stale_count: 0,
+            first_stale: 0,
+        }
+    }
+
+    pub fn grammar(&self) -> &Grammar {
+        &self.grammar
+    }
+
+    /// Marks every line of a text of `line_count` lines as stale.
+    pub fn invalidate_all(&mut self, line_count: usize) {
+        self.cache = vec![Vec::new(); line_count];
+        self.states = vec![None; line_count];
+        self.stale = vec![true; line_count];
+        self.stale_count = line_count;
+        self.first_stale = 0;
+    }
+
+    /// Takes note of an edit to the text: the lines it replaced are swapped
+    /// for stale ones.
+    pub fn edited(&mut self, event: &EditEvent) {
+        let old_end = event.old_end.min(self.cache.len());
+        let start = event.start.min(old_end);
+        let count = event.new_end - event.start;
+        // the line after the edit was highlighted from the end state of the
+        // last line replaced, which is what the new lines have to match.
+        let end_state = old_end.checked_sub(1).and_then(|line| self.states[line]);
+
+        self.cache.splice(start..old_end, iter::repeat_with(Vec::new).take(count));
+        self.states.splice(start..old_end, iter::repeat_n(None, count));
+        let replaced = self.stale.splice(start..old_end, iter::repeat_n(true, count)).filter(|stale| *stale).count();
+        self.stale_count = self.stale_count - replaced + count;
+        if count > 0 {
+            self.states[start + count - 1] = end_state;
+        }
+        self.first_stale = self.first_stale.min(start);
     }
 
-    pub fn highlight_line(&mut self, buffer: &Buffer, line: usize) {
+    pub fn is_up_to_date(&self) -> bool {
+        self.stale_count == 0
+    }
+
+    /// Highlights at most `max_lines` stale lines of `text`, stopping early
+    /// once `max_time` has passed. Returns true once no line is stale.
+    pub fn update(&mut self, text: &Rope, max_lines: usize, max_time: Duration) -> bool {
+        if self.stale.len() != text.len_lines() {
+            self.invalidate_all(text.len_lines());
+        }
+
+        // a time too far off to add up has no deadline.
+        let deadline = Instant::now().checked_add(max_time);
+        let mut highlighted = 0;
+        let mut line = self.first_stale;
+        while self.stale_count > 0 {
+            if self.stale[line] {
+                let out_of_time = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                if highlighted == max_lines || (highlighted > 0 && out_of_time) {
+                    break;
+                }
+                if self.highlight_line(text, line) && line + 1 < self.stale.len() && !self.stale[line + 1] {
+                    self.stale[line + 1] = true;
+                    self.stale_count += 1;
+                }
+                self.stale[line] = false;
+                self.stale_count -= 1;
+                highlighted += 1;
+            }
+            line += 1;
+        }
+        self.first_stale = line;
+        self.is_up_to_date()
+    }
+
+    /// Highlights a line (0 indexed) of the cache, starting from the state
+    /// the line before it ended in. Returns true if the state at its end changed,
+    /// in which case the lines after it need highlighting again.
+    pub fn highlight_line(&mut self, text: &Rope, line: usize) -> bool {
+        let state = match line {
+            0 => None,
+            line => self.states.get(line - 1).copied().flatten(),
+        };
+        let content: String = text.line(line).chars().filter(|c| *c != '\n' && *c != '\r').collect();
+        let (highlights, end_state) = highlight_text(&self.grammar.rules, &content, state);
+
+        self.cache[line] = to_columns(&content, highlights);
+        let changed = self.states[line] != end_state;
+        self.states[line] = end_state;
+        changed
+    }
+
+    /// The highlights of a line (0 indexed), in order of their columns.
+    pub fn line(&self, line: usize) -> &[Highlight] {
+        self.cache.get(line).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::buffer::Buffer;
+    use crate::editor::highlighter::grammar::builtin_grammars;
+
+    fn rust() -> Rc<Grammar> {
+        Rc::new(builtin_grammars().into_iter().find(|grammar| grammar.name == "rust").unwrap())
+    }
+
+    fn highlighted(text: &str, grammar: &Rc<Grammar>) -> Buffer {
+        let mut buffer = Buffer::new(0);
+        buffer.replace_range(0..0, text);
+        buffer.set_highlighter(Some(Highlighter::new(grammar.clone())));
+        assert!(buffer.update_highlighting(usize::MAX, Duration::MAX));
+        buffer
+    }
+
+    fn first_stale(buffer: &Buffer) -> usize {
+        buffer.get_highlighter().unwrap().first_stale
+    }
+
+    const SOURCE: &str = "fn main() {\n    let x = 42; // hi\n    /* a\n  b */ let s = \"q\\\"x\";\n}\n";
+
+    #[test]
+    fn edits_match_highlighting_from_scratch() {
+        let grammar = rust();
+        let mut buffer = highlighted(&SOURCE.repeat(20), &grammar);
+        let mut seed: u64 = 7;
+        let mut random = |n: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n.max(1)
+        };
+        let pieces = ["/*", "*/", "\n", "\"", "x", "\n/* z\n", "let "];
+
+        for _ in 0..200 {
+            let len = buffer.get_text().len_chars();
+            let at = random(len);
+            match random(3) {
+                0 | 1 => buffer.replace_range(at..at, pieces[random(pieces.len())]),
+                _ => buffer.delete_range(at..(at + random(30)).min(len)),
+            }
+            while !buffer.update_highlighting(CHUNK_LINES, CHUNK_TIME) {}
+
+            let fresh = highlighted(&buffer.get_text().to_string(), &grammar);
+            for line in 0..buffer.line_count() {
+                assert_eq!(buffer.line_highlights(line), fresh.line_highlights(line), "line {}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn update_stops_once_states_line_up() {
+        let mut buffer = highlighted(&SOURCE.repeat(100), &rust());
+        buffer.replace_range(0..0, "x");
+        assert!(buffer.is_highlighting());
+        assert!(buffer.update_highlighting(CHUNK_LINES, CHUNK_TIME));
+        assert_eq!(first_stale(&buffer), 1);
+
+        // opening a comment carries on until it is closed.
+        let at = buffer.pos_to_char((2, 4));
+        buffer.replace_range(at..at, "/*");
+        assert!(buffer.update_highlighting(CHUNK_LINES, CHUNK_TIME));
+        assert_eq!(first_stale(&buffer), 3);
+        assert_eq!(buffer.line_highlights(2), [Highlight { range: 0..8, font: Font::Comment }]);
+    }
 
+    #[test]
+    fn update_is_bounded() {
+        let mut buffer = Buffer::new(0);
+        buffer.replace_range(0..0, &SOURCE.repeat(10));
+        buffer.set_highlighter(Some(Highlighter::new(rust())));
+        assert!(!buffer.update_highlighting(20, Duration::MAX));
+        assert_eq!(first_stale(&buffer), 20);
+        // out of time, but at least one line is highlighted each update.
+        assert!(!buffer.update_highlighting(CHUNK_LINES, Duration::ZERO));
+        assert_eq!(first_stale(&buffer), 21);
+        assert!(buffer.update_highlighting(CHUNK_LINES, Duration::MAX));
     }
 }
@@ -0,0 +1,474 @@
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::editor::fonts::Font;
+use crate::editor::highlighter::scopes::ScopeFonts;
+use crate::editor::highlighter::{sublime, textmate, Method, Rule};
+
+/// The rules for highlighting a language, and the files it is used for.
+///
+/// Grammars are written in TOML. Each rule gives the font it highlights in
+/// and one of `keywords`, `match`, or `start` and `end`:
+///
+/// ```toml
+/// name = "rust"
+/// extensions = ["rs"]
+///
+/// [[rule]]
+/// font = "comment"
+/// start = '/\*'
+/// end = '\*/'
+///
+/// [[rule]]
+/// font = "string"
+/// start = '"'
+/// end = '"'
+/// skip = '\\.'
+///
+/// [[rule]]
+/// font = "keyword"
+/// keywords = ["fn", "let", "match"]
+///
+/// [[rule]]
+/// font = "function"
+/// match = '\b([a-z_][a-z0-9_]*)\s*\('
+/// ```
+#[derive(Debug)]
+pub struct Grammar {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GrammarFile {
+    name: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    font: String,
+    keywords: Option<Vec<String>>,
+    #[serde(rename = "match")]
+    pattern: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    skip: Option<String>,
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| e.to_string())
+}
+
+/// Rewrites a regex written for Oniguruma, as the regexes of TextMate and
+/// Sublime Text grammars are, for the `regex` crate. Possessive quantifiers
+/// and atomic groups become plain ones; lookaround and backreferences have
+/// no equivalent, so regexes using them still fail to compile.
+fn translate_oniguruma(pattern: &str) -> String {
+    let mut translated = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    // how many classes deep we are, ie. 2 in `[a-z&&[^aeiou]]`.
+    let mut class_depth = 0;
+    let mut after_quantifier = false;
+    while let Some(c) = chars.next() {
+        let quantifier = class_depth == 0 && matches!(c, '*' | '+' | '?' | '}');
+        match c {
+            '\\' => match chars.next() {
+                Some('h') => translated.push_str("[0-9a-fA-F]"),
+                Some('H') => translated.push_str("[^0-9a-fA-F]"),
+                Some('Z') => translated.push('$'),
+                Some('e') => translated.push_str("\\x1B"),
+                Some(escaped) => {
+                    translated.push('\\');
+                    translated.push(escaped);
+                }
+                None => translated.push('\\'),
+            },
+            '+' if after_quantifier => {}
+            '(' if class_depth == 0 && chars.peek() == Some(&'?') => {
+                chars.next();
+                if chars.peek() == Some(&'>') {
+                    chars.next();
+                    translated.push_str("(?:");
+                } else {
+                    translated.push_str("(?");
+                }
+            }
+            '[' => {
+                class_depth += 1;
+                translated.push(c);
+                if chars.peek() == Some(&'^') {
+                    translated.push(chars.next().unwrap());
+                }
+                // a `]` straight after the start of a class is literal.
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    translated.push_str("\\]");
+                }
+            }
+            ']' if class_depth > 0 => {
+                class_depth -= 1;
+                translated.push(c);
+            }
+            c => translated.push(c),
+        }
+        after_quantifier = quantifier && !(c == '+' && after_quantifier);
+    }
+    translated
+}
+
+/// Compiles `translated`, the translation of `original`, a regex from a
+/// TextMate or Sublime Text grammar. Errors name the original regex, with
+/// only the last line of the `regex` crate's message, which says what is wrong.
+fn compile_translated(original: &str, translated: &str) -> Result<Regex, String> {
+    Regex::new(translated).map_err(|e| {
+        let message = e.to_string();
+        let reason = message.lines().last().unwrap_or_default();
+        format!("{}: {}", original, reason.trim_start_matches("error: "))
+    })
+}
+
+/// Compiles a regex from a TextMate or Sublime Text grammar.
+pub(super) fn compile_oniguruma(pattern: &str) -> Result<Regex, String> {
+    compile_translated(pattern, &translate_oniguruma(pattern))
+}
+
+/// A rule from a TextMate or Sublime Text grammar highlighting the whole
+/// of each match of `pattern` in `font`, or only its first group in
+/// `group_font`. None if neither has a font.
+pub(super) fn match_rule(pattern: &str, font: Option<Font>, group_font: Option<Font>) -> Result<Option<Rule>, String> {
+    let (translated, font) = match (font, group_font) {
+        // wrapped so the whole match is the first group.
+        (Some(font), _) => (format!("({})", translate_oniguruma(pattern)), font),
+        (None, Some(font)) => (translate_oniguruma(pattern), font),
+        (None, None) => return Ok(None),
+    };
+    let method = Method::Pattern(compile_translated(pattern, &translated)?);
+    Ok(Some(Rule { method, font }))
+}
+
+/// A rule from a TextMate or Sublime Text grammar highlighting from `start`
+/// to `end` in `font`. The patterns the grammar matches inside the region
+/// are skipped over when looking for its end, so ie. an escaped quote
+/// doesn't end a string. Inner patterns which don't compile are left out.
+pub(super) fn region_rule(start: &str, end: &str, inner: &[&str], font: Font) -> Result<Rule, String> {
+    let inner: Vec<String> = inner
+        .iter()
+        .map(|pattern| translate_oniguruma(pattern))
+        .filter(|pattern| compile(pattern).is_ok())
+        .map(|pattern| format!("(?:{})", pattern))
+        .collect();
+    let method = Method::Region {
+        start: compile_oniguruma(start)?,
+        end: compile_oniguruma(end)?,
+        skip: if inner.is_empty() {
+            None
+        } else {
+            compile(&inner.join("|")).ok()
+        },
+    };
+    Ok(Rule { method, font })
+}
+
+impl RuleFile {
+    fn into_rule(self) -> Result<Rule, String> {
+        let font = Font::from_name(&self.font).ok_or_else(|| format!("Unknown font: {}", self.font))?;
+        let method = match (self.keywords, self.pattern, self.start, self.end, self.skip) {
+            (Some(keywords), None, None, None, None) => {
+                let words: Vec<String> = keywords.iter().map(|word| regex::escape(word)).collect();
+                Method::Keywords(compile(&format!(r"\b(?:{})\b", words.join("|")))?)
+            }
+            (None, Some(pattern), None, None, None) => Method::Pattern(compile(&pattern)?),
+            (None, None, Some(start), Some(end), skip) => Method::Region {
+                start: compile(&start)?,
+                end: compile(&end)?,
+                skip: skip.as_deref().map(compile).transpose()?,
+            },
+            _ => return Err(String::from("A rule needs one of `keywords`, `match`, or `start` and `end`")),
+        };
+        Ok(Rule { method, font })
+    }
+}
+
+impl Grammar {
+    /// Reads a grammar from the text of its TOML file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: GrammarFile = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        let rules = file
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| rule.into_rule().map_err(|e| format!("rule {}: {}", i + 1, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            name: file.name,
+            extensions: file.extensions,
+            rules,
+        })
+    }
+
+    /// Reads a TextMate (`.tmLanguage` or `.tmLanguage.json`) or Sublime
+    /// Text (`.sublime-syntax`) grammar, highlighting its scopes in the fonts
+    /// `scopes` gives them.
+    ///
+    /// The grammar is flattened into rules: the patterns it matches inside
+    /// other patterns, other than the contents of highlighted regions, are
+    /// matched anywhere. Patterns with regexes the `regex` crate can't
+    /// handle, ie. ones with lookbehind, are left out, and returned with the
+    /// grammar.
+    pub fn load(path: &Path, scopes: &ScopeFonts) -> Result<(Self, Vec<String>), String> {
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if file_name.ends_with(".tmLanguage") {
+            let file = plist::from_file(path).map_err(|e| e.to_string())?;
+            Ok(textmate::convert(file, scopes))
+        } else if file_name.ends_with(".tmLanguage.json") {
+            let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let file = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+            Ok(textmate::convert(file, scopes))
+        } else if file_name.ends_with(".sublime-syntax") {
+            let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let file = serde_yaml_ng::from_str(&text).map_err(|e| e.to_string())?;
+            Ok(sublime::convert(file, scopes))
+        } else {
+            Err(String::from("Not a grammar file"))
+        }
+    }
+
+    /// True if the grammar is for files like `path`, going by its extension.
+    pub fn matches_path(&self, path: &Path) -> bool {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => self.extensions.iter().any(|e| e == extension),
+            None => false,
+        }
+    }
+}
+
+/// Loads the TextMate and Sublime Text grammars in `dir`, in order of their
+/// file names. Returns the grammars and the problems with the files which
+/// couldn't be loaded, or were loaded without some of their patterns.
+pub fn load_grammars(dir: &Path, scopes: &ScopeFonts) -> (Vec<Grammar>, Vec<String>) {
+    let mut grammars = Vec::new();
+    let mut errors = Vec::new();
+    let mut paths: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+        Err(e) => return (grammars, vec![format!("\"{}\" {}", dir.display(), e)]),
+    };
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if !(name.ends_with(".tmLanguage") || name.ends_with(".tmLanguage.json") || name.ends_with(".sublime-syntax")) {
+            continue;
+        }
+        match Grammar::load(&path, scopes) {
+            Ok((grammar, left_out)) => {
+                if let Some(first) = left_out.first() {
+                    let count = left_out.len();
+                    errors.push(format!("\"{}\" left out {} pattern(s), ie. {}", path.display(), count, first));
+                }
+                grammars.push(grammar);
+            }
+            Err(e) => errors.push(format!("\"{}\" {}", path.display(), e)),
+        }
+    }
+    (grammars, errors)
+}
+
+/// The grammars which come with the editor.
+pub fn builtin_grammars() -> Vec<Grammar> {
+    let files = [
+        include_str!("grammars/rust.toml"),
+        include_str!("grammars/c.toml"),
+        include_str!("grammars/python.toml"),
+    ];
+    files
+        .iter()
+        .map(|text| Grammar::parse(text).expect("Error Loading Grammar"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::highlighter::highlight_text;
+    use std::path::PathBuf;
+
+    /// Writes a grammar file into a fresh directory, and gives its path.
+    fn grammar_file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("text_ed_grammar_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    /// The fonts `grammar` highlights `line` with, with the text of each.
+    fn fonts(grammar: &Grammar, line: &str) -> Vec<(String, Font)> {
+        let (highlights, _) = highlight_text(&grammar.rules, line, None);
+        highlights.into_iter().map(|(range, font)| (line[range].to_string(), font)).collect()
+    }
+
+    fn text(s: &str, font: Font) -> (String, Font) {
+        (s.to_string(), font)
+    }
+
+    #[test]
+    fn translate() {
+        assert_eq!(translate_oniguruma(r"\h+\H"), "[0-9a-fA-F]+[^0-9a-fA-F]");
+        assert_eq!(translate_oniguruma(r"end\Z"), "end$");
+        assert_eq!(translate_oniguruma(r"\e\[\d"), r"\x1B\[\d");
+        assert_eq!(translate_oniguruma(r"\\h"), r"\\h");
+        assert_eq!(translate_oniguruma(r"\d++x*+y?+z{2}+"), r"\d+x*y?z{2}");
+        assert_eq!(translate_oniguruma(r"a+\+"), r"a+\+");
+        assert_eq!(translate_oniguruma(r"[+]+"), r"[+]+");
+        assert_eq!(translate_oniguruma(r"(?>a|b)(?:c)(?i)d"), r"(?:a|b)(?:c)(?i)d");
+        assert_eq!(translate_oniguruma(r"[]a][^]b]"), r"[\]a][^\]b]");
+        assert_eq!(translate_oniguruma(r"[(?>]"), r"[(?>]");
+        assert_eq!(translate_oniguruma(r"[a-z&&[^aeiou]]+"), r"[a-z&&[^aeiou]]+");
+    }
+
+    #[test]
+    fn translated_regexes_compile() {
+        for pattern in [r"\b\h++\b", r"(?>\d+)\Z", r"[]\[]", r"\e\[[0-9;]*+m"] {
+            assert!(compile_oniguruma(pattern).is_ok(), "{}", pattern);
+        }
+        let error = compile_oniguruma(r"(?<=x)y").unwrap_err();
+        assert!(error.starts_with("(?<=x)y: "), "{}", error);
+        assert!(!error.contains('\n'));
+    }
+
+    #[test]
+    fn load_textmate_json() {
+        let path = grammar_file(
+            "toy.tmLanguage.json",
+            r##"{
+  "name": "Toy",
+  "scopeName": "source.toy",
+  "fileTypes": ["toy"],
+  "patterns": [
+    { "include": "#comments" },
+    { "name": "string.quoted.double.toy", "begin": "\"", "end": "\"", "patterns": [{ "include": "#escape" }] },
+    { "match": "\\b(fn)\\s+(\\w+)", "captures": { "1": { "name": "storage.type.toy" } } },
+    { "name": "constant.numeric.toy", "match": "\\b\\h+h\\b|\\b\\d++\\b" },
+    { "name": "keyword.lookbehind.toy", "match": "(?<=x)y" },
+    { "name": "string.heredoc.toy", "begin": "<<(\\w+)", "end": "^\\1$" },
+    { "begin": "\\{", "end": "\\}", "patterns": [{ "include": "$self" }] }
+  ],
+  "repository": {
+    "comments": { "patterns": [{ "name": "comment.line.toy", "match": "#.*$" }] },
+    "escape": { "name": "constant.character.escape.toy", "match": "\\\\." }
+  }
+}"##,
+        );
+        let (grammar, left_out) = Grammar::load(&path, &ScopeFonts::default()).unwrap();
+        assert_eq!(grammar.name, "Toy");
+        assert!(grammar.matches_path(Path::new("a.toy")));
+        assert_eq!(left_out.len(), 2, "{:?}", left_out);
+        assert!(left_out[0].starts_with("(?<=x)y: "));
+        assert!(left_out[1].starts_with(r"^\1$: "));
+
+        assert_eq!(
+            fonts(&grammar, r#"fn 12 "a\"b" # c"#),
+            [
+                text("fn", Font::Keyword),
+                text("12", Font::Number),
+                text(r#""a\"b""#, Font::String),
+                text("# c", Font::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_textmate_plist() {
+        let path = grammar_file(
+            "toy.tmLanguage",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+  <key>fileTypes</key><array><string>toy2</string></array>
+  <key>scopeName</key><string>source.toy2</string>
+  <key>patterns</key>
+  <array>
+    <dict><key>name</key><string>keyword.other.toy2</string><key>match</key><string>\b(let|var)\b</string></dict>
+    <dict><key>name</key><string>string.quoted.single.toy2</string><key>begin</key><string>'</string><key>end</key><string>'</string></dict>
+  </array>
+</dict>
+</plist>"#,
+        );
+        let (grammar, left_out) = Grammar::load(&path, &ScopeFonts::default()).unwrap();
+        // named after the last part of its scope, as it has no name of its own.
+        assert_eq!(grammar.name, "toy2");
+        assert!(left_out.is_empty());
+        assert_eq!(fonts(&grammar, "let x = 'y'"), [text("let", Font::Keyword), text("'y'", Font::String)]);
+    }
+
+    #[test]
+    fn load_sublime_syntax() {
+        let path = grammar_file(
+            "toy.sublime-syntax",
+            r#"%YAML 1.2
+---
+name: Toy3
+file_extensions: [toy3]
+scope: source.toy3
+variables:
+  ident: '[a-z_]\w*'
+  fnname: '{{ident}}'
+contexts:
+  prototype:
+    - match: '//.*'
+      scope: comment.line.toy3
+  main:
+    - match: '"'
+      push: string
+    - match: '\b(def)\s+({{fnname}})'
+      captures:
+        1: storage.type.toy3
+    - match: '(?<!\.)\b(while|return)\b'
+      scope: keyword.control.toy3
+    - match: '\b(if|else)\b'
+      scope: keyword.control.toy3
+  string:
+    - meta_scope: string.quoted.double.toy3
+    - match: '\\.'
+    - match: '"'
+      pop: true
+"#,
+        );
+        let (grammar, left_out) = Grammar::load(&path, &ScopeFonts::default()).unwrap();
+        assert_eq!(grammar.name, "Toy3");
+        assert_eq!(left_out.len(), 1, "{:?}", left_out);
+        assert!(left_out[0].starts_with(r"(?<!\.)\b(while|return)\b: "));
+        assert_eq!(
+            fonts(&grammar, r#"def f if "a\"b" // c"#),
+            [
+                text("def", Font::Keyword),
+                text("if", Font::Keyword),
+                text(r#""a\"b""#, Font::String),
+                text("// c", Font::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        let path = grammar_file("bad.sublime-syntax", "name: [unclosed\n");
+        assert!(Grammar::load(&path, &ScopeFonts::default()).is_err());
+
+        let dir = path.parent().unwrap();
+        fs::write(dir.join("notes.txt"), "not a grammar").unwrap();
+        let (grammars, errors) = load_grammars(dir, &ScopeFonts::default());
+        assert!(grammars.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("bad.sublime-syntax"));
+    }
+}
@@ -0,0 +1,67 @@
+use crate::editor::fonts::Font;
+
+/// Which font the scopes of TextMate and Sublime Text grammars are
+/// highlighted in, ie. `entity.name.function` in `Font::Function`.
+///
+/// A scope takes the font of the longest entry it starts with, going by
+/// whole parts: `string` covers `string.quoted.double.rust` but not
+/// `strings`. Of the scopes in a list like `meta.block string.quoted`, the
+/// last one with a font wins.
+#[derive(Clone, Debug)]
+pub struct ScopeFonts {
+    entries: Vec<(String, Font)>,
+}
+
+impl Default for ScopeFonts {
+    fn default() -> Self {
+        let entries = [
+            ("comment", Font::Comment),
+            ("string", Font::String),
+            ("constant.character", Font::String),
+            ("constant.numeric", Font::Number),
+            ("constant.language", Font::Keyword),
+            ("keyword", Font::Keyword),
+            ("keyword.operator", Font::Normal),
+            ("storage", Font::Keyword),
+            ("entity.name.function", Font::Function),
+            ("support.function", Font::Function),
+            ("entity.name", Font::Structure),
+            ("entity.other.inherited-class", Font::Structure),
+            ("support.type", Font::Structure),
+            ("support.class", Font::Structure),
+            ("variable", Font::Variable),
+            ("markup.heading", Font::Bold),
+            ("markup.bold", Font::Bold),
+            ("markup.italic", Font::Italic),
+        ];
+        Self {
+            entries: entries.iter().map(|(scope, font)| (scope.to_string(), *font)).collect(),
+        }
+    }
+}
+
+impl ScopeFonts {
+    /// Highlights `scope` and the scopes under it in `font`, replacing any
+    /// font it had.
+    pub fn set(&mut self, scope: &str, font: Font) {
+        match self.entries.iter_mut().find(|(entry, _)| entry == scope) {
+            Some(entry) => entry.1 = font,
+            None => self.entries.push((scope.to_string(), font)),
+        }
+    }
+
+    /// The font of a space separated list of scopes, if any of them has one.
+    pub fn font(&self, scopes: &str) -> Option<Font> {
+        scopes.split_whitespace().rev().find_map(|scope| self.scope_font(scope))
+    }
+
+    fn scope_font(&self, scope: &str) -> Option<Font> {
+        self.entries
+            .iter()
+            .filter(|(entry, _)| {
+                scope.starts_with(entry.as_str()) && matches!(scope.as_bytes().get(entry.len()), None | Some(b'.'))
+            })
+            .max_by_key(|(entry, _)| entry.len())
+            .map(|(_, font)| *font)
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashSet};
+
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use serde_yaml_ng::Value;
+
+use crate::editor::fonts::Font;
+use crate::editor::highlighter::grammar::{match_rule, region_rule, Grammar};
+use crate::editor::highlighter::scopes::ScopeFonts;
+use crate::editor::highlighter::Rule;
+
+/// A Sublime Text grammar, as read from a `.sublime-syntax` file. Keys the
+/// highlighter has no use for are ignored.
+#[derive(Deserialize)]
+pub struct SyntaxFile {
+    name: Option<String>,
+    scope: String,
+    #[serde(default)]
+    file_extensions: Vec<String>,
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+    contexts: BTreeMap<String, Vec<ContextItem>>,
+}
+
+#[derive(Deserialize)]
+struct ContextItem {
+    #[serde(rename = "match")]
+    pattern: Option<String>,
+    scope: Option<String>,
+    #[serde(default)]
+    captures: BTreeMap<usize, String>,
+    push: Option<ContextRef>,
+    set: Option<ContextRef>,
+    /// true, or how many contexts to pop.
+    pop: Option<Value>,
+    include: Option<String>,
+    meta_scope: Option<String>,
+    meta_content_scope: Option<String>,
+}
+
+impl ContextItem {
+    fn pops(&self) -> bool {
+        matches!(self.pop, Some(Value::Bool(true)) | Some(Value::Number(_)))
+    }
+}
+
+/// The context a match pushes or sets.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ContextRef {
+    Named(String),
+    /// several contexts, the last of which ends up on top.
+    Stack(Vec<String>),
+    Anonymous(Vec<ContextItem>),
+}
+
+/// Turns the contexts of a grammar into rules, following includes and the
+/// contexts matches push.
+struct Converter<'a> {
+    file: &'a SyntaxFile,
+    scopes: &'a ScopeFonts,
+    variables: Regex,
+    rules: Vec<Rule>,
+    /// the matches left out, and why.
+    errors: Vec<String>,
+    /// named contexts already added. Adding one again couldn't change
+    /// anything, as its first copy would always match first.
+    visited: HashSet<&'a str>,
+}
+
+impl<'a> Converter<'a> {
+    /// The items of a named context. Contexts of other grammars can't be
+    /// used, so they have none.
+    fn named(&self, name: &str) -> &'a [ContextItem] {
+        self.file.contexts.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    /// The name and items of the context `reference` leaves on top.
+    fn context(&self, reference: &'a ContextRef) -> (Option<&'a str>, &'a [ContextItem]) {
+        match reference {
+            ContextRef::Named(name) => (Some(name), self.named(name)),
+            ContextRef::Stack(names) => match names.last() {
+                Some(name) => (Some(name), self.named(name)),
+                None => (None, &[]),
+            },
+            ContextRef::Anonymous(items) => (None, items),
+        }
+    }
+
+    fn font(&self, scope: Option<&String>) -> Option<Font> {
+        scope.and_then(|scope| self.scopes.font(scope))
+    }
+
+    /// Replaces the `{{variables}}` in a regex with their values.
+    fn expand(&self, regex: &str) -> String {
+        let mut expanded = regex.to_string();
+        // variables can be made of other variables, but not endlessly.
+        for _ in 0..10 {
+            if !self.variables.is_match(&expanded) {
+                break;
+            }
+            expanded = self
+                .variables
+                .replace_all(&expanded, |captures: &Captures| {
+                    self.file.variables.get(&captures[1]).cloned().unwrap_or_default()
+                })
+                .into_owned();
+        }
+        expanded
+    }
+
+    fn add_rule(&mut self, rule: Result<Option<Rule>, String>) {
+        match rule {
+            Ok(rule) => self.rules.extend(rule),
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    fn add_context(&mut self, items: &'a [ContextItem]) {
+        for item in items {
+            self.add_item(item);
+        }
+    }
+
+    fn add_named(&mut self, name: &'a str) {
+        if self.visited.insert(name) {
+            self.add_context(self.named(name));
+        }
+    }
+
+    fn add_item(&mut self, item: &'a ContextItem) {
+        if let Some(include) = &item.include {
+            self.add_named(include);
+            return;
+        }
+        let regex = match &item.pattern {
+            Some(regex) => self.expand(regex),
+            None => return,
+        };
+
+        if let Some(reference) = item.push.as_ref().or(item.set.as_ref()) {
+            let (name, items) = self.context(reference);
+            let font = items
+                .iter()
+                .find_map(|item| self.font(item.meta_scope.as_ref()).or_else(|| self.font(item.meta_content_scope.as_ref())));
+            let end = items.iter().find(|item| item.pops() && item.pattern.is_some());
+            if let (Some(font), Some(end)) = (font, end) {
+                let end = self.expand(end.pattern.as_deref().unwrap_or_default());
+                let inner = self.inner_patterns(items, &mut HashSet::new());
+                let inner: Vec<&str> = inner.iter().map(String::as_str).collect();
+                self.add_rule(region_rule(&regex, &end, &inner, font).map(Some));
+                return;
+            }
+
+            self.add_match(item, &regex);
+            // a context with no font of its own, ie. a block, only groups
+            // the matches inside it.
+            match name {
+                Some(name) => self.add_named(name),
+                None => self.add_context(items),
+            }
+            return;
+        }
+        self.add_match(item, &regex);
+    }
+
+    fn add_match(&mut self, item: &ContextItem, regex: &str) {
+        let group_font = item.captures.get(&1).and_then(|scope| self.scopes.font(scope));
+        let font = self.font(item.scope.as_ref()).or_else(|| self.font(item.captures.get(&0)));
+        self.add_rule(match_rule(regex, font, group_font));
+    }
+
+    /// The regexes of the matches in a context which stay in it, following
+    /// includes.
+    fn inner_patterns(&self, items: &'a [ContextItem], included: &mut HashSet<&'a str>) -> Vec<String> {
+        let mut regexes = Vec::new();
+        for item in items {
+            match (&item.include, &item.pattern) {
+                (Some(include), _) if included.insert(include) => {
+                    regexes.extend(self.inner_patterns(self.named(include), included));
+                }
+                (None, Some(regex)) if !item.pops() && item.push.is_none() && item.set.is_none() => {
+                    regexes.push(self.expand(regex))
+                }
+                _ => {}
+            }
+        }
+        regexes
+    }
+}
+
+/// Converts a Sublime Text grammar into rules for the highlighter. Returns
+/// the grammar and the matches which had to be left out.
+pub fn convert(file: SyntaxFile, scopes: &ScopeFonts) -> (Grammar, Vec<String>) {
+    let mut converter = Converter {
+        file: &file,
+        scopes,
+        variables: Regex::new(r"\{\{(\w+)\}\}").unwrap(),
+        rules: Vec::new(),
+        errors: Vec::new(),
+        visited: HashSet::new(),
+    };
+    // the prototype context is matched in every other context.
+    converter.add_named("prototype");
+    converter.add_named("main");
+    let (rules, errors) = (converter.rules, converter.errors);
+
+    let name = match &file.name {
+        Some(name) => name.clone(),
+        None => file.scope.rsplit('.').next().unwrap_or_default().to_string(),
+    };
+    let grammar = Grammar {
+        name,
+        extensions: file.file_extensions,
+        rules,
+    };
+    (grammar, errors)
+}
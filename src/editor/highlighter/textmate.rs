@@ -0,0 +1,162 @@
+use std::collections::{BTreeMap, HashSet};
+use std::slice;
+
+use serde::Deserialize;
+
+use crate::editor::fonts::Font;
+use crate::editor::highlighter::grammar::{match_rule, region_rule, Grammar};
+use crate::editor::highlighter::scopes::ScopeFonts;
+use crate::editor::highlighter::Rule;
+
+/// A TextMate grammar, as read from a `.tmLanguage` plist or a
+/// `.tmLanguage.json` file. Keys the highlighter has no use for are ignored.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrammarFile {
+    name: Option<String>,
+    scope_name: String,
+    #[serde(default)]
+    file_types: Vec<String>,
+    #[serde(default)]
+    patterns: Vec<Pattern>,
+    #[serde(default)]
+    repository: BTreeMap<String, Pattern>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Pattern {
+    include: Option<String>,
+    name: Option<String>,
+    content_name: Option<String>,
+    #[serde(rename = "match")]
+    pattern: Option<String>,
+    begin: Option<String>,
+    end: Option<String>,
+    #[serde(default)]
+    captures: BTreeMap<String, Capture>,
+    #[serde(default)]
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Deserialize)]
+struct Capture {
+    name: Option<String>,
+}
+
+/// Turns the patterns of a grammar into rules, following includes.
+struct Converter<'a> {
+    file: &'a GrammarFile,
+    scopes: &'a ScopeFonts,
+    rules: Vec<Rule>,
+    /// the patterns left out, and why.
+    errors: Vec<String>,
+    /// includes already followed. A pattern included a second time could
+    /// never match before its first copy, so each is only followed once.
+    included: HashSet<&'a str>,
+}
+
+impl<'a> Converter<'a> {
+    /// The patterns an include like `#string` or `$self` refers to. Other
+    /// grammars can't be included, so they have none.
+    fn resolve(&self, include: &str) -> &'a [Pattern] {
+        match include {
+            "$self" | "$base" => &self.file.patterns,
+            name => match name.strip_prefix('#').and_then(|name| self.file.repository.get(name)) {
+                Some(pattern) => slice::from_ref(pattern),
+                None => &[],
+            },
+        }
+    }
+
+    fn font(&self, scope: Option<&String>) -> Option<Font> {
+        scope.and_then(|scope| self.scopes.font(scope))
+    }
+
+    fn add_rule(&mut self, rule: Result<Option<Rule>, String>) {
+        match rule {
+            Ok(rule) => self.rules.extend(rule),
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    fn add_patterns(&mut self, patterns: &'a [Pattern]) {
+        for pattern in patterns {
+            self.add_pattern(pattern);
+        }
+    }
+
+    fn add_pattern(&mut self, pattern: &'a Pattern) {
+        if let Some(include) = &pattern.include {
+            if self.included.insert(include) {
+                self.add_patterns(self.resolve(include));
+            }
+            return;
+        }
+
+        if let Some(regex) = &pattern.pattern {
+            let group_font = pattern.captures.get("1").and_then(|capture| self.font(capture.name.as_ref()));
+            let font = self.font(pattern.name.as_ref()).or_else(|| {
+                let whole = pattern.captures.get("0")?;
+                self.font(whole.name.as_ref())
+            });
+            self.add_rule(match_rule(regex, font, group_font));
+            return;
+        }
+
+        if let (Some(begin), Some(end)) = (&pattern.begin, &pattern.end) {
+            let font = self.font(pattern.name.as_ref()).or_else(|| self.font(pattern.content_name.as_ref()));
+            if let Some(font) = font {
+                let inner = self.inner_patterns(&pattern.patterns, &mut HashSet::new());
+                self.add_rule(region_rule(begin, end, &inner, font).map(Some));
+                return;
+            }
+        }
+        // a region with no font of its own, ie. a block, only groups the
+        // patterns inside it.
+        self.add_patterns(&pattern.patterns);
+    }
+
+    /// The regexes of the single line patterns in `patterns`, following
+    /// includes.
+    fn inner_patterns(&self, patterns: &'a [Pattern], included: &mut HashSet<&'a str>) -> Vec<&'a str> {
+        let mut regexes = Vec::new();
+        for pattern in patterns {
+            if let Some(include) = &pattern.include {
+                if included.insert(include) {
+                    regexes.extend(self.inner_patterns(self.resolve(include), included));
+                }
+            } else if let Some(regex) = &pattern.pattern {
+                regexes.push(regex.as_str());
+            } else if pattern.begin.is_none() {
+                regexes.extend(self.inner_patterns(&pattern.patterns, included));
+            }
+        }
+        regexes
+    }
+}
+
+/// Converts a TextMate grammar into rules for the highlighter. Returns the
+/// grammar and the patterns which had to be left out.
+pub fn convert(file: GrammarFile, scopes: &ScopeFonts) -> (Grammar, Vec<String>) {
+    let mut converter = Converter {
+        file: &file,
+        scopes,
+        rules: Vec::new(),
+        errors: Vec::new(),
+        included: ["$self", "$base"].iter().copied().collect(),
+    };
+    converter.add_patterns(&file.patterns);
+    let (rules, errors) = (converter.rules, converter.errors);
+
+    let name = match &file.name {
+        Some(name) => name.clone(),
+        None => file.scope_name.rsplit('.').next().unwrap_or_default().to_string(),
+    };
+    let grammar = Grammar {
+        name,
+        extensions: file.file_types,
+        rules,
+    };
+    (grammar, errors)
+}
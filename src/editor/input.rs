@@ -0,0 +1,147 @@
+use winit::event::{ElementState, KeyboardInput, ModifiersState, VirtualKeyCode};
+
+use crate::editor::keymaps::{Key, KeyPress, Modifiers};
+
+/// Turns winit's keyboard events into `KeyPress`es.
+///
+/// winit reports every key twice: as a `KeyboardInput` with the physical
+/// key, and (if it types something) as a `ReceivedCharacter`. Named keys and
+/// keys held with control, alt or logo are taken from `KeyboardInput`, since
+/// the character they produce is a control code or depends on the platform.
+/// Everything else comes from `ReceivedCharacter`, which knows about the
+/// keyboard layout.
+#[derive(Default)]
+pub struct InputTranslator {
+    modifiers: ModifiersState,
+}
+
+impl InputTranslator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks `WindowEvent::ModifiersChanged`.
+    pub fn modifiers_changed(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            control: self.modifiers.ctrl(),
+            alt: self.modifiers.alt(),
+            logo: self.modifiers.logo(),
+            shift: self.modifiers.shift(),
+        }
+    }
+
+    /// Handles `WindowEvent::KeyboardInput`.
+    pub fn keyboard_input(&self, input: &KeyboardInput) -> Option<KeyPress> {
+        if input.state != ElementState::Pressed {
+            return None;
+        }
+        let keycode = input.virtual_keycode?;
+        let mut modifiers = self.modifiers();
+
+        if let Some(key) = named_key(keycode) {
+            return Some(KeyPress::new(key, modifiers));
+        }
+
+        if !modifiers.is_command() {
+            return None;
+        }
+        let c = key_char(keycode)?;
+        let c = if modifiers.shift { c.to_ascii_uppercase() } else { c };
+        modifiers.shift = false;
+        Some(KeyPress::new(Key::Char(c), modifiers))
+    }
+
+    /// Handles `WindowEvent::ReceivedCharacter`. Characters already reported
+    /// by `keyboard_input` are dropped.
+    pub fn received_character(&self, c: char) -> Option<KeyPress> {
+        if c.is_control() || self.modifiers().is_command() {
+            return None;
+        }
+        Some(KeyPress::from(c))
+    }
+}
+
+fn named_key(keycode: VirtualKeyCode) -> Option<Key> {
+    use VirtualKeyCode::*;
+
+    let key = match keycode {
+        Escape => Key::Escape,
+        Return | NumpadEnter => Key::Enter,
+        Tab => Key::Tab,
+        Back => Key::Backspace,
+        Delete => Key::Delete,
+        Insert => Key::Insert,
+        Up => Key::Up,
+        Down => Key::Down,
+        Left => Key::Left,
+        Right => Key::Right,
+        Home => Key::Home,
+        End => Key::End,
+        PageUp => Key::PageUp,
+        PageDown => Key::PageDown,
+        F1 => Key::F(1),
+        F2 => Key::F(2),
+        F3 => Key::F(3),
+        F4 => Key::F(4),
+        F5 => Key::F(5),
+        F6 => Key::F(6),
+        F7 => Key::F(7),
+        F8 => Key::F(8),
+        F9 => Key::F(9),
+        F10 => Key::F(10),
+        F11 => Key::F(11),
+        F12 => Key::F(12),
+        F13 => Key::F(13),
+        F14 => Key::F(14),
+        F15 => Key::F(15),
+        F16 => Key::F(16),
+        F17 => Key::F(17),
+        F18 => Key::F(18),
+        F19 => Key::F(19),
+        F20 => Key::F(20),
+        F21 => Key::F(21),
+        F22 => Key::F(22),
+        F23 => Key::F(23),
+        F24 => Key::F(24),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// The unshifted character on a key, for keys held with a modifier.
+fn key_char(keycode: VirtualKeyCode) -> Option<char> {
+    use VirtualKeyCode::*;
+
+    let letters = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    if let Some(i) = letters.iter().position(|k| *k == keycode) {
+        return Some((b'a' + i as u8) as char);
+    }
+    let digits = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    if let Some(i) = digits.iter().position(|k| *k == keycode) {
+        return Some((b'0' + i as u8) as char);
+    }
+
+    let c = match keycode {
+        Space => ' ',
+        Minus => '-',
+        Equals => '=',
+        LBracket => '[',
+        RBracket => ']',
+        Backslash => '\\',
+        Semicolon => ';',
+        Apostrophe => '\'',
+        Grave => '`',
+        Comma => ',',
+        Period => '.',
+        Slash => '/',
+        Caret => '^',
+        _ => return None,
+    };
+    Some(c)
+}
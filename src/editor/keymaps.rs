@@ -29,13 +29,20 @@ use std::{collections::HashMap, rc::Rc};
 /// ^^^ This will be deprecated! ^^^
 /// Everything will be a series. renaming series to chain?
 
-use super::EditorState;
+pub mod notation;
+pub use notation::{keys_to_notation, parse_keys};
 
-#[derive(Eq, Clone, Copy, Hash)]
+/// The key `<leader>` stands for in bindings, unless it is changed.
+pub const DEFAULT_LEADER: char = '\\';
+
+/// Modifier keys held during a key press. Shift is only tracked for named
+/// keys, for characters it is already part of the character (`A` vs `a`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Modifiers {
     pub control: bool,
     pub alt: bool,
     pub logo: bool,
+    pub shift: bool,
 }
 
 impl Modifiers {
@@ -44,29 +51,32 @@ impl Modifiers {
             control: false,
             alt: false,
             logo: false,
+            shift: false,
         }
     }
     pub fn with_control() -> Self {
         Self {
             control: true,
-            alt: false,
-            logo: false,
+            ..Self::with_none()
         }
     }
     pub fn with_alt() -> Self {
         Self {
-            control: false,
             alt: true,
-            logo: false,
+            ..Self::with_none()
         }
     }
     pub fn with_logo() -> Self {
         Self {
-            control: false,
-            alt: false,
             logo: true,
+            ..Self::with_none()
         }
     }
+
+    /// True if control, alt or logo is held, ie. the key won't type anything.
+    pub fn is_command(&self) -> bool {
+        self.control || self.alt || self.logo
+    }
 }
 
 impl Default for Modifiers {
@@ -75,46 +85,386 @@ impl Default for Modifiers {
     }
 }
 
-impl PartialEq for Modifiers {
-    #[rustfmt::skip]
-    fn eq(&self, other: &Self) -> bool {
-        self.control == other.control
-        && self.alt  == other.alt
-        && self.logo == other.logo
-    }
+/// A key on the keyboard: either one that types a character, or one of the
+/// named keys which don't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Char(char),
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// F1 to F24.
+    F(u8),
 }
 
 /// A key press which also tracks modifier state.
-#[derive(Eq, Clone, Copy, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct KeyPress {
-    pub key: char,
+    pub key: Key,
     pub modifiers: Modifiers,
 }
 
+impl KeyPress {
+    pub fn new(key: Key, modifiers: Modifiers) -> Self {
+        Self { key, modifiers }
+    }
+
+    /// `key` with control held, ie. `<C-r>`.
+    pub fn control(key: char) -> Self {
+        Self::new(Key::Char(key), Modifiers::with_control())
+    }
+
+    /// True for `<Esc>` and `<C-c>`, which abandon a half typed sequence.
+    pub fn is_abort(&self) -> bool {
+        *self == Key::Escape.into() || *self == Self::control('c')
+    }
+
+    /// The character typed by the key press, if it types one. Enter and Tab
+    /// type `\n` and `\t`, keys with control, alt or logo held type nothing.
+    pub fn typed_char(&self) -> Option<char> {
+        if self.modifiers.is_command() {
+            return None;
+        }
+        match self.key {
+            Key::Char(c) => Some(c),
+            Key::Enter => Some('\n'),
+            Key::Tab => Some('\t'),
+            _ => None,
+        }
+    }
+
+    /// The character of an unmodified character key.
+    pub fn plain_char(&self) -> Option<char> {
+        match self.key {
+            Key::Char(c) if !self.modifiers.is_command() => Some(c),
+            _ => None,
+        }
+    }
+}
+
 impl From<char> for KeyPress {
     fn from(key: char) -> Self {
-        Self {
-            key,
-            modifiers: Modifiers::with_none(),
-        }
+        Self::new(Key::Char(key), Modifiers::with_none())
     }
 }
 
-impl PartialEq for KeyPress {
-    fn eq(&self, other: &Self) -> bool {
-        self.key == other.key && self.modifiers == other.modifiers
+impl From<Key> for KeyPress {
+    fn from(key: Key) -> Self {
+        Self::new(key, Modifiers::with_none())
     }
 }
 
-pub type Chain = HashMap<KeyPress, ChainLink>;
+/// Extra information handed to a command along with the editor.
+#[derive(Clone, Default, Debug)]
+pub struct ChainContext {
+    /// the count typed before the chain, None if there wasn't one.
+    pub count: Option<usize>,
+    /// the register picked with `"x` before the chain, if any.
+    pub register: Option<char>,
+    /// the arguments of a command run by name, checked against its `ArgSpec`s.
+    pub args: Vec<String>,
+}
 
+impl ChainContext {
+    /// The count, treating a missing count as 1.
+    pub fn count(&self) -> usize {
+        self.count.unwrap_or(1)
+    }
+
+    pub fn arg(&self, index: usize) -> Option<&str> {
+        self.args.get(index).map(String::as_str)
+    }
+}
+
+/// A command in the `CommandRegistry` by name, possibly followed by its
+/// arguments, ie. `motion.left` or `buffer.switch 2`.
+pub type CommandName = Rc<str>;
+
+/// A keymap, stored as a trie: each node can be bound to a command, and
+/// lead on to more nodes by key.
+///
+/// Nodes are shared through `Rc` and never changed once they are in use.
+/// Binding keys copies the nodes along the path (see `Rc::make_mut`), so a
+/// `ChainCursor` partway through a sequence, or a command which is running,
+/// never sees the keymap change under it.
+#[derive(Clone, Default)]
+pub struct Chain {
+    command: Option<CommandName>,
+    children: HashMap<KeyPress, Rc<Chain>>,
+}
+
+/// What a key is bound to in a `Chain`. Commands are bound by name, and
+/// looked up in the `CommandRegistry` when the keys are typed.
 pub enum ChainLink {
     SubChain(Chain),
-    Func(Box<dyn Fn(&mut EditorState)>),
+    Command(CommandName),
+}
+
+impl From<&str> for ChainLink {
+    fn from(name: &str) -> Self {
+        Self::Command(name.into())
+    }
+}
+
+impl From<String> for ChainLink {
+    fn from(name: String) -> Self {
+        Self::Command(name.into())
+    }
 }
 
-impl<F: Fn(&mut EditorState) + 'static> From<F> for ChainLink {
-    fn from(f: F) -> Self {
-        Self::Func(Box::new(f))
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds a single key. A command replaces whatever the key was bound
+    /// to on its own, but keeps any longer bindings it starts. A sub chain
+    /// replaces everything.
+    pub fn insert(&mut self, key: KeyPress, link: ChainLink) {
+        match link {
+            ChainLink::Command(name) => Rc::make_mut(self.children.entry(key).or_default()).command = Some(name),
+            ChainLink::SubChain(chain) => {
+                self.children.insert(key, Rc::new(chain));
+            }
+        }
+    }
+
+    pub fn get(&self, key: &KeyPress) -> Option<&Rc<Chain>> {
+        self.children.get(key)
+    }
+
+    /// The command bound to the keys which lead to this chain.
+    pub fn command(&self) -> Option<&CommandName> {
+        self.command.as_ref()
+    }
+
+    /// True if this chain leads on to longer bindings.
+    pub fn has_children(&self) -> bool {
+        !self.children.is_empty()
+    }
+}
+
+/// Binds a sequence of keys in `chain`, creating the sub chains on the way.
+/// An existing binding for the same keys is replaced. Keys can both be bound
+/// to a command and start longer bindings; which one is meant is settled by
+/// the next key, or by a timeout.
+pub fn insert_keys(chain: &mut Chain, keys: &[KeyPress], link: ChainLink) -> Result<(), String> {
+    let (last, prefix) = match keys.split_last() {
+        Some(split) => split,
+        None => return Err(String::from("No keys to bind")),
+    };
+
+    let mut chain = chain;
+    for key in prefix {
+        chain = Rc::make_mut(chain.children.entry(*key).or_default());
+    }
+    chain.insert(*last, link);
+    Ok(())
+}
+
+/// Removes the binding for a sequence of keys from `chain`. Longer bindings
+/// the sequence starts are kept, and sub chains left with nothing in them
+/// are dropped.
+pub fn remove_keys(chain: &mut Chain, keys: &[KeyPress]) -> Result<(), String> {
+    let (first, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return Err(String::from("No keys to unbind")),
+    };
+    let child = match chain.children.get_mut(first) {
+        Some(child) => Rc::make_mut(child),
+        None => return Err(String::from("No such binding")),
+    };
+
+    if rest.is_empty() {
+        if child.command.take().is_none() {
+            return Err(String::from("No such binding"));
+        }
+    } else {
+        remove_keys(child, rest)?;
+    }
+
+    if child.command.is_none() && !child.has_children() {
+        chain.children.remove(first);
+    }
+    Ok(())
+}
+
+/// What a key did to the sequence being typed, see `ChainCursor::step`.
+pub enum ChainStep {
+    /// the key finished a binding.
+    Run(CommandName),
+    /// the key leads on to longer bindings, more keys are needed. The keys
+    /// so far may also be a binding on their own, see `ChainCursor::command`.
+    Pending(ChainCursor),
+    /// the key isn't bound here.
+    Unbound,
+}
+
+/// A position in a keymap, partway through a sequence of keys. Looking keys
+/// up never changes the keymap; the cursor holds on to its own node.
+#[derive(Clone)]
+pub struct ChainCursor {
+    node: Rc<Chain>,
+    keys: Vec<KeyPress>,
+}
+
+impl ChainCursor {
+    /// A cursor at the root of a keymap, with no keys typed.
+    pub fn new(root: Rc<Chain>) -> Self {
+        Self {
+            node: root,
+            keys: Vec::new(),
+        }
+    }
+
+    /// The keys typed to get here.
+    pub fn keys(&self) -> &[KeyPress] {
+        &self.keys
+    }
+
+    /// What the keys typed so far are bound to on their own, if anything.
+    pub fn command(&self) -> Option<&CommandName> {
+        self.node.command()
+    }
+
+    pub fn step(&self, key: KeyPress) -> ChainStep {
+        let node = match self.node.get(&key) {
+            Some(node) => node.clone(),
+            None => return ChainStep::Unbound,
+        };
+
+        if !node.has_children() {
+            return match node.command() {
+                Some(name) => ChainStep::Run(name.clone()),
+                None => ChainStep::Unbound,
+            };
+        }
+
+        let mut keys = self.keys.clone();
+        keys.push(key);
+        ChainStep::Pending(ChainCursor { node, keys })
+    }
+}
+
+/// Binds the editor's own keys, whose notation is known to be valid.
+pub fn bind<L: Into<ChainLink>>(chain: &mut Chain, notation: &str, link: L) {
+    let keys = parse_keys(notation, KeyPress::from(DEFAULT_LEADER)).expect("invalid built-in key notation");
+    insert_keys(chain, &keys, link.into()).expect("conflicting built-in binding");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::{EditMode, EditorState};
+
+    fn keys(notation: &str) -> Vec<KeyPress> {
+        parse_keys(notation, KeyPress::from(DEFAULT_LEADER)).unwrap()
+    }
+
+    /// Steps through `notation` from the root of `chain`, naming what the
+    /// last key did.
+    fn walk(chain: &Chain, notation: &str) -> String {
+        let mut cursor = ChainCursor::new(Rc::new(chain.clone()));
+        let mut step = ChainStep::Unbound;
+        for key in keys(notation) {
+            step = cursor.step(key);
+            match &step {
+                ChainStep::Pending(next) => cursor = next.clone(),
+                _ => break,
+            }
+        }
+        match step {
+            ChainStep::Run(name) => format!("run {}", name),
+            ChainStep::Pending(cursor) => match cursor.command() {
+                Some(name) => format!("pending {}", name),
+                None => String::from("pending"),
+            },
+            ChainStep::Unbound => String::from("unbound"),
+        }
+    }
+
+    #[test]
+    fn insert_and_step() {
+        let mut chain = Chain::new();
+        bind(&mut chain, "dd", "delete_line");
+        bind(&mut chain, "dw", "delete_word");
+        bind(&mut chain, "g", "go");
+        bind(&mut chain, "gg", "top");
+
+        assert_eq!(walk(&chain, "dd"), "run delete_line");
+        assert_eq!(walk(&chain, "d"), "pending");
+        assert_eq!(walk(&chain, "dx"), "unbound");
+        assert_eq!(walk(&chain, "x"), "unbound");
+        // `g` is bound on its own and starts `gg`, the next key decides.
+        assert_eq!(walk(&chain, "g"), "pending go");
+        assert_eq!(walk(&chain, "gg"), "run top");
+
+        // a command over a prefix keeps the longer bindings.
+        bind(&mut chain, "d", "delete");
+        assert_eq!(walk(&chain, "d"), "pending delete");
+        assert_eq!(walk(&chain, "dw"), "run delete_word");
+        assert!(insert_keys(&mut chain, &[], "nothing".into()).is_err());
+    }
+
+    #[test]
+    fn cursor_keys() {
+        let mut chain = Chain::new();
+        bind(&mut chain, "<C-x><C-s>", "save");
+        let cursor = ChainCursor::new(Rc::new(chain));
+        assert!(cursor.keys().is_empty());
+
+        let cursor = match cursor.step(KeyPress::control('x')) {
+            ChainStep::Pending(cursor) => cursor,
+            _ => panic!("<C-x> should be pending"),
+        };
+        assert_eq!(cursor.keys(), &[KeyPress::control('x')][..]);
+        assert!(cursor.command().is_none());
+    }
+
+    #[test]
+    fn remove() {
+        let mut chain = Chain::new();
+        bind(&mut chain, "d", "delete");
+        bind(&mut chain, "dw", "delete_word");
+        bind(&mut chain, "<leader>fs", "save");
+
+        remove_keys(&mut chain, &keys("d")).unwrap();
+        assert_eq!(walk(&chain, "d"), "pending");
+        assert_eq!(walk(&chain, "dw"), "run delete_word");
+
+        remove_keys(&mut chain, &keys("dw")).unwrap();
+        assert_eq!(walk(&chain, "d"), "unbound");
+
+        // a prefix is not a binding of its own.
+        assert_eq!(remove_keys(&mut chain, &keys("<leader>f")), Err(String::from("No such binding")));
+        assert_eq!(remove_keys(&mut chain, &keys("x")), Err(String::from("No such binding")));
+        assert_eq!(remove_keys(&mut chain, &[]), Err(String::from("No keys to unbind")));
+        remove_keys(&mut chain, &keys("<leader>fs")).unwrap();
+        assert!(!chain.has_children());
+    }
+
+    #[test]
+    fn bind_and_unbind() {
+        let mut state = EditorState::with_text("one two three");
+        state.bind(EditMode::Normal, "<leader>d", "buffer.undo").unwrap();
+        state.type_keys("dw<leader>d");
+        assert_eq!(state.text(), "one two three");
+
+        state.unbind(EditMode::Normal, "u").unwrap();
+        state.type_keys("dwu");
+        assert_eq!(state.text(), "two three");
+        assert!(state.unbind(EditMode::Normal, "u").is_err());
+        assert!(state.bind(EditMode::Normal, "q", "no.such.command").is_err());
     }
 }
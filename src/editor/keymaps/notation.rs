@@ -0,0 +1,205 @@
+use std::fmt;
+
+use super::{Key, KeyPress, Modifiers};
+
+/// Named keys, as they are written between `<` and `>`. The first name for
+/// a key is the one it is printed with.
+const KEY_NAMES: [(&str, Key); 21] = [
+    ("Esc", Key::Escape),
+    ("Escape", Key::Escape),
+    ("CR", Key::Enter),
+    ("Enter", Key::Enter),
+    ("Return", Key::Enter),
+    ("Tab", Key::Tab),
+    ("BS", Key::Backspace),
+    ("Backspace", Key::Backspace),
+    ("Del", Key::Delete),
+    ("Delete", Key::Delete),
+    ("Insert", Key::Insert),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("Space", Key::Char(' ')),
+    ("lt", Key::Char('<')),
+];
+
+/// Characters which can also be written by name, ie. in `<C-Space>`.
+const CHAR_NAMES: [(&str, char); 2] = [("Bar", '|'), ("Bslash", '\\')];
+
+/// Parses key notation, like `<C-x><C-s>`, `gqip` or `<leader>fs`, into the
+/// key presses it stands for.
+///
+/// Special keys are written in angle brackets, with any of the modifiers
+/// `C-` (control), `A-` or `M-` (alt), `D-` (logo) and `S-` (shift) in front
+/// of them. Names are not case sensitive. A `<` which doesn't start a key
+/// name stands for itself, `<lt>` can always be used instead.
+pub fn parse_keys(notation: &str, leader: KeyPress) -> Result<Vec<KeyPress>, String> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                if !name.is_empty() && !name.contains('<') {
+                    keys.push(parse_special(name, leader)?);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+
+        keys.push(KeyPress::from(c));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    Ok(keys)
+}
+
+/// Parses what is between the brackets of `<...>`.
+fn parse_special(name: &str, leader: KeyPress) -> Result<KeyPress, String> {
+    if name.eq_ignore_ascii_case("leader") {
+        return Ok(leader);
+    }
+
+    let mut modifiers = Modifiers::with_none();
+    let mut base = name;
+    // a modifier is a letter and a dash, with something after it. `<C-->` is control and minus.
+    while base.len() > 2 && base.as_bytes()[1] == b'-' {
+        match base.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => modifiers.control = true,
+            b'A' | b'M' => modifiers.alt = true,
+            b'D' => modifiers.logo = true,
+            b'S' => modifiers.shift = true,
+            _ => return Err(format!("Unknown modifier in <{}>", name)),
+        }
+        base = &base[2..];
+    }
+
+    let key = if base.chars().count() == 1 {
+        Key::Char(base.chars().next().unwrap())
+    } else if let Some((_, key)) = KEY_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(base)) {
+        *key
+    } else if let Some((_, c)) = CHAR_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(base)) {
+        Key::Char(*c)
+    } else if let Some(n) = function_key_number(base) {
+        Key::F(n)
+    } else {
+        return Err(format!("Unknown key: <{}>", name));
+    };
+
+    // shift is part of the character for character keys.
+    if let (Key::Char(c), true) = (key, modifiers.shift) {
+        modifiers.shift = false;
+        return Ok(KeyPress::new(Key::Char(c.to_uppercase().next().unwrap_or(c)), modifiers));
+    }
+    Ok(KeyPress::new(key, modifiers))
+}
+
+/// The number of a function key name, `F1` to `F24`.
+fn function_key_number(name: &str) -> Option<u8> {
+    let number = name.strip_prefix(|c| c == 'F' || c == 'f')?.parse().ok()?;
+    if (1..=24).contains(&number) {
+        Some(number)
+    } else {
+        None
+    }
+}
+
+/// Writes a sequence of key presses in the notation `parse_keys` reads.
+pub fn keys_to_notation(keys: &[KeyPress]) -> String {
+    keys.iter().map(KeyPress::to_string).collect()
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match (KEY_NAMES.iter().find(|(_, key)| *key == self.key), self.key) {
+            (Some((name, _)), _) => name.to_string(),
+            (None, Key::F(n)) => format!("F{}", n),
+            (None, Key::Char(c)) => c.to_string(),
+            (None, key) => format!("{:?}", key),
+        };
+
+        let mut prefix = String::new();
+        for (held, modifier) in [
+            (self.modifiers.control, "C-"),
+            (self.modifiers.alt, "A-"),
+            (self.modifiers.logo, "D-"),
+            (self.modifiers.shift, "S-"),
+        ] {
+            if held {
+                prefix.push_str(modifier);
+            }
+        }
+
+        match self.key {
+            Key::Char(_) if prefix.is_empty() && name.chars().count() == 1 => write!(formatter, "{}", name),
+            _ => write!(formatter, "<{}{}>", prefix, name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(notation: &str) -> Result<Vec<KeyPress>, String> {
+        parse_keys(notation, KeyPress::from(' '))
+    }
+
+    fn with(key: Key, modifiers: Modifiers) -> KeyPress {
+        KeyPress::new(key, modifiers)
+    }
+
+    #[test]
+    fn parse_characters() {
+        let keys: Vec<KeyPress> = "gqip".chars().map(KeyPress::from).collect();
+        assert_eq!(parse("gqip"), Ok(keys));
+        assert_eq!(parse("<"), Ok(vec![KeyPress::from('<')]));
+        assert_eq!(parse("<>"), Ok(vec![KeyPress::from('<'), KeyPress::from('>')]));
+        assert_eq!(parse("<lt>"), Ok(vec![KeyPress::from('<')]));
+        assert_eq!(parse("<Bar><bslash>"), Ok(vec![KeyPress::from('|'), KeyPress::from('\\')]));
+    }
+
+    #[test]
+    fn parse_special_keys() {
+        let none = Modifiers::with_none();
+        assert_eq!(parse("<Esc>"), Ok(vec![with(Key::Escape, none)]));
+        assert_eq!(parse("<cr><RETURN>"), Ok(vec![with(Key::Enter, none), with(Key::Enter, none)]));
+        assert_eq!(parse("<F12>"), Ok(vec![with(Key::F(12), none)]));
+        assert_eq!(parse("<leader>w"), Ok(vec![KeyPress::from(' '), KeyPress::from('w')]));
+        assert!(parse("<F25>").is_err());
+        assert!(parse("<Nope>").is_err());
+    }
+
+    #[test]
+    fn parse_modifiers() {
+        assert_eq!(parse("<C-x><C-s>"), Ok(vec![KeyPress::control('x'), KeyPress::control('s')]));
+        assert_eq!(parse("<M-a>"), Ok(vec![with(Key::Char('a'), Modifiers::with_alt())]));
+        assert_eq!(parse("<D-Left>"), Ok(vec![with(Key::Left, Modifiers::with_logo())]));
+        assert_eq!(parse("<C-->"), Ok(vec![KeyPress::control('-')]));
+        // shift is part of the character for character keys.
+        assert_eq!(parse("<S-a>"), Ok(vec![KeyPress::from('A')]));
+        let shift_tab = Modifiers {
+            shift: true,
+            ..Modifiers::with_none()
+        };
+        assert_eq!(parse("<S-Tab>"), Ok(vec![with(Key::Tab, shift_tab)]));
+        assert!(parse("<X-a>").is_err());
+    }
+
+    #[test]
+    fn print_keys() {
+        for notation in ["gqip", "<Esc>", "<C-x><C-s>", "<A-Left>", "<S-Tab>", "<F5>", "<lt>", "<C-Space>", "<CR>"] {
+            let keys = parse(notation).unwrap();
+            assert_eq!(keys_to_notation(&keys), notation);
+        }
+        let keys = parse("<Escape><Return><Space>").unwrap();
+        assert_eq!(keys_to_notation(&keys), "<Esc><CR><Space>");
+    }
+}
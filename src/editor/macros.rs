@@ -0,0 +1,207 @@
+use crate::editor::commands::{ArgKind, ArgSpec, CommandRegistry};
+use crate::editor::ex::ExCommand;
+use crate::editor::keymaps::{self, keys_to_notation, parse_keys, Chain, KeyPress};
+use crate::editor::registers::{Register, Registers};
+use crate::editor::EditorState;
+
+/// The keys typed since `q{register}`.
+pub struct MacroRecording {
+    pub register: char,
+    pub keys: Vec<KeyPress>,
+}
+
+impl EditorState {
+    /// Starts recording keys into `register`. An uppercase register appends
+    /// to what is already there.
+    pub fn start_recording(&mut self, register: char) {
+        if !Registers::is_writable(register) {
+            self.message = format!("Invalid register name: {}", register);
+            self.command_failed = true;
+            return;
+        }
+        self.recording = Some(MacroRecording {
+            register,
+            keys: Vec::new(),
+        });
+    }
+
+    /// Stops recording, and stores the keys in the register as key notation.
+    pub fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            let keys = Register::charwise(keys_to_notation(&recording.keys));
+            if let Err(e) = self.registers.set(recording.register, keys) {
+                self.message = e;
+            }
+        }
+    }
+
+    /// Replays the keys in `register` `count` times, through the same path
+    /// as typed keys. `@` replays the register played last.
+    pub fn play_macro(&mut self, register: char, count: usize) {
+        let register = match (register, self.last_macro) {
+            ('@', Some(last)) => last,
+            ('@', None) => {
+                self.message = String::from("No previously used register");
+                self.command_failed = true;
+                return;
+            }
+            (register, _) => register,
+        };
+
+        let keys = match self.registers.get(register) {
+            Ok(Some(contents)) => parse_keys(&contents.text, self.leader),
+            Ok(None) => Err(format!("Register {} is empty", register)),
+            Err(e) => Err(e),
+        };
+        let keys = match keys {
+            Ok(keys) => keys,
+            Err(e) => {
+                self.message = e;
+                self.command_failed = true;
+                return;
+            }
+        };
+        self.last_macro = Some(register);
+
+        // the keys go in front of any still waiting, so a macro can play
+        // another one part way through.
+        for _ in 0..count {
+            for key in keys.iter().rev() {
+                self.typeahead.push_front(*key);
+            }
+        }
+    }
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        "macro.record",
+        "record the keys typed until the next q into a register",
+        &[ArgSpec::required("register", ArgKind::Text)],
+        |s, ctx| {
+            if let Some(register) = ctx.arg(0).and_then(|arg| arg.chars().next()) {
+                s.start_recording(register);
+            }
+        },
+    );
+    registry.register(
+        "macro.play",
+        "replay the keys in a register, by default the one played last",
+        &[ArgSpec::optional("register", ArgKind::Text)],
+        |s, ctx| {
+            let register = ctx.arg(0).and_then(|arg| arg.chars().next()).unwrap_or('@');
+            s.play_macro(register, ctx.count());
+        },
+    );
+}
+
+/// Binds `q{register}`, which starts recording, and `@{register}` and `@@`,
+/// which play a macro back from any register that can be read. A `q` typed
+/// while recording stops it, see `EditorState::process_keystroke`.
+pub fn insert_macros(chain: &mut Chain) {
+    for register in ('a'..='z').chain('A'..='Z') {
+        keymaps::bind(chain, &format!("q{}", register), format!("macro.record {}", register));
+    }
+    let readable = ('a'..='z').chain('A'..='Z').chain('0'..='9').chain("\"-+*".chars());
+    for register in readable {
+        keymaps::bind(chain, &format!("@{}", register), format!("macro.play {}", register));
+    }
+    keymaps::bind(chain, "@@", "macro.play");
+}
+
+/// `:let @{register} = {keys}` sets a register, ie. to fix up a macro.
+/// The text may be quoted.
+pub fn ex_let(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    let mut chars = command.args.chars();
+    let register = match (chars.next(), chars.next()) {
+        (Some('@'), Some(register)) if Registers::is_writable(register) => register,
+        _ => return Err(format!("Invalid argument: {}", command.args)),
+    };
+    let value = match chars.as_str().trim_start().strip_prefix('=') {
+        Some(value) => value.trim(),
+        None => return Err(format!("Invalid argument: {}", command.args)),
+    };
+
+    let unquoted = ['"', '\'']
+        .iter()
+        .find_map(|quote| value.strip_prefix(*quote).and_then(|value| value.strip_suffix(*quote)));
+    s.registers.set(register, Register::charwise(unquoted.unwrap_or(value)))
+}
+
+/// `:registers` shows what is in each register.
+pub fn ex_registers(s: &mut EditorState, _: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    let entries: Vec<String> = s
+        .registers
+        .filled()
+        .into_iter()
+        .map(|(name, register)| format!("\"{} {}", name, register.text.replace('\n', "^J")))
+        .collect();
+    s.message = entries.join(" | ");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::EditorState;
+
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    fn register_text(state: &mut EditorState, name: char) -> Option<String> {
+        state.registers.get(name).unwrap().map(|register| register.text)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut state = EditorState::with_text("a\nb\nc\nd");
+        state.type_keys("qaix<Esc>jq");
+        assert_eq!(register_text(&mut state, 'a').as_deref(), Some("ix<Esc>j"));
+        assert!(state.recording.is_none());
+
+        state.type_keys("@a");
+        assert_eq!(state.text(), "xa\nxb\nc\nd");
+        state.type_keys("@@");
+        assert_eq!(state.text(), "xa\nxb\nxc\nd");
+    }
+
+    #[test]
+    fn replay_with_count() {
+        assert_eq!(after("a\nb\nc\nd\ne", "qaix<Esc>jq2@a"), "xa\nxb\nxc\nd\ne");
+    }
+
+    #[test]
+    fn replay_stops_when_a_motion_fails() {
+        // the `j` on the last line fails, so the other replays never happen.
+        assert_eq!(after("a\nb\nc", "qaix<Esc>jq9@a"), "xa\nxb\nxc");
+    }
+
+    #[test]
+    fn append_to_a_macro() {
+        let mut state = EditorState::with_text("a\nb\nc");
+        state.type_keys("qaix<Esc>qqAjq");
+        assert_eq!(register_text(&mut state, 'a').as_deref(), Some("ix<Esc>j"));
+        state.type_keys("@A");
+        assert_eq!(state.text(), "xa\nxb\nc");
+        assert_eq!(state.active_buffer().get_cursor_pos().0, 3);
+    }
+
+    #[test]
+    fn replay_yanks_and_deletes() {
+        assert_eq!(after("jdd\n1\n2\n3", "y$@0"), "jdd\n2\n3");
+        assert_eq!(after("jdd\n1\n2\n3", "y$@\""), "jdd\n2\n3");
+        // the delete leaves `"` holding the deleted line, `0` still has the yank.
+        assert_eq!(after("jdd\n1\n2\n3", "y$@0@@"), "jdd\n2");
+    }
+
+    #[test]
+    fn replay_an_empty_register() {
+        let mut state = EditorState::with_text("a");
+        state.type_keys("@b");
+        assert_eq!(state.message, "Register b is empty");
+        state.type_keys("@@");
+        assert_eq!(state.message, "No previously used register");
+    }
+}
@@ -0,0 +1,431 @@
+use crate::editor::buffer::Buffer;
+use crate::editor::commands::CommandRegistry;
+use crate::editor::keymaps::{self, Chain, Key};
+use crate::editor::{EditMode, EditorState};
+
+/// Movements, as described in the `keymaps` docs. A motion only computes
+/// where the cursor would end up, so the same motion can move the cursor in
+/// normal mode or mark out the range an operator works on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordForward,
+    WordBackward,
+    WordEnd,
+    BigWordForward,
+    BigWordBackward,
+    BigWordEnd,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    DocumentStart,
+    DocumentEnd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    Whitespace,
+    Punctuation,
+    Word,
+}
+
+/// What kind of character `c` is to word motions. A big word (`W`) is
+/// anything that isn't whitespace.
+pub fn char_class(c: char, big_word: bool) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Whitespace
+    } else if big_word || c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// The last column the cursor can sit on in normal mode.
+fn last_column(buffer: &Buffer, line: usize) -> usize {
+    buffer.line_len(line - 1).saturating_sub(1)
+}
+
+fn first_non_blank(buffer: &Buffer, line: usize) -> usize {
+    buffer
+        .get_text()
+        .line(line - 1)
+        .chars()
+        .take(buffer.line_len(line - 1))
+        .position(|c| c != ' ' && c != '\t')
+        .unwrap_or_else(|| last_column(buffer, line))
+}
+
+/// True if `char_index` is the newline of a line with nothing else on it.
+/// Word motions treat empty lines as words of their own.
+fn is_empty_line(buffer: &Buffer, char_index: usize) -> bool {
+    let text = buffer.get_text();
+    text.char(char_index) == '\n' && (char_index == 0 || text.char(char_index - 1) == '\n')
+}
+
+fn word_forward(buffer: &Buffer, from: usize, big_word: bool) -> usize {
+    let text = buffer.get_text();
+    let len = text.len_chars();
+    let mut i = from;
+    if i >= len {
+        return i;
+    }
+
+    // skip the rest of the word the cursor is on
+    let class = char_class(text.char(i), big_word);
+    if class != CharClass::Whitespace {
+        while i < len && char_class(text.char(i), big_word) == class {
+            i += 1;
+        }
+    }
+
+    // then the whitespace after it
+    while i < len && text.char(i).is_whitespace() {
+        if i != from && is_empty_line(buffer, i) {
+            break;
+        }
+        i += 1;
+    }
+
+    // after the last word the motion stops at the end of the buffer, so an
+    // operator takes in all of that word. A final newline isn't skipped.
+    if i == len && text.char(len - 1) == '\n' {
+        len - 1
+    } else {
+        i
+    }
+}
+
+fn word_end(buffer: &Buffer, from: usize, big_word: bool) -> usize {
+    let text = buffer.get_text();
+    let len = text.len_chars();
+    let mut i = from + 1;
+
+    while i < len && text.char(i).is_whitespace() {
+        i += 1;
+    }
+    if i >= len {
+        return from;
+    }
+
+    let class = char_class(text.char(i), big_word);
+    while i + 1 < len && char_class(text.char(i + 1), big_word) == class {
+        i += 1;
+    }
+    i
+}
+
+fn word_backward(buffer: &Buffer, from: usize, big_word: bool) -> usize {
+    let text = buffer.get_text();
+    if from == 0 {
+        return 0;
+    }
+    let mut i = from - 1;
+
+    while i > 0 && text.char(i).is_whitespace() {
+        if is_empty_line(buffer, i) {
+            return i;
+        }
+        i -= 1;
+    }
+
+    let class = char_class(text.char(i), big_word);
+    while i > 0 && char_class(text.char(i - 1), big_word) == class {
+        i -= 1;
+    }
+    i
+}
+
+impl Motion {
+    pub const ALL: [Motion; 15] = [
+        Self::Left,
+        Self::Right,
+        Self::Up,
+        Self::Down,
+        Self::WordForward,
+        Self::WordBackward,
+        Self::WordEnd,
+        Self::BigWordForward,
+        Self::BigWordBackward,
+        Self::BigWordEnd,
+        Self::LineStart,
+        Self::FirstNonBlank,
+        Self::LineEnd,
+        Self::DocumentStart,
+        Self::DocumentEnd,
+    ];
+
+    /// The name of the motion's command, without the `motion.` in front.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::WordForward => "word_forward",
+            Self::WordBackward => "word_backward",
+            Self::WordEnd => "word_end",
+            Self::BigWordForward => "big_word_forward",
+            Self::BigWordBackward => "big_word_backward",
+            Self::BigWordEnd => "big_word_end",
+            Self::LineStart => "line_start",
+            Self::FirstNonBlank => "first_non_blank",
+            Self::LineEnd => "line_end",
+            Self::DocumentStart => "document_start",
+            Self::DocumentEnd => "document_end",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Left => "left a character",
+            Self::Right => "right a character",
+            Self::Up => "up a line",
+            Self::Down => "down a line",
+            Self::WordForward => "to the start of the next word",
+            Self::WordBackward => "to the start of the word",
+            Self::WordEnd => "to the end of the word",
+            Self::BigWordForward => "to the start of the next WORD",
+            Self::BigWordBackward => "to the start of the WORD",
+            Self::BigWordEnd => "to the end of the WORD",
+            Self::LineStart => "to the start of the line",
+            Self::FirstNonBlank => "to the first non-blank character of the line",
+            Self::LineEnd => "to the end of the line",
+            Self::DocumentStart => "to the first line, or the line given by the count",
+            Self::DocumentEnd => "to the last line, or the line given by the count",
+        }
+    }
+
+    /// Where the motion takes the cursor when it starts at `from`. `count` is
+    /// the count as it was typed, None if there wasn't one.
+    ///
+    /// Returns None if the motion can't move at all, ie. `k` on the first line.
+    /// Motions to a fixed place, like `G` on the last line, return `from`.
+    pub fn target(&self, buffer: &Buffer, from: (usize, usize), count: Option<usize>) -> Option<(usize, usize)> {
+        self.find_target(buffer, from, count, false)
+    }
+
+    /// Like `target`, but the cursor can also go just past the last
+    /// character of a line, as it can in insert mode.
+    pub fn target_past_end(&self, buffer: &Buffer, from: (usize, usize), count: Option<usize>) -> Option<(usize, usize)> {
+        self.find_target(buffer, from, count, true)
+    }
+
+    fn find_target(
+        &self,
+        buffer: &Buffer,
+        from: (usize, usize),
+        count: Option<usize>,
+        past_end: bool,
+    ) -> Option<(usize, usize)> {
+        let last_column = |line: usize| match past_end {
+            true => buffer.line_len(line - 1),
+            false => last_column(buffer, line),
+        };
+        let (line, column) = from;
+        let n = count.unwrap_or(1);
+        let last_line = buffer.line_count();
+
+        let target = match self {
+            Self::Left => (line, column.saturating_sub(n)),
+            Self::Right => (line, (column + n).min(last_column(line))),
+            Self::Up | Self::Down => {
+                let line = match self {
+                    Self::Up => line.saturating_sub(n).max(1),
+                    _ => (line + n).min(last_line),
+                };
+                let preferred = buffer.get_preferred_column().unwrap_or(column);
+                (line, preferred.min(last_column(line)))
+            }
+
+            Self::WordForward | Self::BigWordForward
+            | Self::WordBackward | Self::BigWordBackward
+            | Self::WordEnd | Self::BigWordEnd => {
+                let big_word = matches!(self, Self::BigWordForward | Self::BigWordBackward | Self::BigWordEnd);
+                let mut i = buffer.pos_to_char(from);
+                for _ in 0..n {
+                    i = match self {
+                        Self::WordForward | Self::BigWordForward => word_forward(buffer, i, big_word),
+                        Self::WordBackward | Self::BigWordBackward => word_backward(buffer, i, big_word),
+                        _ => word_end(buffer, i, big_word),
+                    };
+                }
+                buffer.char_to_pos(i)
+            }
+
+            Self::LineStart => (line, 0),
+            Self::FirstNonBlank => (line, first_non_blank(buffer, line)),
+            Self::LineEnd => {
+                let line = (line + n - 1).min(last_line);
+                (line, last_column(line))
+            }
+            Self::DocumentStart | Self::DocumentEnd => {
+                let line = match (self, count) {
+                    (_, Some(n)) => n.clamp(1, last_line),
+                    (Self::DocumentStart, None) => 1,
+                    _ => last_line,
+                };
+                (line, first_non_blank(buffer, line))
+            }
+        };
+
+        if target == from && self.fails_in_place() {
+            None
+        } else {
+            Some(target)
+        }
+    }
+
+    /// Linewise motions make operators act on whole lines.
+    pub fn is_linewise(&self) -> bool {
+        matches!(self, Self::Up | Self::Down | Self::DocumentStart | Self::DocumentEnd)
+    }
+
+    /// Motions to a fixed place, like `0` or `G`, aren't an error when the
+    /// cursor is already there. Others, like `j` on the last line, are, and
+    /// stop a macro.
+    pub fn fails_in_place(&self) -> bool {
+        !matches!(
+            self,
+            Self::LineStart | Self::FirstNonBlank | Self::LineEnd | Self::DocumentStart | Self::DocumentEnd
+        )
+    }
+
+    /// Inclusive motions make operators act on the character under the target too.
+    pub fn is_inclusive(&self) -> bool {
+        matches!(self, Self::WordEnd | Self::BigWordEnd | Self::LineEnd)
+    }
+}
+
+impl EditorState {
+    /// Moves the cursor of the active buffer. Returns false if the motion
+    /// couldn't move.
+    pub fn apply_motion(&mut self, motion: Motion, count: Option<usize>) -> bool {
+        let buffer = self.active_buffer();
+        let from = buffer.get_cursor_pos();
+        let target = match self.mode {
+            // the arrow keys can move past the last character to append there.
+            EditMode::Insert => motion.target_past_end(buffer, from, count),
+            _ => motion.target(buffer, from, count),
+        };
+        let target = match target {
+            Some(target) => target,
+            None => return false,
+        };
+
+        // vertical motions try to stay in the column they started from.
+        let preferred_column = match motion {
+            Motion::Up | Motion::Down => Some(buffer.get_preferred_column().unwrap_or(from.1)),
+            Motion::LineEnd => Some(usize::MAX),
+            _ => None,
+        };
+
+        let buffer = self.active_buffer_mut();
+        buffer.set_cursor_pos(target);
+        buffer.set_preferred_column(preferred_column);
+        true
+    }
+}
+
+/// Registers a `motion.*` command for every motion.
+pub fn register_commands(registry: &mut CommandRegistry) {
+    for motion in Motion::ALL {
+        let description = format!("move {}", motion.description());
+        registry.register(motion_command(motion), description, &[], move |s, ctx| {
+            if !s.run_motion(motion, ctx.count) && motion.fails_in_place() {
+                s.command_failed = true;
+            }
+        });
+    }
+}
+
+/// Binds the standard motion keys into `chain`. The same bindings serve
+/// normal mode and operator-pending mode, see `EditorState::run_motion`.
+pub fn insert_motions(chain: &mut Chain) {
+    let bindings = [
+        ('h', Motion::Left),
+        ('l', Motion::Right),
+        ('k', Motion::Up),
+        ('j', Motion::Down),
+        ('w', Motion::WordForward),
+        ('b', Motion::WordBackward),
+        ('e', Motion::WordEnd),
+        ('W', Motion::BigWordForward),
+        ('B', Motion::BigWordBackward),
+        ('E', Motion::BigWordEnd),
+        ('0', Motion::LineStart),
+        ('^', Motion::FirstNonBlank),
+        ('$', Motion::LineEnd),
+        ('G', Motion::DocumentEnd),
+    ];
+
+    for (key, motion) in bindings {
+        chain.insert(key.into(), motion_command(motion).into());
+    }
+    insert_arrow_keys(chain);
+    keymaps::bind(chain, "gg", motion_command(Motion::DocumentStart));
+}
+
+/// Binds the arrow keys, Home and End. These are also bound in insert mode.
+pub fn insert_arrow_keys(chain: &mut Chain) {
+    let bindings = [
+        (Key::Left, Motion::Left),
+        (Key::Right, Motion::Right),
+        (Key::Up, Motion::Up),
+        (Key::Down, Motion::Down),
+        (Key::Home, Motion::LineStart),
+        (Key::End, Motion::LineEnd),
+    ];
+
+    for (key, motion) in bindings {
+        chain.insert(key.into(), motion_command(motion).into());
+    }
+}
+
+fn motion_command(motion: Motion) -> String {
+    format!("motion.{}", motion.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::EditorState;
+
+    /// Where the cursor is after typing `keys` into a buffer holding `text`.
+    fn cursor_after(text: &str, keys: &str) -> (usize, usize) {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.active_buffer().get_cursor_pos()
+    }
+
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    #[test]
+    fn normal_mode_stops_on_the_last_character() {
+        assert_eq!(cursor_after("abc\nde", "$"), (1, 2));
+        assert_eq!(cursor_after("abc\nde", "5l"), (1, 2));
+        assert_eq!(cursor_after("abc\nde", "<End>"), (1, 2));
+        assert_eq!(cursor_after("abc\nde", "$j"), (2, 1));
+    }
+
+    #[test]
+    fn insert_mode_arrows_reach_past_the_end() {
+        assert_eq!(cursor_after("abc\nde", "i<End>"), (1, 3));
+        assert_eq!(cursor_after("abc\nde", "i<Right><Right><Right><Right>"), (1, 3));
+        assert_eq!(cursor_after("abc\nde", "i<End><Down>"), (2, 2));
+        assert_eq!(cursor_after("abc\nde", "i<End><Left>"), (1, 2));
+        assert_eq!(after("abc\nde", "i<End>x<Down><End>y<Esc>"), "abcx\ndey");
+    }
+
+    #[test]
+    fn operators_take_the_last_character() {
+        assert_eq!(after("abc", "$dl"), "ab");
+        assert_eq!(after("abc", "l2dl"), "a");
+    }
+}
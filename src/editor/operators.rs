@@ -0,0 +1,477 @@
+use std::ops::Range;
+
+use crate::editor::buffer::SelectionKind;
+use crate::editor::commands::CommandRegistry;
+use crate::editor::keymaps::{self, Chain, Key};
+use crate::editor::motions::{self, Motion};
+use crate::editor::registers::Register;
+use crate::editor::textobjects::{self, TextObject};
+use crate::editor::{EditMode, EditorState};
+
+/// Operators wait for a motion or text object, then act on the text it covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+}
+
+impl Operator {
+    pub const ALL: [Operator; 8] = [
+        Self::Delete,
+        Self::Change,
+        Self::Yank,
+        Self::Indent,
+        Self::Outdent,
+        Self::Lowercase,
+        Self::Uppercase,
+        Self::ToggleCase,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::Change => "change",
+            Self::Yank => "yank",
+            Self::Indent => "indent",
+            Self::Outdent => "outdent",
+            Self::Lowercase => "lowercase",
+            Self::Uppercase => "uppercase",
+            Self::ToggleCase => "toggle_case",
+        }
+    }
+}
+
+/// An operator that has been typed, and the count and register typed
+/// before it.
+#[derive(Clone, Copy, Debug)]
+pub struct PendingOperator {
+    pub operator: Operator,
+    pub count: Option<usize>,
+    pub register: Option<char>,
+}
+
+/// Multiplies the count typed before the operator with the one typed after
+/// it, so `2d3w` deletes six words.
+fn combine_counts(first: Option<usize>, second: Option<usize>) -> Option<usize> {
+    match (first, second) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn toggle_case(c: char) -> String {
+    if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else {
+        c.to_lowercase().collect()
+    }
+}
+
+impl EditorState {
+    /// Starts waiting for a motion for `operator`.
+    pub fn begin_operator(&mut self, operator: Operator, count: Option<usize>, register: Option<char>) {
+        self.pending_operator = Some(PendingOperator {
+            operator,
+            count,
+            register,
+        });
+        self.set_mode(EditMode::OperatorPending);
+    }
+
+    pub fn cancel_operator(&mut self) {
+        self.pending_operator = None;
+        self.set_mode(EditMode::Normal);
+    }
+
+    /// Takes the pending operator and leaves operator-pending mode.
+    fn finish_operator(&mut self) -> Option<PendingOperator> {
+        let pending = self.pending_operator.take();
+        self.set_mode(EditMode::Normal);
+        pending
+    }
+
+    /// Runs a motion: in operator-pending mode it picks out the text for the
+    /// pending operator, otherwise it moves the cursor.
+    pub fn run_motion(&mut self, motion: Motion, count: Option<usize>) -> bool {
+        match self.mode {
+            EditMode::OperatorPending => self.operate_on_motion(motion, count),
+            _ => self.apply_motion(motion, count),
+        }
+    }
+
+    /// Runs a text object: in operator-pending mode the pending operator acts
+    /// on it, in visual mode it becomes the selection.
+    pub fn run_text_object(&mut self, object: TextObject, count: Option<usize>) -> bool {
+        let pending = match self.mode {
+            EditMode::OperatorPending => self.finish_operator(),
+            EditMode::Visual => return self.select_text_object(object, count),
+            _ => None,
+        };
+        let pending = match pending {
+            Some(pending) => pending,
+            None => return false,
+        };
+
+        let count = combine_counts(pending.count, count).unwrap_or(1);
+        let buffer = self.active_buffer();
+        match object.range(buffer, buffer.get_cursor_pos(), count) {
+            Some(range) => {
+                self.apply_operator(pending.operator, range, object.is_linewise(), pending.register);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn select_text_object(&mut self, object: TextObject, count: Option<usize>) -> bool {
+        let buffer = self.active_buffer();
+        let range = match object.range(buffer, buffer.get_cursor_pos(), count.unwrap_or(1)) {
+            Some(range) if !range.is_empty() => range,
+            _ => return false,
+        };
+
+        let start = buffer.char_to_pos(range.start);
+        let end = buffer.char_to_pos(range.end - 1);
+        let buffer = self.active_buffer_mut();
+        if object.is_linewise() {
+            buffer.set_selection_kind(SelectionKind::Linewise);
+        }
+        buffer.set_selection_anchor(start);
+        buffer.set_cursor_pos(end);
+        true
+    }
+
+    fn operate_on_motion(&mut self, motion: Motion, count: Option<usize>) -> bool {
+        let pending = match self.finish_operator() {
+            Some(pending) => pending,
+            None => return false,
+        };
+        let count = combine_counts(pending.count, count);
+
+        // like vim, `cw` on a word only changes to the end of the word.
+        let buffer = self.active_buffer();
+        let from = buffer.get_cursor_pos();
+        let on_word = buffer
+            .get_text()
+            .get_char(buffer.pos_to_char(from))
+            .is_some_and(|c| !c.is_whitespace());
+        let motion = match motion {
+            Motion::WordForward if pending.operator == Operator::Change && on_word => Motion::WordEnd,
+            Motion::BigWordForward if pending.operator == Operator::Change && on_word => Motion::BigWordEnd,
+            motion => motion,
+        };
+
+        let target = match motion {
+            // `l` can reach past the last character for an operator, so `dl`
+            // takes the last character of the line too.
+            Motion::Right => motion.target_past_end(buffer, from, count),
+            motion => motion.target(buffer, from, count),
+        };
+        let target = match target {
+            Some(target) => target,
+            None => return false,
+        };
+
+        // a linewise motion that stays put, ie. `dG` on the last line, acts
+        // on the current line.
+        if motion.is_linewise() {
+            let range = self.line_range(from.0.min(target.0), from.0.max(target.0));
+            self.apply_operator(pending.operator, range, true, pending.register);
+            return true;
+        }
+
+        let (start, mut end) = if target < from { (target, from) } else { (from, target) };
+        if matches!(motion, Motion::WordForward | Motion::BigWordForward) && end.0 > start.0 {
+            // a word motion that runs off the end of the line stops at the end of the line.
+            end = (start.0, buffer.line_len(start.0 - 1));
+        }
+
+        let line_end = buffer.pos_to_char((end.0, buffer.line_len(end.0 - 1)));
+        let start = buffer.pos_to_char(start);
+        let mut end = buffer.pos_to_char(end);
+        if motion.is_inclusive() {
+            end = (end + 1).min(line_end.max(end));
+        }
+        if start == end && pending.operator != Operator::Change {
+            // an exclusive motion that stays put, ie. `d0` at the start of the line.
+            return true;
+        }
+
+        self.apply_operator(pending.operator, start..end, false, pending.register);
+        true
+    }
+
+    /// Applies the pending operator to `count` whole lines, for `dd`, `yy`, `>>` etc.
+    /// Does nothing unless `operator` is the one that is pending.
+    pub fn operate_on_lines(&mut self, operator: Operator, count: Option<usize>) -> bool {
+        let pending = match self.finish_operator() {
+            Some(pending) if pending.operator == operator => pending,
+            _ => return false,
+        };
+
+        let count = combine_counts(pending.count, count).unwrap_or(1);
+        let first = self.active_buffer().get_cursor_pos().0;
+        let last = (first + count - 1).min(self.active_buffer().line_count());
+
+        let range = self.line_range(first, last);
+        self.apply_operator(operator, range, true, pending.register);
+        true
+    }
+
+    /// The characters making up lines `first..=last`, including their newlines.
+    pub fn line_range(&self, first: usize, last: usize) -> Range<usize> {
+        let text = self.active_buffer().get_text();
+        let start = text.line_to_char(first - 1);
+        let end = if last < text.len_lines() {
+            text.line_to_char(last)
+        } else {
+            text.len_chars()
+        };
+        start..end
+    }
+
+    /// Performs `operator` on a range of characters. Linewise ranges cover
+    /// whole lines, as returned by `line_range`. Deleted, changed and yanked
+    /// text goes into `register`, or the default registers if there isn't one.
+    pub fn apply_operator(&mut self, operator: Operator, range: Range<usize>, linewise: bool, register: Option<char>) {
+        let text = self.active_buffer().get_range(range.clone());
+        let value = if linewise {
+            Register::linewise(text)
+        } else {
+            Register::charwise(text)
+        };
+        self.store_operator_text(operator, register, value);
+        self.perform_operator(operator, range, linewise);
+    }
+
+    /// Keeps the text an operator takes out of the buffer, or yanks.
+    /// Other operators don't touch the registers.
+    pub fn store_operator_text(&mut self, operator: Operator, name: Option<char>, value: Register) {
+        let result = match operator {
+            Operator::Yank => self.registers.record_yank(name, value),
+            Operator::Delete | Operator::Change => self.registers.record_delete(name, value),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.message = e;
+        }
+    }
+
+    /// Changes the buffer for `operator`, without touching the registers.
+    pub fn perform_operator(&mut self, operator: Operator, range: Range<usize>, linewise: bool) {
+        // a change is finished when its insert ends, and yanks change nothing.
+        if !matches!(operator, Operator::Change | Operator::Yank) {
+            self.finish_change();
+        }
+
+        let buffer = self.active_buffer();
+        let original = buffer.get_range(range.clone());
+        let first_line = buffer.char_to_pos(range.start).0;
+        let last_line = buffer.char_to_pos(range.end.max(range.start + 1) - 1).0.max(first_line);
+
+        match operator {
+            Operator::Delete => {
+                let buffer = self.active_buffer_mut();
+                let mut range = range;
+                if linewise && !original.ends_with('\n') && range.start > 0 {
+                    // the last line has no newline of its own, take the one before it.
+                    range.start -= 1;
+                }
+                buffer.delete_range(range);
+                if linewise {
+                    let line = first_line.min(buffer.line_count());
+                    self.apply_motion_to_line(line);
+                }
+            }
+            Operator::Change => {
+                let buffer = self.active_buffer_mut();
+                buffer.begin_change();
+                if linewise {
+                    // keep the line itself, only its contents go.
+                    let end = buffer.pos_to_char((last_line, buffer.line_len(last_line - 1)));
+                    buffer.delete_range(range.start..end);
+                } else {
+                    buffer.delete_range(range);
+                }
+                self.set_insert_mode();
+            }
+            Operator::Yank => {
+                let start = self.active_buffer().char_to_pos(range.start);
+                self.active_buffer_mut().set_cursor_pos(start);
+            }
+            Operator::Indent | Operator::Outdent => {
+                let shift_width = self.options.shift_width;
+                let buffer = self.active_buffer_mut();
+                buffer.begin_change();
+                for line in first_line..=last_line {
+                    let start = buffer.pos_to_char((line, 0));
+                    if operator == Operator::Indent {
+                        if buffer.line_len(line - 1) > 0 {
+                            buffer.replace_range(start..start, &" ".repeat(shift_width));
+                        }
+                    } else {
+                        let indent = buffer
+                            .get_text()
+                            .line(line - 1)
+                            .chars()
+                            .take(shift_width)
+                            .take_while(|c| *c == ' ')
+                            .count();
+                        buffer.delete_range(start..start + indent);
+                    }
+                }
+                buffer.end_change();
+                self.apply_motion_to_line(first_line);
+            }
+            Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase => {
+                let replacement: String = match operator {
+                    Operator::Lowercase => original.to_lowercase(),
+                    Operator::Uppercase => original.to_uppercase(),
+                    _ => original.chars().map(toggle_case).collect(),
+                };
+                self.active_buffer_mut().replace_range(range, &replacement);
+            }
+        }
+    }
+
+    /// Puts the cursor on the first non-blank character of `line`.
+    pub fn apply_motion_to_line(&mut self, line: usize) {
+        let buffer = self.active_buffer_mut();
+        buffer.set_cursor_pos((line, 0));
+        if let Some(target) = Motion::FirstNonBlank.target(buffer, buffer.get_cursor_pos(), None) {
+            buffer.set_cursor_pos(target);
+        }
+    }
+}
+
+/// Registers `operator.*` commands, which start an operator, and
+/// `operator.*_lines`, which apply the pending one to whole lines.
+pub fn register_commands(registry: &mut CommandRegistry) {
+    for operator in Operator::ALL {
+        let description = format!("{} the text of the next motion or text object", operator.name().replace('_', " "));
+        registry.register(begin_operator_command(operator), description, &[], move |s, ctx| {
+            s.begin_operator(operator, ctx.count, ctx.register)
+        });
+
+        let description = format!("{} whole lines, when the operator is pending", operator.name().replace('_', " "));
+        registry.register(line_operator_command(operator), description, &[], move |s, ctx| {
+            s.operate_on_lines(operator, ctx.count);
+        });
+    }
+    registry.register("operator.cancel", "stop waiting for a motion", &[], |s, _| s.cancel_operator());
+}
+
+/// Binds the operator keys into normal mode's `chain`.
+pub fn insert_operators(chain: &mut Chain) {
+    let bindings = [
+        ('d', Operator::Delete),
+        ('c', Operator::Change),
+        ('y', Operator::Yank),
+        ('>', Operator::Indent),
+        ('<', Operator::Outdent),
+    ];
+    for (key, operator) in bindings {
+        chain.insert(key.into(), begin_operator_command(operator).into());
+    }
+
+    keymaps::bind(chain, "gu", begin_operator_command(Operator::Lowercase));
+    keymaps::bind(chain, "gU", begin_operator_command(Operator::Uppercase));
+    keymaps::bind(chain, "g~", begin_operator_command(Operator::ToggleCase));
+}
+
+/// Builds the chain used while an operator is pending: every motion and text
+/// object, and the operator keys again for the linewise `dd`, `gUU` forms.
+pub fn operator_chain() -> Chain {
+    let mut chain = Chain::new();
+    motions::insert_motions(&mut chain);
+    textobjects::insert_text_objects(&mut chain);
+
+    let bindings = [
+        ('d', Operator::Delete),
+        ('c', Operator::Change),
+        ('y', Operator::Yank),
+        ('>', Operator::Indent),
+        ('<', Operator::Outdent),
+        ('u', Operator::Lowercase),
+        ('U', Operator::Uppercase),
+        ('~', Operator::ToggleCase),
+    ];
+    for (key, operator) in bindings {
+        chain.insert(key.into(), line_operator_command(operator).into());
+    }
+
+    keymaps::bind(&mut chain, "gu", line_operator_command(Operator::Lowercase));
+    keymaps::bind(&mut chain, "gU", line_operator_command(Operator::Uppercase));
+    keymaps::bind(&mut chain, "g~", line_operator_command(Operator::ToggleCase));
+
+    chain.insert(Key::Escape.into(), "operator.cancel".into());
+    chain
+}
+
+fn begin_operator_command(operator: Operator) -> String {
+    format!("operator.{}", operator.name())
+}
+
+fn line_operator_command(operator: Operator) -> String {
+    format!("operator.{}_lines", operator.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::EditorState;
+
+    /// The text left after typing `keys` into a buffer holding `text`.
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    #[test]
+    fn word_motions() {
+        assert_eq!(after("one two three", "dw"), "two three");
+        assert_eq!(after("one two three", "d2w"), "three");
+        assert_eq!(after("one two\nthree", "wdw"), "one \nthree");
+        assert_eq!(after("one two", "cwx<Esc>"), "x two");
+    }
+
+    #[test]
+    fn word_motions_at_end_of_buffer() {
+        assert_eq!(after("one two", "wdw"), "one ");
+        assert_eq!(after("one two", "wcwx<Esc>"), "one x");
+        assert_eq!(after("one two\n", "wdw"), "one \n");
+        assert_eq!(after("one", "dw"), "");
+    }
+
+    #[test]
+    fn motions_that_stay_put() {
+        assert_eq!(after("one\ntwo", "jdG"), "one");
+        assert_eq!(after("one\ntwo", "dgg"), "two");
+        assert_eq!(after("one two", "$d$"), "one tw");
+        assert_eq!(after("one two", "$dl"), "one tw");
+        assert_eq!(after("one two", "d0"), "one two");
+        assert_eq!(after("one two", "c0x<Esc>"), "xone two");
+    }
+
+    #[test]
+    fn motions_that_fail() {
+        assert_eq!(after("one\ntwo", "jdj"), "one\ntwo");
+        assert_eq!(after("one two", "dh"), "one two");
+        assert_eq!(after("one two", "dhdw"), "two");
+    }
+
+    #[test]
+    fn line_operators() {
+        assert_eq!(after("one\ntwo\nthree", "dd"), "two\nthree");
+        assert_eq!(after("one\ntwo\nthree", "2dd"), "three");
+        assert_eq!(after("one\ntwo\nthree", "jdk"), "three");
+        assert_eq!(after("one\ntwo", ">>"), "    one\ntwo");
+        assert_eq!(after("one two", "g~w"), "ONE two");
+    }
+}
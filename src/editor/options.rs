@@ -0,0 +1,109 @@
+/// Editor settings which can be changed with `:set`.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// how many spaces `>` and `<` shift lines by.
+    pub shift_width: usize,
+    /// searches match either case.
+    pub ignore_case: bool,
+    /// ...unless the pattern has an uppercase letter in it.
+    pub smart_case: bool,
+    /// move to the match while the pattern is being typed.
+    pub incremental_search: bool,
+    /// highlight every match of the last search.
+    pub highlight_search: bool,
+    /// searches carry on from the other end of the buffer.
+    pub wrap_scan: bool,
+    /// stop waiting for the rest of an ambiguous key sequence after `timeout_len`.
+    pub timeout: bool,
+    /// in milliseconds.
+    pub timeout_len: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            shift_width: 4,
+            ignore_case: false,
+            smart_case: false,
+            incremental_search: true,
+            highlight_search: true,
+            wrap_scan: true,
+            timeout: true,
+            timeout_len: 1000,
+        }
+    }
+}
+
+enum OptionValue<'a> {
+    Number(&'a mut usize),
+    Bool(&'a mut bool),
+}
+
+impl Options {
+    /// Finds an option by its full or short name.
+    fn lookup(&mut self, name: &str) -> Option<(&'static str, OptionValue<'_>)> {
+        match name {
+            "shiftwidth" | "sw" => Some(("shiftwidth", OptionValue::Number(&mut self.shift_width))),
+            "ignorecase" | "ic" => Some(("ignorecase", OptionValue::Bool(&mut self.ignore_case))),
+            "smartcase" | "scs" => Some(("smartcase", OptionValue::Bool(&mut self.smart_case))),
+            "incsearch" | "is" => Some(("incsearch", OptionValue::Bool(&mut self.incremental_search))),
+            "hlsearch" | "hls" => Some(("hlsearch", OptionValue::Bool(&mut self.highlight_search))),
+            "wrapscan" | "ws" => Some(("wrapscan", OptionValue::Bool(&mut self.wrap_scan))),
+            "timeout" | "to" => Some(("timeout", OptionValue::Bool(&mut self.timeout))),
+            "timeoutlen" | "tm" => Some(("timeoutlen", OptionValue::Number(&mut self.timeout_len))),
+            _ => None,
+        }
+    }
+
+    /// Applies one `:set` argument: `name`, `noname`, `invname`, `name!`,
+    /// `name=value` or `name?`. Queries return the option's value.
+    pub fn set(&mut self, argument: &str) -> Result<Option<String>, String> {
+        let (name, value) = match argument.find(['=', ':']) {
+            Some(i) => (&argument[..i], Some(&argument[i + 1..])),
+            None => (argument, None),
+        };
+
+        let (name, query) = match name.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let (name, toggle) = match name.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+
+        if let Some((full_name, option)) = self.lookup(name) {
+            return match (option, value) {
+                (OptionValue::Number(n), _) if query || (value.is_none() && !toggle) => {
+                    Ok(Some(format!("{}={}", full_name, n)))
+                }
+                (OptionValue::Number(n), Some(value)) => {
+                    *n = value
+                        .parse()
+                        .map_err(|_| format!("Number required after =: {}", argument))?;
+                    Ok(None)
+                }
+                (OptionValue::Bool(b), None) if query => {
+                    Ok(Some(format!("{}{}", if *b { "" } else { "no" }, full_name)))
+                }
+                (OptionValue::Bool(b), None) => {
+                    *b = if toggle { !*b } else { true };
+                    Ok(None)
+                }
+                _ => Err(format!("Invalid argument: {}", argument)),
+            };
+        }
+
+        // `noname` and `invname` turn a flag off or flip it.
+        for (prefix, invert) in [("no", false), ("inv", true)] {
+            if let Some(name) = name.strip_prefix(prefix) {
+                if let Some((_, OptionValue::Bool(b))) = self.lookup(name) {
+                    *b = invert && !*b;
+                    return Ok(None);
+                }
+            }
+        }
+
+        Err(format!("Unknown option: {}", name))
+    }
+}
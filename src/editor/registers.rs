@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+
+use crate::editor::clipboard::{ClipboardProvider, MemoryClipboard};
+use crate::editor::commands::{ArgKind, ArgSpec, CommandRegistry};
+use crate::editor::keymaps::{self, Chain};
+use crate::editor::EditorState;
+
+/// How the text of a register goes back into the buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterKind {
+    /// put inside the line, at the cursor.
+    Charwise,
+    /// whole lines, put above or below the cursor's line.
+    Linewise,
+    /// a column of text, one line of it on each buffer line.
+    Blockwise,
+}
+
+/// The contents of a register.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn charwise<S: Into<String>>(text: S) -> Self {
+        Self {
+            text: text.into(),
+            kind: RegisterKind::Charwise,
+        }
+    }
+
+    /// Whole lines. The text always ends in a newline.
+    pub fn linewise<S: Into<String>>(text: S) -> Self {
+        let mut text = text.into();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Self {
+            text,
+            kind: RegisterKind::Linewise,
+        }
+    }
+
+    pub fn blockwise<S: Into<String>>(text: S) -> Self {
+        Self {
+            text: text.into(),
+            kind: RegisterKind::Blockwise,
+        }
+    }
+
+    /// Adds `other` to the end, as `"Ayy` does. Anything with lines in it
+    /// makes the whole register linewise.
+    fn append(&mut self, other: Register) {
+        if other.kind == RegisterKind::Linewise && !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.text.push_str(&other.text);
+        if other.kind == RegisterKind::Linewise {
+            self.kind = RegisterKind::Linewise;
+        }
+    }
+}
+
+/// Text kept under a single character name:
+///
+/// - `"` the unnamed register, holding the last yank or delete.
+/// - `0` the last yank, and `1` to `9` the last deletes of whole lines,
+///   newest first.
+/// - `-` the last delete within a line.
+/// - `a` to `z`, written to by name. `A` to `Z` append to them.
+/// - `_` the black hole, which throws away what is written to it.
+/// - `+` and `*` the clipboard.
+pub struct Registers {
+    unnamed: Option<Register>,
+    numbered: [Option<Register>; 10],
+    small_delete: Option<Register>,
+    named: BTreeMap<char, Register>,
+    clipboard: Box<dyn ClipboardProvider>,
+    /// what was last written to the clipboard, to know its kind when the
+    /// clipboard still holds the same text.
+    clipboard_register: Option<Register>,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::with_clipboard(Box::new(MemoryClipboard::new()))
+    }
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clipboard(clipboard: Box<dyn ClipboardProvider>) -> Self {
+        Self {
+            unnamed: None,
+            numbered: Default::default(),
+            small_delete: None,
+            named: BTreeMap::new(),
+            clipboard,
+            clipboard_register: None,
+        }
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn ClipboardProvider>) {
+        self.clipboard = clipboard;
+        self.clipboard_register = None;
+    }
+
+    /// True for the names of registers, ie. the `x` in `"x`.
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '_' | '+' | '*')
+    }
+
+    /// True for the names registers can be written to by name. The
+    /// numbered registers are only filled by yanks and deletes.
+    pub fn is_writable(name: char) -> bool {
+        name.is_ascii_alphabetic() || matches!(name, '"' | '-' | '_' | '+' | '*')
+    }
+
+    /// The contents of a register, or None if it is empty. Only reading
+    /// the clipboard can fail.
+    pub fn get(&mut self, name: char) -> Result<Option<Register>, String> {
+        let register = match name {
+            '"' => self.unnamed.clone(),
+            '0'..='9' => self.numbered[name as usize - '0' as usize].clone(),
+            '-' => self.small_delete.clone(),
+            '+' | '*' => {
+                let text = self.clipboard.get_text()?;
+                match &self.clipboard_register {
+                    _ if text.is_empty() => None,
+                    Some(register) if register.text == text => Some(register.clone()),
+                    _ if text.ends_with('\n') => Some(Register::linewise(text)),
+                    _ => Some(Register::charwise(text)),
+                }
+            }
+            name if name.is_ascii_alphabetic() => self.named.get(&name.to_ascii_lowercase()).cloned(),
+            _ => None,
+        };
+        Ok(register)
+    }
+
+    /// Writes a register by name. Writing to an uppercase name appends to
+    /// the lowercase register of the same letter.
+    pub fn set(&mut self, name: char, register: Register) -> Result<(), String> {
+        match name {
+            '"' => self.unnamed = Some(register),
+            '-' => self.small_delete = Some(register),
+            '_' => {}
+            '+' | '*' => {
+                self.clipboard.set_text(&register.text)?;
+                self.clipboard_register = Some(register);
+            }
+            name if name.is_ascii_uppercase() => {
+                let lowercase = name.to_ascii_lowercase();
+                match self.named.get_mut(&lowercase) {
+                    Some(existing) => existing.append(register),
+                    None => {
+                        self.named.insert(lowercase, register);
+                    }
+                }
+            }
+            name if name.is_ascii_lowercase() => {
+                self.named.insert(name, register);
+            }
+            name => return Err(format!("Invalid register name: {}", name)),
+        }
+        Ok(())
+    }
+
+    /// Keeps yanked text: in the register given, or else in `0`. Either
+    /// way the unnamed register gets it too.
+    pub fn record_yank(&mut self, name: Option<char>, register: Register) -> Result<(), String> {
+        match name {
+            Some(name) => self.record_named(name, register),
+            None => {
+                self.numbered[0] = Some(register.clone());
+                self.unnamed = Some(register);
+                Ok(())
+            }
+        }
+    }
+
+    /// Keeps deleted text: in the register given, or else in `1` if it has
+    /// lines in it, shifting the older deletes along, or in `-` if not.
+    /// Either way the unnamed register gets it too.
+    pub fn record_delete(&mut self, name: Option<char>, register: Register) -> Result<(), String> {
+        match name {
+            Some(name) => self.record_named(name, register),
+            None => {
+                if register.kind == RegisterKind::Charwise && !register.text.contains('\n') {
+                    self.small_delete = Some(register.clone());
+                } else {
+                    self.numbered[1..].rotate_right(1);
+                    self.numbered[1] = Some(register.clone());
+                }
+                self.unnamed = Some(register);
+                Ok(())
+            }
+        }
+    }
+
+    fn record_named(&mut self, name: char, register: Register) -> Result<(), String> {
+        if name == '_' {
+            return Ok(());
+        }
+        self.set(name, register)?;
+        self.unnamed = self.get(name)?;
+        Ok(())
+    }
+
+    /// The registers which hold something, in the order `:registers`
+    /// shows them.
+    pub fn filled(&mut self) -> Vec<(char, Register)> {
+        let names = ['"']
+            .iter()
+            .copied()
+            .chain('0'..='9')
+            .chain(Some('-'))
+            .chain('a'..='z')
+            .chain(Some('+'));
+        names
+            .filter_map(|name| match self.get(name) {
+                Ok(Some(register)) => Some((name, register)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl EditorState {
+    /// Picks the register the next yank, delete or put uses, for `"x`.
+    /// A count typed before it is kept for the command after it.
+    pub fn select_register(&mut self, name: char, count: Option<usize>) {
+        if !Registers::is_valid(name) {
+            self.message = format!("Invalid register name: {}", name);
+            self.command_failed = true;
+            return;
+        }
+        self.pending_register = Some(name);
+        self.pending_count = count;
+    }
+
+    /// Puts the contents of a register into the buffer `count` times, after
+    /// the cursor or before it. By default the unnamed register is put.
+    pub fn put_register(&mut self, name: Option<char>, count: usize, after: bool) {
+        let name = name.unwrap_or('"');
+        let register = match self.registers.get(name) {
+            Ok(Some(register)) => register,
+            Ok(None) => {
+                self.message = format!("Register {} is empty", name);
+                self.command_failed = true;
+                return;
+            }
+            Err(e) => {
+                self.message = e;
+                self.command_failed = true;
+                return;
+            }
+        };
+
+        self.finish_change();
+        self.active_buffer_mut().begin_change();
+        match register.kind {
+            RegisterKind::Charwise => self.put_charwise(&register.text.repeat(count), after),
+            RegisterKind::Linewise => self.put_linewise(&register.text.repeat(count), after),
+            RegisterKind::Blockwise => self.put_blockwise(&register.text, count, after),
+        }
+        self.active_buffer_mut().end_change();
+    }
+
+    /// Puts text inside the cursor's line. The cursor ends on the last
+    /// character put, or on the first if the text has lines in it.
+    fn put_charwise(&mut self, text: &str, after: bool) {
+        let buffer = self.active_buffer_mut();
+        let (line, column) = buffer.get_cursor_pos();
+        let len = buffer.line_len(line - 1);
+        let column = if after && len > 0 { (column + 1).min(len) } else { column.min(len) };
+
+        let at = buffer.pos_to_char((line, column));
+        buffer.replace_range(at..at, text);
+        let cursor = if text.contains('\n') {
+            at
+        } else {
+            at + text.chars().count().saturating_sub(1)
+        };
+        buffer.set_cursor_pos(buffer.char_to_pos(cursor));
+    }
+
+    /// Puts whole lines below or above the cursor's line, and moves to the
+    /// first of them.
+    fn put_linewise(&mut self, text: &str, after: bool) {
+        let buffer = self.active_buffer_mut();
+        let line = buffer.get_cursor_pos().0;
+        let first = if after { line + 1 } else { line };
+
+        if !after {
+            let at = buffer.pos_to_char((line, 0));
+            buffer.replace_range(at..at, text);
+        } else if line < buffer.line_count() {
+            let at = buffer.pos_to_char((line + 1, 0));
+            buffer.replace_range(at..at, text);
+        } else {
+            // the last line has no newline to put the lines after.
+            let at = buffer.get_text().len_chars();
+            let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(text));
+            buffer.replace_range(at..at, &text);
+        }
+        self.apply_motion_to_line(first);
+    }
+
+    /// Puts a block `count` times over, at the same column on the cursor's
+    /// line and the ones below it. Short lines are padded out with spaces
+    /// and lines are added at the end of the buffer as needed.
+    fn put_blockwise(&mut self, text: &str, count: usize, after: bool) {
+        let buffer = self.active_buffer_mut();
+        let (first, column) = buffer.get_cursor_pos();
+        let column = if after && buffer.line_len(first - 1) > 0 { column + 1 } else { column };
+
+        let pieces: Vec<&str> = text.split('\n').collect();
+        let width = pieces.iter().map(|piece| piece.chars().count()).max().unwrap_or(0);
+        for (i, piece) in pieces.iter().enumerate() {
+            let line = first + i;
+            if line > buffer.line_count() {
+                let end = buffer.get_text().len_chars();
+                buffer.replace_range(end..end, "\n");
+            }
+
+            let len = buffer.line_len(line - 1);
+            let padded = format!("{:width$}", piece, width = width);
+            let mut insert = " ".repeat(column.saturating_sub(len));
+            insert.push_str(&padded.repeat(count.saturating_sub(1)));
+            // padding is only needed when there is text after the block.
+            if len > column {
+                insert.push_str(&padded);
+            } else {
+                insert.push_str(piece);
+            }
+
+            let at = buffer.pos_to_char((line, column.min(len)));
+            buffer.replace_range(at..at, &insert);
+        }
+        buffer.set_cursor_pos((first, column));
+    }
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register(
+        "register.select",
+        "use a register for the next yank, delete or put",
+        &[ArgSpec::required("register", ArgKind::Text)],
+        |s, ctx| {
+            if let Some(name) = ctx.arg(0).and_then(|arg| arg.chars().next()) {
+                s.select_register(name, ctx.count);
+            }
+        },
+    );
+    registry.register("register.put_after", "put a register after the cursor", &[], |s, ctx| {
+        s.put_register(ctx.register, ctx.count(), true)
+    });
+    registry.register("register.put_before", "put a register before the cursor", &[], |s, ctx| {
+        s.put_register(ctx.register, ctx.count(), false)
+    });
+}
+
+/// Binds `"{register}`, which picks the register for the next command.
+pub fn insert_register_selection(chain: &mut Chain) {
+    let names = ('a'..='z').chain('A'..='Z').chain('0'..='9').chain("\"-_+*".chars());
+    for name in names {
+        keymaps::bind(chain, &format!("\"{}", name), format!("register.select {}", name));
+    }
+}
+
+/// Binds `"{register}`, `p` and `P` into normal mode's `chain`.
+pub fn insert_registers(chain: &mut Chain) {
+    insert_register_selection(chain);
+    chain.insert('p'.into(), "register.put_after".into());
+    chain.insert('P'.into(), "register.put_before".into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(registers: &mut Registers, name: char) -> Option<Register> {
+        registers.get(name).unwrap()
+    }
+
+    /// The text left after typing `keys` into a buffer holding `text`.
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    #[test]
+    fn yanks_and_deletes() {
+        let mut registers = Registers::new();
+        registers.record_yank(None, Register::charwise("yanked")).unwrap();
+        registers.record_delete(None, Register::charwise("word")).unwrap();
+        registers.record_delete(None, Register::linewise("first")).unwrap();
+        registers.record_delete(None, Register::linewise("second")).unwrap();
+
+        assert_eq!(get(&mut registers, '0'), Some(Register::charwise("yanked")));
+        assert_eq!(get(&mut registers, '-'), Some(Register::charwise("word")));
+        assert_eq!(get(&mut registers, '1'), Some(Register::linewise("second\n")));
+        assert_eq!(get(&mut registers, '2'), Some(Register::linewise("first\n")));
+        assert_eq!(get(&mut registers, '"'), Some(Register::linewise("second\n")));
+        assert_eq!(get(&mut registers, '3'), None);
+    }
+
+    #[test]
+    fn named_registers() {
+        let mut registers = Registers::new();
+        registers.record_yank(Some('a'), Register::charwise("one")).unwrap();
+        registers.record_yank(Some('A'), Register::charwise(" two")).unwrap();
+        assert_eq!(get(&mut registers, 'a'), Some(Register::charwise("one two")));
+        assert_eq!(get(&mut registers, '"'), Some(Register::charwise("one two")));
+        // named yanks leave `0` alone.
+        assert_eq!(get(&mut registers, '0'), None);
+
+        // appending lines makes the register linewise.
+        registers.record_yank(Some('A'), Register::linewise("three")).unwrap();
+        assert_eq!(get(&mut registers, 'a'), Some(Register::linewise("one two\nthree\n")));
+
+        registers.record_yank(Some('B'), Register::charwise("new")).unwrap();
+        assert_eq!(get(&mut registers, 'b'), Some(Register::charwise("new")));
+        assert!(registers.set('0', Register::charwise("x")).is_err());
+    }
+
+    #[test]
+    fn black_hole() {
+        let mut registers = Registers::new();
+        registers.record_yank(None, Register::charwise("kept")).unwrap();
+        registers.record_delete(Some('_'), Register::linewise("gone")).unwrap();
+        assert_eq!(get(&mut registers, '_'), None);
+        assert_eq!(get(&mut registers, '1'), None);
+        assert_eq!(get(&mut registers, '"'), Some(Register::charwise("kept")));
+    }
+
+    #[test]
+    fn clipboard() {
+        let mut registers = Registers::with_clipboard(Box::new(MemoryClipboard::new()));
+        assert_eq!(get(&mut registers, '+'), None);
+
+        registers.record_yank(Some('+'), Register::blockwise("ab\ncd")).unwrap();
+        assert_eq!(get(&mut registers, '*'), Some(Register::blockwise("ab\ncd")));
+        assert_eq!(get(&mut registers, '"'), Some(Register::blockwise("ab\ncd")));
+
+        // text put on the clipboard by another program.
+        registers.clipboard.set_text("line\n").unwrap();
+        assert_eq!(get(&mut registers, '+'), Some(Register::linewise("line\n")));
+        registers.clipboard.set_text("word").unwrap();
+        assert_eq!(get(&mut registers, '+'), Some(Register::charwise("word")));
+    }
+
+    #[test]
+    fn put_charwise() {
+        assert_eq!(after("one two", "yep"), "oonene two");
+        assert_eq!(after("one two", "yeP"), "oneone two");
+        assert_eq!(after("one two", "dwP"), "one two");
+        assert_eq!(after("one two", "dw$p"), "twoone ");
+        assert_eq!(after("ab", "yl3p"), "aaaab");
+    }
+
+    #[test]
+    fn put_linewise() {
+        assert_eq!(after("one\ntwo", "yyp"), "one\none\ntwo");
+        assert_eq!(after("one\ntwo", "jyyP"), "one\ntwo\ntwo");
+        assert_eq!(after("one\ntwo", "jyyp"), "one\ntwo\ntwo");
+        assert_eq!(after("one\ntwo", "ddp"), "two\none");
+        assert_eq!(after("one\ntwo", "yy2P"), "one\none\none\ntwo");
+    }
+
+    #[test]
+    fn put_blockwise() {
+        let mut state = EditorState::with_text("abc\ndef");
+        state.registers.set('a', Register::blockwise("12\n34\n56")).unwrap();
+        state.type_keys("\"ap");
+        assert_eq!(state.text(), "a12bc\nd34ef\n 56");
+
+        let mut state = EditorState::with_text("abc\nd");
+        state.registers.set('a', Register::blockwise("1\n2")).unwrap();
+        state.type_keys("l\"a2P");
+        assert_eq!(state.text(), "a11bc\nd22");
+    }
+
+    #[test]
+    fn put_named_and_clipboard_registers() {
+        assert_eq!(after("one two", "\"ayw\"Ayw\"ap"), "oone one ne two");
+        assert_eq!(after("one\ntwo", "yyj\"_dd\"\"p"), "one\none");
+        assert_eq!(after("one\ntwo", "\"+yyj\"+p"), "one\ntwo\none");
+        assert_eq!(after("one", "\"zp"), "one");
+    }
+}
@@ -0,0 +1,162 @@
+use crate::editor::commands::CommandRegistry;
+use crate::editor::keymaps::{Chain, KeyPress};
+use crate::editor::{EditMode, EditorState};
+
+/// The keys of the last change, for `.` to repeat.
+#[derive(Clone, Debug)]
+pub struct LastChange {
+    /// the count typed before the change.
+    pub count: Option<usize>,
+    /// the keys after the count, up to the end of the change.
+    pub keys: Vec<KeyPress>,
+}
+
+impl LastChange {
+    /// Splits the count off the front of the keys of a change.
+    fn new(keys: &[KeyPress]) -> Self {
+        let digits = keys
+            .iter()
+            .enumerate()
+            .take_while(|(i, key)| match key.plain_char() {
+                Some('0') => *i > 0,
+                Some(c) => c.is_ascii_digit(),
+                None => false,
+            })
+            .count();
+        let count: String = keys[..digits].iter().filter_map(KeyPress::plain_char).collect();
+        Self {
+            count: count.parse().ok(),
+            keys: keys[digits..].to_vec(),
+        }
+    }
+}
+
+impl EditorState {
+    /// True between commands in normal mode, where the keys of a new
+    /// change start.
+    fn at_command_start(&self) -> bool {
+        matches!(self.mode, EditMode::Normal)
+            && self.pending_chain.is_none()
+            && self.pending_count.is_none()
+            && self.pending_register.is_none()
+            && self.pending_substitute.is_none()
+    }
+
+    /// Adds a key to the keys of the command being typed, starting over
+    /// when a new command starts.
+    pub fn capture_change_key(&mut self, key: KeyPress) {
+        if self.at_command_start() {
+            self.change_keys = Some(Vec::new());
+        }
+        if let Some(keys) = &mut self.change_keys {
+            keys.push(key);
+        }
+    }
+
+    /// Marks the command being typed as a change: an operator has been
+    /// applied, or an insert has ended. It becomes the last change once
+    /// the key being processed is done with.
+    pub fn finish_change(&mut self) {
+        self.change_finished = true;
+    }
+
+    /// Keeps the captured keys as the last change, if they made one.
+    pub fn record_change(&mut self) {
+        if !std::mem::take(&mut self.change_finished) {
+            return;
+        }
+        // changes made without keys typed in normal mode can't be repeated.
+        if let Some(keys) = &self.change_keys {
+            self.last_change = Some(LastChange::new(keys));
+        }
+    }
+
+    /// Repeats the last change, with `count` in place of its own count.
+    pub fn repeat_change(&mut self, count: Option<usize>) {
+        let change = match &self.last_change {
+            Some(change) => change,
+            None => {
+                self.command_failed = true;
+                return;
+            }
+        };
+
+        let count = count.or(change.count).map(|count| count.to_string()).unwrap_or_default();
+        let keys = count.chars().map(KeyPress::from).chain(change.keys.iter().copied());
+        let keys: Vec<KeyPress> = keys.collect();
+        for key in keys.into_iter().rev() {
+            self.typeahead.push_front(key);
+        }
+    }
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register("buffer.repeat_change", "repeat the last change", &[], |s, ctx| s.repeat_change(ctx.count));
+}
+
+/// Binds `.`.
+pub fn insert_repeat(chain: &mut Chain) {
+    chain.insert('.'.into(), "buffer.repeat_change".into());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::editor::{EditMode, EditorState};
+
+    fn lines(count: usize) -> String {
+        let lines: Vec<String> = (1..=count).map(|n| n.to_string()).collect();
+        lines.join("\n")
+    }
+
+    #[test]
+    fn repeat_changes() {
+        let mut state = EditorState::with_text("one two three four");
+        state.type_keys("dw.");
+        assert_eq!(state.text(), "three four");
+        state.type_keys("cwx<Esc>w.");
+        assert_eq!(state.text(), "x x");
+        state.type_keys("u");
+        assert_eq!(state.text(), "x four");
+    }
+
+    #[test]
+    fn repeat_with_count() {
+        let mut state = EditorState::with_text(&lines(6));
+        state.type_keys("2dd.");
+        assert_eq!(state.text(), "5\n6");
+
+        let mut state = EditorState::with_text(&lines(6));
+        state.type_keys("dd3.");
+        assert_eq!(state.text(), "5\n6");
+    }
+
+    #[test]
+    fn repeat_change_finished_by_timeout() {
+        let mut state = EditorState::with_text(&lines(5));
+        state.bind(EditMode::OperatorPending, "dx", "buffer.undo").unwrap();
+        state.type_keys("dd");
+        assert!(state.check_key_timeout(Instant::now() + Duration::from_secs(60)));
+        assert_eq!(state.text(), "2\n3\n4\n5");
+
+        // the repeated `dd` waits for the timeout again.
+        state.type_keys("j.");
+        state.check_key_timeout(Instant::now() + Duration::from_secs(60));
+        assert_eq!(state.text(), "2\n4\n5");
+    }
+
+    #[test]
+    fn repeat_change_flushed_by_next_key() {
+        let mut state = EditorState::with_text(&lines(5));
+        state.bind(EditMode::OperatorPending, "dx", "buffer.undo").unwrap();
+        state.type_keys("ddj");
+        assert_eq!(state.text(), "2\n3\n4\n5");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 0));
+
+        state.type_keys(".");
+        state.check_key_timeout(Instant::now() + Duration::from_secs(60));
+        assert_eq!(state.text(), "2\n4\n5");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (2, 0));
+    }
+}
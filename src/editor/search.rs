@@ -0,0 +1,243 @@
+use regex::Regex;
+
+use crate::editor::buffer::search::{self, Direction};
+use crate::editor::commands::CommandRegistry;
+use crate::editor::keymaps::Chain;
+use crate::editor::EditorState;
+
+/// The last pattern searched for, which `n`, `N` and `:s` reuse.
+pub struct LastSearch {
+    pub pattern: String,
+    pub regex: Regex,
+    pub direction: Direction,
+}
+
+/// A search being typed on the command line.
+#[derive(Clone, Copy, Debug)]
+pub struct PendingSearch {
+    pub direction: Direction,
+    /// where the cursor was when the search started, it goes back there if
+    /// the search is cancelled.
+    pub origin: (usize, usize),
+    pub count: Option<usize>,
+}
+
+impl EditorState {
+    /// Compiles a pattern with the case options in effect.
+    pub fn compile_search(&self, pattern: &str) -> Result<Regex, String> {
+        search::compile_pattern(pattern, self.options.ignore_case, self.options.smart_case)
+    }
+
+    /// Remembers `pattern` for `n`, `N` and highlighting.
+    pub fn set_last_search(&mut self, pattern: &str, regex: Regex, direction: Direction) {
+        self.last_search = Some(LastSearch {
+            pattern: pattern.to_string(),
+            regex,
+            direction,
+        });
+        self.search_highlight_hidden = false;
+    }
+
+    /// Opens the command line to type a search pattern, `/` searching
+    /// forward and `?` backward.
+    pub fn start_search(&mut self, direction: Direction, count: Option<usize>) {
+        self.pending_search = Some(PendingSearch {
+            direction,
+            origin: self.active_buffer().get_cursor_pos(),
+            count,
+        });
+        let prompt = match direction {
+            Direction::Forward => '/',
+            Direction::Backward => '?',
+        };
+        self.command_line.open(prompt, "");
+        self.set_command_mode();
+    }
+
+    /// Moves the cursor to the first match of the pattern typed so far, or
+    /// back to where it started if nothing matches.
+    pub fn update_incremental_search(&mut self) {
+        let pending = match self.pending_search {
+            Some(pending) if self.options.incremental_search => pending,
+            _ => return,
+        };
+
+        let found = match self.compile_search(self.command_line.get_text()) {
+            Ok(regex) if !self.command_line.get_text().is_empty() => {
+                let buffer = self.active_buffer();
+                buffer.search(&regex, pending.origin, pending.direction, self.options.wrap_scan)
+            }
+            _ => None,
+        };
+        let buffer = self.active_buffer_mut();
+        let target = match found {
+            Some(range) => buffer.char_to_pos(range.start),
+            None => pending.origin,
+        };
+        buffer.set_cursor_pos(target);
+    }
+
+    pub fn cancel_search(&mut self) {
+        if let Some(pending) = self.pending_search.take() {
+            self.active_buffer_mut().set_cursor_pos(pending.origin);
+        }
+    }
+
+    /// Runs the search typed on the command line. An empty pattern searches
+    /// for the last one again.
+    pub fn finish_search(&mut self, pattern: &str) {
+        let pending = match self.pending_search.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        self.active_buffer_mut().set_cursor_pos(pending.origin);
+
+        if !pattern.is_empty() {
+            match self.compile_search(pattern) {
+                Ok(regex) => self.set_last_search(pattern, regex, pending.direction),
+                Err(e) => {
+                    self.message = e;
+                    self.command_failed = true;
+                    return;
+                }
+            }
+        } else if let Some(last) = &mut self.last_search {
+            last.direction = pending.direction;
+        }
+
+        if !self.search_next(false, pending.count) {
+            self.command_failed = true;
+        }
+    }
+
+    /// Jumps to the `count`th next match of the last search, in the
+    /// direction it was made in, or the other way with `reverse`.
+    pub fn search_next(&mut self, reverse: bool, count: Option<usize>) -> bool {
+        let last = match &self.last_search {
+            Some(last) => last,
+            None => {
+                self.message = String::from("No previous search pattern");
+                return false;
+            }
+        };
+        let direction = if reverse { last.direction.reversed() } else { last.direction };
+
+        let buffer = self.active_buffer();
+        let mut found = Some(buffer.get_cursor_pos());
+        for _ in 0..count.unwrap_or(1) {
+            found = found
+                .and_then(|position| buffer.search(&last.regex, position, direction, self.options.wrap_scan))
+                .map(|range| buffer.char_to_pos(range.start));
+        }
+        let position = match found {
+            Some(position) => position,
+            None => {
+                self.message = format!("Pattern not found: {}", last.pattern);
+                return false;
+            }
+        };
+
+        self.search_highlight_hidden = false;
+        let buffer = self.active_buffer_mut();
+        buffer.set_cursor_pos(position);
+        buffer.set_preferred_column(None);
+        true
+    }
+
+    /// The pattern whose matches are highlighted: the one being typed, or
+    /// else the last search when `hlsearch` is on.
+    pub fn search_highlight(&self) -> Option<Regex> {
+        if self.pending_search.is_some() && self.options.incremental_search {
+            let pattern = self.command_line.get_text();
+            if pattern.is_empty() {
+                return None;
+            }
+            return self.compile_search(pattern).ok();
+        }
+
+        match &self.last_search {
+            Some(last) if self.options.highlight_search && !self.search_highlight_hidden => Some(last.regex.clone()),
+            _ => None,
+        }
+    }
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register("search.forward", "search forward for a pattern", &[], |s, ctx| {
+        s.start_search(Direction::Forward, ctx.count)
+    });
+    registry.register("search.backward", "search backward for a pattern", &[], |s, ctx| {
+        s.start_search(Direction::Backward, ctx.count)
+    });
+    registry.register("search.next", "jump to the next match of the last search", &[], |s, ctx| {
+        if !s.search_next(false, ctx.count) {
+            s.command_failed = true;
+        }
+    });
+    registry.register("search.previous", "jump to the previous match of the last search", &[], |s, ctx| {
+        if !s.search_next(true, ctx.count) {
+            s.command_failed = true;
+        }
+    });
+}
+
+/// Binds `/` and `?`, which start a search, and `n` and `N`, which repeat it.
+pub fn insert_search(chain: &mut Chain) {
+    chain.insert('/'.into(), "search.forward".into());
+    chain.insert('?'.into(), "search.backward".into());
+    insert_search_repeats(chain);
+}
+
+/// Binds `n` and `N` alone, for visual mode where the selection follows them.
+pub fn insert_search_repeats(chain: &mut Chain) {
+    chain.insert('n'.into(), "search.next".into());
+    chain.insert('N'.into(), "search.previous".into());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::EditorState;
+
+    /// Where the cursor is after typing `keys` into a buffer holding `text`.
+    fn cursor_after(text: &str, keys: &str) -> (usize, usize) {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.active_buffer().get_cursor_pos()
+    }
+
+    #[test]
+    fn search_and_repeat() {
+        let text = "one two\ntwo one\none";
+        assert_eq!(cursor_after(text, "/one<CR>"), (2, 4));
+        assert_eq!(cursor_after(text, "/one<CR>n"), (3, 0));
+        assert_eq!(cursor_after(text, "/one<CR>nN"), (2, 4));
+        assert_eq!(cursor_after(text, "2/t.o<CR>"), (2, 0));
+        assert_eq!(cursor_after(text, "G?two<CR>"), (2, 0));
+        assert_eq!(cursor_after(text, "G?two<CR>n"), (1, 4));
+        // an empty pattern searches for the last one.
+        assert_eq!(cursor_after(text, "/two<CR>/<CR>"), (2, 0));
+    }
+
+    #[test]
+    fn search_wraps_around() {
+        assert_eq!(cursor_after("one\ntwo", "j/one<CR>"), (1, 0));
+        assert_eq!(cursor_after("one\ntwo", ":set nowrapscan<CR>j/one<CR>"), (2, 0));
+    }
+
+    #[test]
+    fn failed_search() {
+        let mut state = EditorState::with_text("one two");
+        state.type_keys("w/three<CR>");
+        assert_eq!(state.message, "Pattern not found: three");
+        assert_eq!(state.active_buffer().get_cursor_pos(), (1, 4));
+
+        let mut state = EditorState::with_text("one");
+        state.type_keys("n");
+        assert_eq!(state.message, "No previous search pattern");
+    }
+
+    #[test]
+    fn cancelled_search_goes_back() {
+        assert_eq!(cursor_after("one two two", "w/two<Esc>"), (1, 4));
+    }
+}
@@ -0,0 +1,501 @@
+use regex::Regex;
+
+use crate::editor::buffer::search::{compile_pattern, Direction};
+use crate::editor::commands::CommandRegistry;
+use crate::editor::ex::ExCommand;
+use crate::editor::keymaps::{self, Chain, Key, KeyPress};
+use crate::editor::EditorState;
+
+/// A parsed `:s/pattern/replacement/flags`.
+#[derive(Clone, Debug)]
+pub struct Substitution {
+    pub regex: Regex,
+    /// the pattern as it was typed, to compile again with other case flags.
+    pub pattern: String,
+    /// the replacement, in the `$1` syntax of `Captures::expand`.
+    pub replacement: String,
+    /// replace every match on a line, not just the first.
+    pub global: bool,
+    /// ask before each replacement.
+    pub confirm: bool,
+}
+
+/// A substitution with `c` that is waiting for an answer about the match
+/// under the cursor.
+#[derive(Clone, Debug)]
+pub struct PendingSubstitute {
+    substitution: Substitution,
+    /// where the next match is looked for, (line, column).
+    position: (usize, usize),
+    last_line: usize,
+    replaced: usize,
+    lines_changed: usize,
+    /// the last line a replacement was made on, so each line is only counted once.
+    last_changed: Option<usize>,
+}
+
+/// Splits `text` at the first `delimiter` not escaped by a backslash. An
+/// escaped delimiter loses its backslash, other escapes are kept.
+fn split_delimited(text: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (part, Some(&text[i + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => part.push(next),
+                Some((_, next)) => {
+                    part.push(c);
+                    part.push(next);
+                }
+                None => part.push(c),
+            }
+        } else {
+            part.push(c);
+        }
+    }
+    (part, None)
+}
+
+/// The flags written after a substitution, ie. `gc`.
+struct Flags {
+    global: bool,
+    confirm: bool,
+    /// `ignore_case` and `smart_case`, if `i` or `I` overrode the options.
+    case: Option<(bool, bool)>,
+}
+
+impl Flags {
+    /// Reads `flags` on top of the `global` and `confirm` already in effect.
+    /// `g` turns global off again if it was on.
+    fn parse(flags: &str, global: bool, confirm: bool) -> Result<Self, String> {
+        let mut parsed = Self { global, confirm, case: None };
+        for flag in flags.trim().chars() {
+            match flag {
+                'g' => parsed.global = !parsed.global,
+                'c' => parsed.confirm = true,
+                'i' => parsed.case = Some((true, false)),
+                'I' => parsed.case = Some((false, false)),
+                _ => return Err(format!("Trailing characters: {}", flags)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Translates a vim style replacement, where `&` and `\0` are the whole
+/// match and `\1` to `\9` are groups, to the syntax `Captures::expand` takes.
+fn translate_replacement(replacement: &str) -> String {
+    let mut translated = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => translated.push_str("${0}"),
+            '$' => translated.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => translated.push_str(&format!("${{{}}}", digit)),
+                Some('n') | Some('r') => translated.push('\n'),
+                Some('t') => translated.push('\t'),
+                Some(other) => translated.push(other),
+                None => translated.push('\\'),
+            },
+            c => translated.push(c),
+        }
+    }
+    translated
+}
+
+impl EditorState {
+    /// Parses the arguments of `:s`. An empty pattern uses the last search.
+    /// Arguments which are only flags repeat the last substitution with
+    /// those flags, and a leading `&` keeps its flags as well, ie. `:&&`.
+    fn parse_substitution(&mut self, args: &str) -> Result<Substitution, String> {
+        if let Some(flags) = args.strip_prefix('&') {
+            return self.repeat_substitution(flags, true);
+        }
+        if args.chars().all(|c| c.is_whitespace() || "gciI".contains(c)) {
+            return self.repeat_substitution(args, false);
+        }
+
+        let mut chars = args.chars();
+        let delimiter = match chars.next() {
+            Some(c) if c.is_alphanumeric() || c.is_whitespace() || c == '\\' || c == '"' || c == '|' => {
+                return Err(String::from("Regular expression can't be delimited by letters"));
+            }
+            Some(c) => c,
+            None => unreachable!("no arguments repeat the last substitution"),
+        };
+
+        let (pattern, rest) = split_delimited(chars.as_str(), delimiter);
+        let (replacement, flags) = match rest {
+            Some(rest) => {
+                let (replacement, flags) = split_delimited(rest, delimiter);
+                (replacement, flags.unwrap_or(""))
+            }
+            None => (String::new(), ""),
+        };
+        let flags = Flags::parse(flags, false, false)?;
+        let (ignore_case, smart_case) = flags.case.unwrap_or((self.options.ignore_case, self.options.smart_case));
+
+        let (pattern, regex) = if pattern.is_empty() {
+            match &self.last_search {
+                Some(last) if flags.case.is_some() => {
+                    (last.pattern.clone(), compile_pattern(&last.pattern, ignore_case, smart_case)?)
+                }
+                Some(last) => (last.pattern.clone(), last.regex.clone()),
+                None => return Err(String::from("No previous regular expression")),
+            }
+        } else {
+            let regex = compile_pattern(&pattern, ignore_case, smart_case)?;
+            let direction = self.last_search.as_ref().map_or(Direction::Forward, |last| last.direction);
+            self.set_last_search(&pattern, regex.clone(), direction);
+            (pattern, regex)
+        };
+
+        let substitution = Substitution {
+            regex,
+            pattern,
+            replacement: translate_replacement(&replacement),
+            global: flags.global,
+            confirm: flags.confirm,
+        };
+        self.last_substitution = Some(substitution.clone());
+        Ok(substitution)
+    }
+
+    /// The last substitution again with `flags`, on top of the flags it was
+    /// made with if `keep_flags` is set.
+    fn repeat_substitution(&self, flags: &str, keep_flags: bool) -> Result<Substitution, String> {
+        let last = match &self.last_substitution {
+            Some(last) => last,
+            None => return Err(String::from("No previous substitute regular expression")),
+        };
+        let flags = match keep_flags {
+            true => Flags::parse(flags, last.global, last.confirm)?,
+            false => Flags::parse(flags, false, false)?,
+        };
+        let regex = match flags.case {
+            Some((ignore_case, smart_case)) => compile_pattern(&last.pattern, ignore_case, smart_case)?,
+            None => last.regex.clone(),
+        };
+        Ok(Substitution {
+            regex,
+            global: flags.global,
+            confirm: flags.confirm,
+            ..last.clone()
+        })
+    }
+
+    /// Starts substituting over lines `first..=last`. Without confirmation
+    /// every match is replaced straight away, all as one undoable change.
+    pub fn substitute(&mut self, substitution: Substitution, (first, last): (usize, usize)) -> Result<(), String> {
+        let confirm = substitution.confirm;
+        self.active_buffer_mut().begin_change();
+        self.pending_substitute = Some(PendingSubstitute {
+            substitution,
+            position: (first, 0),
+            last_line: last,
+            replaced: 0,
+            lines_changed: 0,
+            last_changed: None,
+        });
+
+        if confirm {
+            if !self.next_substitute_match() {
+                return Err(String::from("Pattern not found"));
+            }
+        } else {
+            while self.next_substitute_match() {
+                self.replace_substitute_match();
+            }
+            let replaced = self.pending_substitute.as_ref().map_or(0, |pending| pending.replaced);
+            self.finish_substitute();
+            if replaced == 0 {
+                return Err(String::from("Pattern not found"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the next match at or after the pending position, and puts the
+    /// cursor on it. Ends the substitution if there are no matches left.
+    fn next_substitute_match(&mut self) -> bool {
+        let pending = match &mut self.pending_substitute {
+            Some(pending) => pending,
+            None => return false,
+        };
+        let buffer = self.buffers.active();
+
+        let (mut line, mut column) = pending.position;
+        while line <= pending.last_line.min(buffer.line_count()) {
+            let text = buffer.line_text(line - 1);
+            if let Some(start) = text.char_indices().map(|(i, _)| i).chain([text.len()]).nth(column) {
+                if let Some(found) = pending.substitution.regex.find_at(&text, start) {
+                    let start = column + text[start..found.start()].chars().count();
+                    pending.position = (line, start);
+                    self.buffers.active_mut().set_cursor_pos((line, start));
+                    if pending.substitution.confirm {
+                        self.message = self.substitute_prompt();
+                    }
+                    return true;
+                }
+            }
+            line += 1;
+            column = 0;
+        }
+
+        if pending.substitution.confirm {
+            self.finish_substitute();
+        }
+        false
+    }
+
+    fn substitute_prompt(&self) -> String {
+        match &self.pending_substitute {
+            Some(pending) => format!("replace with {} (y/n/a/q/l)?", pending.substitution.replacement),
+            None => String::new(),
+        }
+    }
+
+    /// Replaces the match at the pending position, and moves past it.
+    fn replace_substitute_match(&mut self) {
+        let pending = match &mut self.pending_substitute {
+            Some(pending) => pending,
+            None => return,
+        };
+        let buffer = self.buffers.active_mut();
+        let (line, column) = pending.position;
+
+        let text = buffer.line_text(line - 1);
+        let start_byte = text.char_indices().nth(column).map_or(text.len(), |(i, _)| i);
+        let captures = match pending.substitution.regex.captures_at(&text, start_byte) {
+            Some(captures) => captures,
+            None => return,
+        };
+        let whole = captures.get(0).expect("a match always has group 0");
+        let mut replacement = String::new();
+        captures.expand(&pending.substitution.replacement, &mut replacement);
+
+        let start = buffer.pos_to_char((line, column));
+        let end = start + whole.as_str().chars().count();
+        buffer.replace_range(start..end, &replacement);
+
+        pending.replaced += 1;
+        if pending.last_changed != Some(line) {
+            pending.lines_changed += 1;
+        }
+
+        // carry on after the replacement, which may have split the line.
+        let added_lines = replacement.matches('\n').count();
+        let end_line = line + added_lines;
+        let end_column = match replacement.rfind('\n') {
+            Some(i) => replacement[i + 1..].chars().count(),
+            None => column + replacement.chars().count(),
+        };
+        pending.last_line += added_lines;
+        pending.last_changed = Some(end_line);
+        pending.position = Self::after_match(pending, end_line, end_column, whole.as_str().is_empty());
+    }
+
+    /// Leaves the match at the pending position alone, and moves past it.
+    fn skip_substitute_match(&mut self) {
+        let pending = match &mut self.pending_substitute {
+            Some(pending) => pending,
+            None => return,
+        };
+        let (line, column) = pending.position;
+        let text = self.buffers.active().line_text(line - 1);
+        let start_byte = text.char_indices().nth(column).map_or(text.len(), |(i, _)| i);
+        let length = pending
+            .substitution
+            .regex
+            .find_at(&text, start_byte)
+            .map_or(0, |found| found.as_str().chars().count());
+        pending.position = Self::after_match(pending, line, column + length, length == 0);
+    }
+
+    /// Where to look for the next match after one ending at (line, column).
+    /// Without `g` that is the next line. After an empty match the next char
+    /// is stepped over, so the same spot doesn't match again.
+    fn after_match(pending: &PendingSubstitute, line: usize, column: usize, empty: bool) -> (usize, usize) {
+        if !pending.substitution.global {
+            (line + 1, 0)
+        } else if empty {
+            (line, column + 1)
+        } else {
+            (line, column)
+        }
+    }
+
+    /// Closes the undo group and reports what was done.
+    fn finish_substitute(&mut self) {
+        let pending = match self.pending_substitute.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        self.active_buffer_mut().end_change();
+        if let Some(line) = pending.last_changed {
+            self.goto_line(line);
+        }
+
+        if pending.replaced > 1 {
+            let lines = if pending.lines_changed == 1 { "line" } else { "lines" };
+            self.message = format!("{} substitutions on {} {}", pending.replaced, pending.lines_changed, lines);
+        }
+    }
+
+    /// Answers the question asked for a `c` substitution: `y`es, `n`o,
+    /// `a`ll remaining, `q`uit, or `l`ast (replace this one and stop).
+    pub fn process_substitute_key(&mut self, key: KeyPress) {
+        if key.key == Key::Escape {
+            self.finish_substitute();
+            return;
+        }
+
+        match key.plain_char().unwrap_or('\0') {
+            'y' => {
+                self.replace_substitute_match();
+                self.next_substitute_match();
+            }
+            'n' => {
+                self.skip_substitute_match();
+                self.next_substitute_match();
+            }
+            'a' => {
+                if let Some(pending) = &mut self.pending_substitute {
+                    pending.substitution.confirm = false;
+                }
+                self.replace_substitute_match();
+                while self.next_substitute_match() {
+                    self.replace_substitute_match();
+                }
+                self.finish_substitute();
+            }
+            'l' => {
+                self.replace_substitute_match();
+                self.finish_substitute();
+            }
+            'q' => self.finish_substitute(),
+            _ => self.message = self.substitute_prompt(),
+        }
+    }
+}
+
+/// `:[range]s/pattern/replacement/[flags]`, by default on the cursor line.
+pub fn ex_substitute(s: &mut EditorState, command: &ExCommand, range: Option<(usize, usize)>) -> Result<(), String> {
+    let substitution = s.parse_substitution(&command.args)?;
+    let current = s.active_buffer().get_cursor_pos().0;
+    s.substitute(substitution, range.unwrap_or((current, current)))
+}
+
+/// `&` repeats the last substitution on the cursor line, without its
+/// flags, and `g&` repeats it with its flags on every line.
+fn repeat_substitute(s: &mut EditorState, keep_flags: bool, lines: Option<(usize, usize)>) {
+    let current = s.active_buffer().get_cursor_pos().0;
+    let result = s
+        .repeat_substitution("", keep_flags)
+        .and_then(|substitution| s.substitute(substitution, lines.unwrap_or((current, current))));
+    if let Err(e) = result {
+        s.message = e;
+        s.command_failed = true;
+    }
+}
+
+pub fn register_commands(registry: &mut CommandRegistry) {
+    registry.register("substitute.repeat", "repeat the last substitution on the line", &[], |s, _| {
+        repeat_substitute(s, false, None)
+    });
+    registry.register("substitute.repeat_all", "repeat the last substitution on every line", &[], |s, _| {
+        let lines = s.active_buffer().line_count();
+        repeat_substitute(s, true, Some((1, lines)))
+    });
+}
+
+/// Binds `&` and `g&`.
+pub fn insert_substitute_repeats(chain: &mut Chain) {
+    chain.insert('&'.into(), "substitute.repeat".into());
+    keymaps::bind(chain, "g&", "substitute.repeat_all");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::EditorState;
+
+    /// The text after typing `keys` into a buffer holding `text`.
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    #[test]
+    fn substitute_lines() {
+        assert_eq!(after("foo foo\nfoo", ":s/foo/bar/<CR>"), "bar foo\nfoo");
+        assert_eq!(after("foo foo\nfoo", ":%s/foo/bar/g<CR>"), "bar bar\nbar");
+        assert_eq!(after("a1 b22", r":s/(\w)(\d+)/\2\1/g<CR>"), "1a 22b");
+        assert_eq!(after("a-b", ":s/-/[&]/<CR>"), "a[-]b");
+        assert_eq!(after("Foo foo", ":s/foo/x/gi<CR>"), "x x");
+        assert_eq!(after("a,b", ":s#,#/#<CR>"), "a/b");
+    }
+
+    #[test]
+    fn substitute_uses_the_last_search() {
+        assert_eq!(after("one two", "/two<CR>:s//2/<CR>"), "one 2");
+    }
+
+    #[test]
+    fn substitution_is_one_undo_step() {
+        assert_eq!(after("foo\nfoo\nfoo", ":%s/foo/bar/<CR>u"), "foo\nfoo\nfoo");
+    }
+
+    #[test]
+    fn repeat_without_flags() {
+        assert_eq!(after("foo foo\nfoo foo", ":s/foo/bar/g<CR>j:s<CR>"), "bar bar\nbar foo");
+        assert_eq!(after("foo foo\nfoo foo", ":s/foo/bar/g<CR>j:&<CR>"), "bar bar\nbar foo");
+        assert_eq!(after("foo foo\nfoo foo", ":s/foo/bar/g<CR>j&"), "bar bar\nbar foo");
+    }
+
+    #[test]
+    fn repeat_with_flags() {
+        assert_eq!(after("foo foo\nfoo foo", ":s/foo/bar/g<CR>j:&&<CR>"), "bar bar\nbar bar");
+        assert_eq!(after("foo foo\nfoo foo", ":s/foo/bar/<CR>j:&g<CR>"), "bar foo\nbar bar");
+        assert_eq!(after("foo foo\nfoo foo", ":s/foo/bar/<CR>j:s g<CR>"), "bar foo\nbar bar");
+        assert_eq!(after("foo\nFOO", ":s/foo/bar/<CR>j:&i<CR>"), "bar\nbar");
+        assert_eq!(after("foo foo\nfoo foo\nfoo", ":s/foo/bar/g<CR>g&"), "bar bar\nbar bar\nbar");
+    }
+
+    #[test]
+    fn repeat_needs_a_substitution() {
+        let mut state = EditorState::with_text("foo");
+        state.type_keys(":&&<CR>");
+        assert_eq!(state.message, "No previous substitute regular expression");
+        state.type_keys("&");
+        assert!(state.command_failed);
+        assert_eq!(state.text(), "foo");
+    }
+
+    #[test]
+    fn confirm_each_match() {
+        assert_eq!(after("a a a\na", ":%s/a/b/gc<CR>ynyy"), "b a b\nb");
+        assert_eq!(after("a a a\na", ":%s/a/b/gc<CR>na"), "a b b\nb");
+        assert_eq!(after("a a a\na", ":%s/a/b/gc<CR>nl"), "a b a\na");
+        assert_eq!(after("a a a\na", ":%s/a/b/gc<CR>yq"), "b a a\na");
+        assert_eq!(after("a a a\na", ":%s/a/b/gc<CR>y<Esc>"), "b a a\na");
+        // the answers are one change.
+        assert_eq!(after("a a a\na", ":%s/a/b/gc<CR>yya<Esc>u"), "a a a\na");
+    }
+
+    #[test]
+    fn confirm_with_repeat() {
+        let mut state = EditorState::with_text("a a\na a");
+        state.type_keys(":s/a/b/gc<CR>yy");
+        assert_eq!(state.text(), "b b\na a");
+        state.type_keys("j:&&<CR>n");
+        assert_eq!(state.message, "replace with b (y/n/a/q/l)?");
+        state.type_keys("y");
+        assert_eq!(state.text(), "b b\na b");
+        assert!(state.pending_substitute.is_none());
+    }
+}
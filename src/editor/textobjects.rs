@@ -0,0 +1,600 @@
+use std::cmp::Reverse;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use ropey::Rope;
+
+use crate::editor::buffer::Buffer;
+use crate::editor::commands::CommandRegistry;
+use crate::editor::keymaps::{Chain, ChainLink};
+use crate::editor::motions::{char_class, CharClass};
+
+/// A region of text picked out around the cursor. `around` objects include
+/// the delimiters (or surrounding whitespace), inner objects don't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextObject {
+    /// a word, or a run of whitespace. Big words are anything but whitespace.
+    Word { big: bool, around: bool },
+    /// text ending in `.`, `!` or `?` and whitespace, or at the end of a paragraph.
+    Sentence { around: bool },
+    /// lines up to the next blank line, or a run of blank lines.
+    Paragraph { around: bool },
+    /// a quoted string on the cursor's line.
+    Quote { quote: char, around: bool },
+    Bracket { open: char, close: char, around: bool },
+    /// the text between an XML or HTML tag and its closing tag.
+    Tag { around: bool },
+}
+
+/// True if the character at `i` comes after an odd number of backslashes.
+fn is_escaped(text: &Rope, i: usize) -> bool {
+    let backslashes = (0..i).rev().take_while(|j| text.char(*j) == '\\').count();
+    backslashes % 2 == 1
+}
+
+/// True if `i` is the newline of an empty line.
+fn is_blank_line(text: &Rope, i: usize) -> bool {
+    text.char(i) == '\n' && (i == 0 || text.char(i - 1) == '\n')
+}
+
+/// The class of the character at `i` for word objects. Newlines are a class
+/// of their own, so neither words nor whitespace run on to the next line.
+fn word_class(text: &Rope, i: usize, big_word: bool) -> Option<CharClass> {
+    match text.char(i) {
+        '\n' => None,
+        c => Some(char_class(c, big_word)),
+    }
+}
+
+/// The end of the run of characters of the same class as the one at `i`.
+fn run_end(text: &Rope, i: usize, big_word: bool) -> usize {
+    let class = word_class(text, i, big_word);
+    let mut end = i + 1;
+    if class.is_some() {
+        while end < text.len_chars() && word_class(text, end, big_word) == class {
+            end += 1;
+        }
+    }
+    end
+}
+
+fn run_start(text: &Rope, i: usize, big_word: bool) -> usize {
+    let class = word_class(text, i, big_word);
+    let mut start = i;
+    if class.is_some() {
+        while start > 0 && word_class(text, start - 1, big_word) == class {
+            start -= 1;
+        }
+    }
+    start
+}
+
+/// `count` words from the cursor, counting runs of whitespace as words. An
+/// `around` word takes the whitespace after it, or before it if there is
+/// none after; on whitespace, it takes the word after it.
+fn word_range(buffer: &Buffer, cursor: usize, count: usize, big_word: bool, around: bool) -> Option<Range<usize>> {
+    let text = buffer.get_text();
+    let len = text.len_chars();
+    if cursor >= len || word_class(text, cursor, big_word).is_none() {
+        return None;
+    }
+
+    let on_space = word_class(text, cursor, big_word) == Some(CharClass::Whitespace);
+    let mut start = run_start(text, cursor, big_word);
+    let mut end = cursor;
+    let mut trailing_space = false;
+    for _ in 0..count {
+        if end >= len {
+            return None;
+        }
+        let space = word_class(text, end, big_word) == Some(CharClass::Whitespace);
+        end = run_end(text, end, big_word);
+        if around && end < len {
+            let next = word_class(text, end, big_word);
+            let next_space = next == Some(CharClass::Whitespace);
+            if next.is_some() && space != next_space {
+                end = run_end(text, end, big_word);
+                trailing_space = next_space;
+            }
+        }
+    }
+
+    if around && !on_space && !trailing_space {
+        while start > 0 && word_class(text, start - 1, big_word) == Some(CharClass::Whitespace) {
+            start -= 1;
+        }
+    }
+    Some(start..end)
+}
+
+/// Skips spaces and line breaks, but not blank lines, which end a paragraph.
+fn skip_space(text: &Rope, mut i: usize) -> usize {
+    let len = text.len_chars();
+    while i < len {
+        match text.char(i) {
+            ' ' | '\t' => i += 1,
+            '\n' if i + 1 < len && text.char(i + 1) != '\n' && !is_blank_line(text, i) => i += 1,
+            _ => break,
+        }
+    }
+    i
+}
+
+/// The end of the sentence starting at `start`: just after its `.`, `!` or
+/// `?` and any closing brackets and quotes, or the end of its paragraph.
+fn sentence_end(text: &Rope, start: usize) -> usize {
+    let len = text.len_chars();
+    let mut end = len;
+    for i in start..len {
+        let c = text.char(i);
+        if matches!(c, '.' | '!' | '?') {
+            let mut j = i + 1;
+            while j < len && matches!(text.char(j), ')' | ']' | '"' | '\'') {
+                j += 1;
+            }
+            if j == len || text.char(j).is_whitespace() {
+                return j;
+            }
+        } else if c == '\n' && (i + 1 == len || text.char(i + 1) == '\n') {
+            end = i;
+            break;
+        }
+    }
+    while end > start && text.char(end - 1).is_whitespace() {
+        end -= 1;
+    }
+    end
+}
+
+/// The first character of the paragraph `i` is in.
+fn paragraph_start(text: &Rope, mut i: usize) -> usize {
+    while i > 0 && !is_blank_line(text, i - 1) {
+        i -= 1;
+    }
+    i
+}
+
+/// Splits a paragraph into sentences and the whitespace between them.
+/// Each piece is a range, and whether it is whitespace.
+fn sentence_pieces(text: &Rope, from: usize) -> Vec<(Range<usize>, bool)> {
+    let len = text.len_chars();
+    let mut pieces = Vec::new();
+    let mut i = from;
+    loop {
+        let start = skip_space(text, i);
+        if start > i {
+            pieces.push((i..start, true));
+        }
+        if start >= len || is_blank_line(text, start) {
+            break;
+        }
+        let end = sentence_end(text, start);
+        if end == start {
+            break;
+        }
+        pieces.push((start..end, false));
+        i = end;
+    }
+    pieces
+}
+
+/// `count` sentences from the cursor. Inner sentences count the whitespace
+/// between them as sentences too; `around` sentences take the whitespace
+/// after them, or before them if there is none after.
+fn sentence_range(buffer: &Buffer, cursor: usize, count: usize, around: bool) -> Option<Range<usize>> {
+    let text = buffer.get_text();
+    if cursor >= text.len_chars() || is_blank_line(text, cursor) {
+        return None;
+    }
+
+    let pieces = sentence_pieces(text, paragraph_start(text, cursor));
+    let index = pieces.iter().position(|(range, _)| range.contains(&cursor))?;
+    let (mut start, on_space) = (pieces[index].0.start, pieces[index].1);
+    if !around {
+        return Some(start..pieces.get(index + count - 1)?.0.end);
+    }
+
+    let mut i = index;
+    let mut sentences = 0;
+    let mut end = start;
+    while sentences < count {
+        let (range, space) = pieces.get(i)?;
+        end = range.end;
+        if !space {
+            sentences += 1;
+        }
+        i += 1;
+    }
+    if !on_space {
+        match pieces.get(i) {
+            Some((range, true)) => end = range.end,
+            _ if index > 0 && pieces[index - 1].1 => start = pieces[index - 1].0.start,
+            _ => {}
+        }
+    }
+    Some(start..end)
+}
+
+/// `count` paragraphs from the cursor's line, counting runs of blank lines
+/// as paragraphs. An `around` paragraph takes the blank lines after it, or
+/// before it if there are none after.
+fn paragraph_range(buffer: &Buffer, line: usize, count: usize, around: bool) -> Option<Range<usize>> {
+    let lines = buffer.line_count();
+    let is_blank = |line: usize| buffer.line_text(line - 1).trim().is_empty();
+    // the last line of the run of blank or non-blank lines starting at `line`.
+    let run_end = |line: usize| {
+        let blank = is_blank(line);
+        let mut last = line;
+        while last < lines && is_blank(last + 1) == blank {
+            last += 1;
+        }
+        last
+    };
+
+    let blank = is_blank(line);
+    let mut first = line;
+    while first > 1 && is_blank(first - 1) == blank {
+        first -= 1;
+    }
+
+    let runs = if around { count * 2 } else { count };
+    let mut last = run_end(line);
+    let mut taken = 1;
+    while taken < runs && last < lines {
+        last = run_end(last + 1);
+        taken += 1;
+    }
+    if taken < runs {
+        if !around || taken + 1 < runs {
+            return None;
+        }
+        if !blank {
+            while first > 1 && is_blank(first - 1) {
+                first -= 1;
+            }
+        }
+    }
+
+    let text = buffer.get_text();
+    let end = if last < text.len_lines() {
+        text.line_to_char(last)
+    } else {
+        text.len_chars()
+    };
+    Some(text.line_to_char(first - 1)..end)
+}
+
+/// The quoted string on the cursor's line which the cursor is in, or else
+/// the next one after it. Quotes pair up from the start of the line, and
+/// escaped quotes are skipped. A count over one takes the quotes too.
+fn quote_range(buffer: &Buffer, cursor: usize, quote: char, count: usize, around: bool) -> Option<Range<usize>> {
+    let text = buffer.get_text();
+    let line = buffer.char_to_pos(cursor).0;
+    let line_start = text.line_to_char(line - 1);
+    let line_end = line_start + buffer.line_len(line - 1);
+
+    let quotes: Vec<usize> = (line_start..line_end)
+        .filter(|i| text.char(*i) == quote && !is_escaped(text, *i))
+        .collect();
+    let before = quotes.iter().filter(|i| **i < cursor).count();
+    let pair = before - before % 2;
+    let (open, close) = (*quotes.get(pair)?, *quotes.get(pair + 1)?);
+
+    if !around {
+        return Some(if count > 1 { open..close + 1 } else { open + 1..close });
+    }
+    let is_space = |i: usize| matches!(text.char(i), ' ' | '\t');
+    let mut start = open;
+    let mut end = close + 1;
+    while end < line_end && is_space(end) {
+        end += 1;
+    }
+    if end == close + 1 {
+        while start > line_start && is_space(start - 1) {
+            start -= 1;
+        }
+    }
+    Some(start..end)
+}
+
+/// Scans backwards from just before `before` for an `open` bracket that
+/// isn't closed again before `before`.
+fn find_open(buffer: &Buffer, before: usize, open: char, close: char) -> Option<usize> {
+    let text = buffer.get_text();
+    let mut depth = 0;
+    let mut i = before;
+    while i > 0 {
+        i -= 1;
+        let c = text.char(i);
+        if (c != open && c != close) || is_escaped(text, i) {
+            continue;
+        }
+        if c == close {
+            depth += 1;
+        } else if depth == 0 {
+            return Some(i);
+        } else {
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Finds the `open`/`close` pair enclosing `from`, skipping over nested pairs
+/// and escaped brackets. `count` picks the nth enclosing pair. Returns the
+/// indices of both brackets.
+fn find_bracket_pair(buffer: &Buffer, from: usize, open: char, close: char, count: usize) -> Option<(usize, usize)> {
+    let text = buffer.get_text();
+    let len = text.len_chars();
+
+    let mut open_index = if from < len && text.char(from) == open && !is_escaped(text, from) {
+        from
+    } else {
+        find_open(buffer, from, open, close)?
+    };
+    for _ in 1..count {
+        open_index = find_open(buffer, open_index, open, close)?;
+    }
+
+    let mut depth = 0;
+    for i in open_index + 1..len {
+        let c = text.char(i);
+        if (c != open && c != close) || is_escaped(text, i) {
+            continue;
+        }
+        if c == open {
+            depth += 1;
+        } else if depth == 0 {
+            return Some((open_index, i));
+        } else {
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// The inside of a bracket pair. Like vim, when the brackets are on lines of
+/// their own the inside is just the lines between them.
+fn inner_bracket_range(buffer: &Buffer, open: usize, close: usize) -> Range<usize> {
+    let text = buffer.get_text();
+    let mut start = open + 1;
+    let mut end = close;
+    if start < end && text.char(start) == '\n' {
+        start += 1;
+        let indent = (start..end).rev().take_while(|i| matches!(text.char(*i), ' ' | '\t')).count();
+        if end - indent > start && text.char(end - indent - 1) == '\n' {
+            end -= indent;
+        }
+    }
+    start..end.max(start)
+}
+
+/// How many lines either side of the cursor `it` and `at` look for tags in.
+const TAG_SEARCH_LINES: usize = 1000;
+
+/// An opening or closing tag, with its slash and name captured.
+fn tag_regex() -> &'static Regex {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    TAG.get_or_init(|| Regex::new(r"<(/?)([A-Za-z][^\s/>]*)[^>]*>").unwrap())
+}
+
+/// The `count`th tag pair enclosing the cursor, from the inside out. Tags
+/// are matched up by name, and self closing tags are skipped. Only tags
+/// within `TAG_SEARCH_LINES` of the cursor are looked at.
+fn tag_range(buffer: &Buffer, cursor: usize, count: usize, around: bool) -> Option<Range<usize>> {
+    let rope = buffer.get_text();
+    let line = rope.char_to_line(cursor);
+    let first_line = line.saturating_sub(TAG_SEARCH_LINES);
+    let last_line = (line + TAG_SEARCH_LINES + 1).min(rope.len_lines());
+    let offset = rope.line_to_char(first_line);
+    let window = rope.slice(offset..rope.line_to_char(last_line));
+    let text = window.to_string();
+
+    // open tags waiting for their closing tag: name, start and end.
+    let mut open_tags: Vec<(&str, usize, usize)> = Vec::new();
+    let mut pairs = Vec::new();
+    for captures in tag_regex().captures_iter(&text) {
+        let whole = captures.get(0).unwrap();
+        if whole.as_str().ends_with("/>") {
+            continue;
+        }
+        let start = offset + window.byte_to_char(whole.start());
+        let end = offset + window.byte_to_char(whole.end());
+        let name = captures.get(2).unwrap().as_str();
+        if captures[1].is_empty() {
+            open_tags.push((name, start, end));
+        } else if let Some(index) = open_tags.iter().rposition(|(open_name, _, _)| *open_name == name) {
+            let (_, open_start, open_end) = open_tags[index];
+            open_tags.truncate(index);
+            pairs.push((open_start, open_end, start, end));
+        }
+    }
+
+    let mut enclosing: Vec<_> = pairs
+        .into_iter()
+        .filter(|(open_start, _, _, close_end)| *open_start <= cursor && cursor < *close_end)
+        .collect();
+    enclosing.sort_by_key(|(open_start, _, _, _)| Reverse(*open_start));
+    let (open_start, open_end, close_start, close_end) = *enclosing.get(count - 1)?;
+    Some(if around { open_start..close_end } else { open_end..close_start })
+}
+
+impl TextObject {
+    /// The range of characters the object covers with the cursor at `from`.
+    pub fn range(&self, buffer: &Buffer, from: (usize, usize), count: usize) -> Option<Range<usize>> {
+        let cursor = buffer.pos_to_char(from);
+        match *self {
+            Self::Word { big, around } => word_range(buffer, cursor, count, big, around),
+            Self::Sentence { around } => sentence_range(buffer, cursor, count, around),
+            Self::Paragraph { around } => paragraph_range(buffer, from.0, count, around),
+            Self::Quote { quote, around } => quote_range(buffer, cursor, quote, count, around),
+            Self::Bracket { open, close, around } => {
+                let (open_index, close_index) = find_bracket_pair(buffer, cursor, open, close, count)?;
+                if around {
+                    Some(open_index..close_index + 1)
+                } else {
+                    Some(inner_bracket_range(buffer, open_index, close_index))
+                }
+            }
+            Self::Tag { around } => tag_range(buffer, cursor, count, around),
+        }
+    }
+
+    /// Linewise objects make operators act on whole lines.
+    pub fn is_linewise(&self) -> bool {
+        matches!(self, Self::Paragraph { .. })
+    }
+
+    /// What the object's commands are listed as doing.
+    fn description(&self) -> String {
+        let (object, around) = match *self {
+            Self::Word { big: false, around } => (String::from("word"), around),
+            Self::Word { big: true, around } => (String::from("WORD"), around),
+            Self::Sentence { around } => (String::from("sentence"), around),
+            Self::Paragraph { around } => (String::from("paragraph"), around),
+            Self::Quote { quote, around } => (format!("{}{} string", quote, quote), around),
+            Self::Bracket { open, close, around } => (format!("{}{} block", open, close), around),
+            Self::Tag { around } => (String::from("tag block"), around),
+        };
+        if around {
+            format!("select the {} around the cursor, with its delimiters or whitespace", object)
+        } else {
+            format!("select the inside of the {} around the cursor", object)
+        }
+    }
+}
+
+/// The bracket pairs, with the name of their text objects and the keys
+/// they are bound to after `i` or `a`.
+const BRACKETS: [(char, char, &str, &[char]); 4] = [
+    ('(', ')', "paren", &['(', ')', 'b']),
+    ('[', ']', "bracket", &['[', ']']),
+    ('{', '}', "brace", &['{', '}', 'B']),
+    ('<', '>', "angle", &['<', '>']),
+];
+
+/// The quotes, with the name of their text objects.
+const QUOTES: [(char, &str); 3] = [('"', "double_quote"), ('\'', "single_quote"), ('`', "backtick")];
+
+/// Every text object, with the name of its commands and the keys it is
+/// bound to after `i` or `a`.
+fn text_objects(around: bool) -> Vec<(&'static str, Vec<char>, TextObject)> {
+    let mut objects = vec![
+        ("word", vec!['w'], TextObject::Word { big: false, around }),
+        ("big_word", vec!['W'], TextObject::Word { big: true, around }),
+        ("sentence", vec!['s'], TextObject::Sentence { around }),
+        ("paragraph", vec!['p'], TextObject::Paragraph { around }),
+        ("tag", vec!['t'], TextObject::Tag { around }),
+    ];
+    for (quote, name) in QUOTES {
+        objects.push((name, vec![quote], TextObject::Quote { quote, around }));
+    }
+    for (open, close, name, keys) in BRACKETS {
+        objects.push((name, keys.to_vec(), TextObject::Bracket { open, close, around }));
+    }
+    objects
+}
+
+/// Registers `textobject.inner_*` and `textobject.around_*` commands for
+/// every text object.
+pub fn register_commands(registry: &mut CommandRegistry) {
+    for around in [false, true] {
+        for (name, _, object) in text_objects(around) {
+            registry.register(text_object_command(name, around), object.description(), &[], move |s, ctx| {
+                if !s.run_text_object(object, ctx.count) {
+                    s.command_failed = true;
+                }
+            });
+        }
+    }
+}
+
+/// Binds the text objects under `i` and `a` in `chain`.
+pub fn insert_text_objects(chain: &mut Chain) {
+    for (around, prefix) in [(false, 'i'), (true, 'a')] {
+        let mut object_chain = Chain::new();
+        for (name, keys, _) in text_objects(around) {
+            for key in keys {
+                object_chain.insert(key.into(), text_object_command(name, around).into());
+            }
+        }
+        chain.insert(prefix.into(), ChainLink::SubChain(object_chain));
+    }
+}
+
+fn text_object_command(name: &str, around: bool) -> String {
+    let extent = if around { "around" } else { "inner" };
+    format!("textobject.{}_{}", extent, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::EditorState;
+
+    /// The text left after typing `keys` into a buffer holding `text`.
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    #[test]
+    fn words_quotes_and_brackets() {
+        assert_eq!(after("one two three", "wdiw"), "one  three");
+        assert_eq!(after("one two three", "wdaw"), "one three");
+        assert_eq!(after(r#"say "hi there" now"#, "5ldi\""), r#"say "" now"#);
+        assert_eq!(after(r#"say "hi there" now"#, "5lda\""), "say now");
+        assert_eq!(after("f(a, (b))", "6ldi("), "f(a, ())");
+        assert_eq!(after("f(a, (b))", "6ld2i("), "f()");
+    }
+
+    #[test]
+    fn tags() {
+        let text = "<div><p class=\"x\">one<br/>two</p></div>";
+        assert_eq!(after(text, "18ldit"), "<div><p class=\"x\"></p></div>");
+        assert_eq!(after(text, "18ldat"), "<div></div>");
+        assert_eq!(after(text, "18ld2it"), "<div></div>");
+        assert_eq!(after(text, "18ld3it"), text);
+        assert_eq!(after("<a>one</b>", "3ldit"), "<a>one</b>");
+    }
+
+    #[test]
+    fn tags_over_many_lines() {
+        let lines = "line\n".repeat(50);
+        let text = format!("<outer>\n<inner>\n{}</inner>\n</outer>", lines);
+        let mut state = EditorState::with_text(&text);
+        state.type_keys("30jd2it");
+        assert_eq!(state.text(), "<outer></outer>");
+    }
+
+    #[test]
+    fn sentences() {
+        let text = "One two. Three four! Five? Six";
+        assert_eq!(after(text, "9ldis"), "One two.  Five? Six");
+        assert_eq!(after(text, "9ldas"), "One two. Five? Six");
+        assert_eq!(after(text, "9ld2is"), "One two. Five? Six");
+        assert_eq!(after(text, "9ld2as"), "One two. Six");
+        // on the whitespace between sentences, that is the inner sentence.
+        assert_eq!(after(text, "8ldis"), "One two.Three four! Five? Six");
+        // the last sentence has no whitespace after it, so it takes what is before it.
+        assert_eq!(after(text, "$das"), "One two. Three four! Five?");
+        // a full stop without whitespace after it doesn't end a sentence.
+        assert_eq!(after("See e.g.this one. Next", "dis"), " Next");
+        assert_eq!(after("One.\nTwo.", "jdis"), "One.\n");
+    }
+
+    #[test]
+    fn paragraphs() {
+        let text = "a\nb\n\nc\nd\n\n\ne";
+        assert_eq!(after(text, "dip"), "\nc\nd\n\n\ne");
+        assert_eq!(after(text, "dap"), "c\nd\n\n\ne");
+        assert_eq!(after(text, "d2ip"), "c\nd\n\n\ne");
+        assert_eq!(after(text, "d3ip"), "\n\ne");
+        assert_eq!(after(text, "3jdap"), "a\nb\n\ne");
+        // on blank lines, the inner paragraph is the blank lines.
+        assert_eq!(after(text, "5jdip"), "a\nb\n\nc\nd\ne");
+        // the last paragraph has no blank lines after it, so it takes those before it.
+        assert_eq!(after(text, "Gdap"), "a\nb\n\nc\nd");
+    }
+}
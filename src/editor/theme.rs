@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+
+use serde::Deserialize;
+
+use crate::editor::config::theme_dir;
+use crate::editor::ex::ExCommand;
+use crate::editor::fonts::{Color, Font, FontDefinition, TypeFace};
+use crate::editor::EditorState;
+
+/// The colors of the parts of the editor which aren't text.
+#[derive(Clone, Copy, Debug)]
+pub struct UiColors {
+    pub background: [f32; 4],
+    pub cursor: [f32; 4],
+    /// drawn behind selected text.
+    pub selection: [f32; 4],
+    pub status_line: [f32; 4],
+    pub status_line_background: [f32; 4],
+}
+
+impl Default for UiColors {
+    fn default() -> Self {
+        Self {
+            background: [0.0, 0.0, 0.0, 1.0],
+            cursor: [1.0, 1.0, 1.0, 1.0],
+            selection: [0.25, 0.3, 0.45, 1.0],
+            status_line: [1.0, 1.0, 1.0, 1.0],
+            status_line_background: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// How every font and the editor around them look.
+pub struct Theme {
+    pub name: String,
+    pub fonts: HashMap<Font, FontDefinition>,
+    pub ui: UiColors,
+}
+
+/// A theme file, ie. `~/.config/text_ed/themes/dusk.toml`:
+///
+/// ```toml
+/// name = "dusk"
+///
+/// [ui]
+/// background = "#1c1c24"
+/// selection = "navy"
+///
+/// [fonts.normal]
+/// color = "#dcdcdc"
+/// size = 28
+///
+/// [fonts.comment]
+/// color = "gray"
+/// italic = true
+/// ```
+///
+/// Colors are `#rrggbb`, `#rgb` or a name. Fonts the file leaves out, and
+/// the settings it leaves out of a font, are taken from `normal`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    name: String,
+    #[serde(default)]
+    ui: UiFile,
+    #[serde(default)]
+    fonts: BTreeMap<String, FontFile>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UiFile {
+    background: Option<String>,
+    cursor: Option<String>,
+    selection: Option<String>,
+    status_line: Option<String>,
+    status_line_background: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FontFile {
+    color: Option<String>,
+    background: Option<String>,
+    size: Option<f32>,
+    bold: Option<bool>,
+    italic: Option<bool>,
+    underline: Option<bool>,
+    strikethrough: Option<bool>,
+    typeface: Option<String>,
+}
+
+/// Sets `color` to the color written in `text`, if there is one.
+fn parse_color(color: &mut [f32; 4], text: &Option<String>) -> Result<(), String> {
+    if let Some(text) = text {
+        *color = Color::parse(text)?.into();
+    }
+    Ok(())
+}
+
+impl FontFile {
+    /// Applies the settings the file gives on top of `font`.
+    fn apply(&self, font: &mut FontDefinition) -> Result<(), String> {
+        parse_color(&mut font.color, &self.color)?;
+        if let Some(background) = &self.background {
+            font.background = Some(Color::parse(background)?.into());
+        }
+        if let Some(typeface) = &self.typeface {
+            font.typeface = TypeFace::from_name(typeface).ok_or_else(|| format!("Unknown typeface: {}", typeface))?;
+        }
+        font.size = self.size.unwrap_or(font.size);
+        font.bold = self.bold.unwrap_or(font.bold);
+        font.italic = self.italic.unwrap_or(font.italic);
+        font.underline = self.underline.unwrap_or(font.underline);
+        font.strikethrough = self.strikethrough.unwrap_or(font.strikethrough);
+        Ok(())
+    }
+}
+
+impl Theme {
+    /// Reads a theme from the text of its TOML file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: ThemeFile = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        for name in file.fonts.keys() {
+            Font::from_name(name).ok_or_else(|| format!("Unknown font: {}", name))?;
+        }
+
+        let mut normal = FontDefinition::default();
+        if let Some(settings) = file.fonts.get("normal") {
+            settings.apply(&mut normal).map_err(|e| format!("normal: {}", e))?;
+        }
+        let mut fonts = HashMap::new();
+        for font in Font::ALL {
+            let mut definition = normal;
+            definition.bold |= matches!(font, Font::Bold | Font::BoldItalic);
+            definition.italic |= matches!(font, Font::Italic | Font::BoldItalic);
+            let name = file.fonts.keys().find(|name| Font::from_name(name) == Some(font));
+            if let Some(name) = name {
+                file.fonts[name].apply(&mut definition).map_err(|e| format!("{}: {}", name, e))?;
+            }
+            fonts.insert(font, definition);
+        }
+
+        let mut ui = UiColors::default();
+        parse_color(&mut ui.background, &file.ui.background)?;
+        parse_color(&mut ui.cursor, &file.ui.cursor)?;
+        parse_color(&mut ui.selection, &file.ui.selection)?;
+        parse_color(&mut ui.status_line, &file.ui.status_line)?;
+        parse_color(&mut ui.status_line_background, &file.ui.status_line_background)?;
+
+        Ok(Self {
+            name: file.name,
+            fonts,
+            ui,
+        })
+    }
+}
+
+/// The themes which come with the editor, by name.
+const BUILTIN_THEMES: [(&str, &str); 2] = [
+    ("default", include_str!("themes/default.toml")),
+    ("light", include_str!("themes/light.toml")),
+];
+
+pub fn default_theme() -> Theme {
+    Theme::parse(BUILTIN_THEMES[0].1).expect("Error Loading Theme")
+}
+
+/// Finds the theme called `name`: a file `name.toml` in the user's theme
+/// directory, or else one of the themes which come with the editor.
+pub fn find_theme(name: &str) -> Result<Theme, String> {
+    if let Some(path) = theme_dir().map(|dir| dir.join(format!("{}.toml", name))) {
+        match fs::read_to_string(&path) {
+            Ok(text) => return Theme::parse(&text).map_err(|e| format!("\"{}\" {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("\"{}\" {}", path.display(), e)),
+        }
+    }
+    match BUILTIN_THEMES.iter().find(|(builtin, _)| *builtin == name) {
+        Some((_, text)) => Theme::parse(text),
+        None => Err(format!("Cannot find color scheme '{}'", name)),
+    }
+}
+
+/// The names of the themes `find_theme` can find.
+pub fn theme_names() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN_THEMES.iter().map(|(name, _)| name.to_string()).collect();
+    if let Some(entries) = theme_dir().and_then(|dir| fs::read_dir(dir).ok()) {
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            if path.extension().is_some_and(|extension| extension == "toml") {
+                names.extend(path.file_stem().and_then(|stem| stem.to_str()).map(String::from));
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+impl EditorState {
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme.fonts;
+        self.ui_colors = theme.ui;
+        self.colorscheme = theme.name;
+    }
+
+    /// Switches to the theme called `name`, see `find_theme`.
+    pub fn set_colorscheme(&mut self, name: &str) -> Result<(), String> {
+        let theme = find_theme(name)?;
+        self.set_theme(theme);
+        Ok(())
+    }
+}
+
+pub fn ex_colorscheme(s: &mut EditorState, command: &ExCommand, _: Option<(usize, usize)>) -> Result<(), String> {
+    if !command.args.is_empty() {
+        return s.set_colorscheme(&command.args);
+    }
+    s.message = format!("{} ({})", s.colorscheme, theme_names().join(", "));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_themes_parse() {
+        for (name, text) in BUILTIN_THEMES {
+            assert_eq!(Theme::parse(text).unwrap().name, name);
+        }
+    }
+
+    #[test]
+    fn fonts_fall_back_to_normal() {
+        let theme = Theme::parse(
+            r##"
+name = "test"
+
+[ui]
+cursor = "red"
+
+[fonts.normal]
+color = "#fff"
+size = 20
+typeface = "serif"
+
+[fonts.comment]
+color = "gray"
+italic = true
+"##,
+        )
+        .unwrap();
+        let comment = theme.fonts[&Font::Comment];
+        assert_eq!(comment.color, [128.0 / 255.0, 128.0 / 255.0, 128.0 / 255.0, 1.0]);
+        assert!(comment.italic && !comment.bold);
+        assert_eq!(comment.size, 20.0);
+
+        let bold = theme.fonts[&Font::BoldItalic];
+        assert_eq!(bold.color, [1.0; 4]);
+        assert!(bold.bold && bold.italic);
+        assert!(bold.typeface == TypeFace::Serif);
+
+        assert_eq!(theme.ui.cursor, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(theme.ui.background, UiColors::default().background);
+    }
+
+    #[test]
+    fn bad_themes() {
+        let error = |text: &str| Theme::parse(text).err().unwrap();
+        assert_eq!(error("name = \"x\"\n[fonts.nope]\n"), "Unknown font: nope");
+        assert_eq!(error("name = \"x\"\n[fonts.string]\ncolor = \"#12\"\n"), "string: Unknown color: #12");
+        assert_eq!(error("name = \"x\"\n[fonts.normal]\ntypeface = \"cursive\"\n"), "normal: Unknown typeface: cursive");
+        assert_eq!(error("name = \"x\"\n[ui]\ncursor = \"nope\"\n"), "Unknown color: nope");
+        assert!(Theme::parse("name = \"x\"\n[ui]\nborder = \"red\"\n").is_err());
+    }
+}
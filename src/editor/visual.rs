@@ -0,0 +1,239 @@
+use crate::editor::buffer::SelectionKind;
+use crate::editor::commands::CommandRegistry;
+use crate::editor::keymaps::{Chain, Key, KeyPress};
+use crate::editor::motions;
+use crate::editor::operators::Operator;
+use crate::editor::registers::{self, Register};
+use crate::editor::search;
+use crate::editor::textobjects;
+use crate::editor::{EditMode, EditorState};
+
+impl EditorState {
+    /// Enters visual mode with a selection anchored at the cursor. In visual
+    /// mode already, switches the kind of selection, or leaves visual mode if
+    /// it is already of that kind.
+    pub fn start_visual(&mut self, kind: SelectionKind) {
+        if let EditMode::Visual = self.mode {
+            match self.active_buffer().get_selection() {
+                Some(selection) if selection.kind == kind => self.set_normal_mode(),
+                _ => self.active_buffer_mut().set_selection_kind(kind),
+            }
+            return;
+        }
+
+        self.active_buffer_mut().start_selection(kind);
+        self.set_visual_mode();
+    }
+
+    /// Applies `operator` to the selection, and leaves visual mode.
+    pub fn operate_on_selection(&mut self, operator: Operator, register: Option<char>) {
+        let buffer = self.active_buffer();
+        let (selection, (start, end)) = match (buffer.get_selection(), buffer.selection_bounds()) {
+            (Some(selection), Some(bounds)) => (selection, bounds),
+            _ => return,
+        };
+        self.set_normal_mode();
+
+        match selection.kind {
+            SelectionKind::Charwise => {
+                let buffer = self.active_buffer();
+                let line_end = buffer.pos_to_char((end.0, buffer.line_len(end.0 - 1)));
+                let end = (buffer.pos_to_char(end) + 1).min(line_end.max(buffer.pos_to_char(end)));
+                let range = buffer.pos_to_char(start)..end;
+                self.apply_operator(operator, range, false, register);
+            }
+            SelectionKind::Linewise => {
+                self.active_buffer_mut().set_cursor_pos(start);
+                let range = self.line_range(start.0, end.0);
+                self.apply_operator(operator, range, true, register);
+            }
+            SelectionKind::Blockwise => self.operate_on_block(operator, register, selection.anchor, (start, end)),
+        }
+    }
+
+    /// Blocks are operated on one line at a time, bottom up so the earlier
+    /// lines keep their positions. A change only inserts on the first line,
+    /// and shifting a block shifts the whole lines.
+    fn operate_on_block(
+        &mut self,
+        operator: Operator,
+        register: Option<char>,
+        anchor: (usize, usize),
+        (start, end): ((usize, usize), (usize, usize)),
+    ) {
+        if let Operator::Indent | Operator::Outdent = operator {
+            self.active_buffer_mut().set_cursor_pos(start);
+            let range = self.line_range(start.0, end.0);
+            self.apply_operator(operator, range, true, register);
+            return;
+        }
+
+        let cursor = self.active_buffer().get_cursor_pos();
+        let left = anchor.1.min(cursor.1);
+        let right = anchor.1.max(cursor.1) + 1;
+
+        let buffer = self.active_buffer();
+        let ranges: Vec<_> = (start.0..=end.0)
+            .map(|line| {
+                let len = buffer.line_len(line - 1);
+                buffer.pos_to_char((line, left.min(len)))..buffer.pos_to_char((line, right.min(len)))
+            })
+            .collect();
+        // the register gets the whole block at once, not a line at a time.
+        let block: Vec<String> = ranges.iter().map(|range| buffer.get_range(range.clone())).collect();
+        self.store_operator_text(operator, register, Register::blockwise(block.join("\n")));
+
+        let buffer = self.active_buffer_mut();
+        buffer.set_cursor_pos((start.0, left));
+        buffer.begin_change();
+        for (line, range) in (start.0..end.0 + 1).zip(ranges).rev() {
+            let operator = match operator {
+                Operator::Change if line != start.0 => Operator::Delete,
+                operator => operator,
+            };
+            if range.is_empty() && operator != Operator::Change {
+                continue;
+            }
+            self.active_buffer_mut().set_cursor_pos((line, left));
+            self.perform_operator(operator, range, false);
+        }
+        if operator != Operator::Change {
+            self.active_buffer_mut().end_change();
+        }
+        self.active_buffer_mut().set_cursor_pos((start.0, left));
+    }
+
+    pub fn swap_selection_ends(&mut self) {
+        self.active_buffer_mut().swap_selection_ends();
+    }
+}
+
+/// The kinds of selection, with the names of their commands.
+const SELECTION_KINDS: [(SelectionKind, &str); 3] = [
+    (SelectionKind::Charwise, "charwise"),
+    (SelectionKind::Linewise, "linewise"),
+    (SelectionKind::Blockwise, "blockwise"),
+];
+
+/// Registers the `visual.*` commands: one to start or switch to each kind
+/// of selection, and one to apply each operator to the selection.
+pub fn register_commands(registry: &mut CommandRegistry) {
+    for (kind, name) in SELECTION_KINDS {
+        let description = format!("start or switch to a {} selection", name);
+        registry.register(format!("visual.{}", name), description, &[], move |s, _| s.start_visual(kind));
+    }
+    for operator in Operator::ALL {
+        let description = format!("{} the selection", operator.name().replace('_', " "));
+        registry.register(format!("visual.{}", operator.name()), description, &[], move |s, ctx| {
+            s.operate_on_selection(operator, ctx.register)
+        });
+    }
+    registry.register("visual.swap_ends", "move the cursor to the other end of the selection", &[], |s, _| {
+        s.swap_selection_ends()
+    });
+}
+
+/// Builds the chain used in visual mode: motions to extend the selection,
+/// text objects to pick it, and operators to act on it.
+pub fn visual_chain() -> Chain {
+    let mut chain = Chain::new();
+    motions::insert_motions(&mut chain);
+    textobjects::insert_text_objects(&mut chain);
+
+    let operators = [
+        ('d', Operator::Delete),
+        ('x', Operator::Delete),
+        ('c', Operator::Change),
+        ('s', Operator::Change),
+        ('y', Operator::Yank),
+        ('>', Operator::Indent),
+        ('<', Operator::Outdent),
+        ('u', Operator::Lowercase),
+        ('U', Operator::Uppercase),
+        ('~', Operator::ToggleCase),
+    ];
+    for (key, operator) in operators {
+        chain.insert(key.into(), format!("visual.{}", operator.name()).into());
+    }
+
+    insert_visual_toggles(&mut chain);
+    registers::insert_register_selection(&mut chain);
+    search::insert_search_repeats(&mut chain);
+    chain.insert(':'.into(), "editor.command_line".into());
+    chain.insert('o'.into(), "visual.swap_ends".into());
+    chain.insert(Key::Escape.into(), "editor.normal_mode".into());
+    chain
+}
+
+/// Binds `v`, `V` and Ctrl-v, which start (or switch) visual selections.
+pub fn insert_visual_toggles(chain: &mut Chain) {
+    let keys = [KeyPress::from('v'), KeyPress::from('V'), KeyPress::control('v')];
+    for (key, (_, name)) in keys.iter().zip(SELECTION_KINDS.iter()) {
+        chain.insert(*key, format!("visual.{}", name).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::buffer::SelectionKind;
+    use crate::editor::{EditMode, EditorState};
+
+    fn after(text: &str, keys: &str) -> String {
+        let mut state = EditorState::with_text(text);
+        state.type_keys(keys);
+        state.text()
+    }
+
+    #[test]
+    fn charwise() {
+        assert_eq!(after("one two three", "wvlld"), "one  three");
+        assert_eq!(after("one two three", "wvhhd"), "onwo three");
+        assert_eq!(after("one\ntwo", "lvjd"), "oo");
+        assert_eq!(after("one two", "veU"), "ONE two");
+        assert_eq!(after("one two", "wviw~"), "one TWO");
+    }
+
+    #[test]
+    fn linewise() {
+        assert_eq!(after("1\n2\n3\n4", "jVjd"), "1\n4");
+        assert_eq!(after("1\n2\n3", "Vjyjjp"), "1\n2\n3\n1\n2");
+        assert_eq!(after("a\nb", "Vj>"), after("a\nb", ">j"));
+    }
+
+    #[test]
+    fn blockwise() {
+        let text = "abcd\nefgh\nijkl";
+        assert_eq!(after(text, "l<C-v>jld"), "ad\neh\nijkl");
+        assert_eq!(after(text, "l<C-v>jjlcX<Esc>"), "aXd\neh\nil");
+        // short lines are left alone.
+        assert_eq!(after("abcd\na\nabcd", "ll<C-v>jjd"), "abd\na\nabd");
+    }
+
+    #[test]
+    fn switch_and_leave() {
+        let mut state = EditorState::with_text("one\ntwo");
+        state.type_keys("vV");
+        assert!(matches!(state.mode, EditMode::Visual));
+        assert_eq!(state.active_buffer().get_selection().map(|selection| selection.kind), Some(SelectionKind::Linewise));
+        state.type_keys("V");
+        assert!(matches!(state.mode, EditMode::Normal));
+        assert_eq!(state.active_buffer().get_selection(), None);
+
+        state.type_keys("v<Esc>");
+        assert!(matches!(state.mode, EditMode::Normal));
+    }
+
+    #[test]
+    fn swap_ends() {
+        assert_eq!(after("one two three", "wvlohd"), "oneo three");
+    }
+
+    #[test]
+    fn into_a_register() {
+        let mut state = EditorState::with_text("one two");
+        state.type_keys("ve\"ay");
+        assert_eq!(state.registers.get('a').unwrap().map(|register| register.text).as_deref(), Some("one"));
+        assert_eq!(state.text(), "one two");
+        assert_eq!(state.active_buffer().get_mark('>'), Some((1, 2)));
+    }
+}
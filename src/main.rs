@@ -1,4 +1,4 @@
-use wgpu_glyph::{ab_glyph, GlyphBrushBuilder, Text, Section, OwnedSection, OwnedText};
+use wgpu_glyph::{ab_glyph, FontId, GlyphBrushBuilder, Text, Section, OwnedSection, OwnedText};
 use winit::{
     dpi::PhysicalSize,
     event::*,
@@ -8,40 +8,16 @@ use winit::{
 
 use std::rc::Rc;
 use std::borrow::Cow;
+use std::time::Instant;
 
 use pixels::{PixelsBuilder, SurfaceTexture};
 
 mod editor;
 use editor::EditorState;
-
-// when do we do things?
-// during the event loop.
-// what does the event loop need?
-// static ownership of everything that it captures.
-// solutions?
-//  - everything to do with anything gets moved inside of the event loop
-//  - somehow segregate anything to do with the event handler from the editor logic.
-//    + separate threads?
-//
-// what exactly do you want to do?
-// you want to store a plain reference to a Chain, and another reference to to sub chains in that chain.
-//  - these chains will need to be mutable.
-//  - because of this, you probably shouldn't actually store these as references.
-//  - how do you know what the current active subchain is then?
-//  - you can't use a reference if the struct owns the chain (the reason this is a problem in the first place)
-//  - you can't clone the subchain, because of the Boxed values
-//
-//  - what should EditorState functions be able to do?
-//  - modify Chains?
-//  - modify the buffer?
-//  - modify anything?
-//  - Editor Struct, which holds editor variables, keymaps, etc.
-//  - EditorState holds references to the keymaps held in the Editor struct.
-//  - ^ this doesn't work, would have to pass the lifetime up, and since the keympas are mut, the references would be garbage
-//
-//  - when traversing down a keymap, you pop each subchain, store the parent in a vector. when you reach the end of a chain, you just go back through
-//    the vector, and add everything back where it should go.
-//
+use editor::fonts::TypeFace;
+use editor::graphics::FontFaces;
+use editor::clipboard::SystemClipboard;
+use editor::input::InputTranslator;
 
 fn main() {
     env_logger::init();
@@ -102,31 +78,88 @@ fn main() {
 
     let context = pixels.context();
 
-    let bookerly = ab_glyph::FontArc::try_from_slice(include_bytes!(
-        "/System/Library/Fonts/Menlo.ttc"
-    ))
-    .expect("Error Loading Font");
+    let menlo: &'static [u8] = include_bytes!("/System/Library/Fonts/Menlo.ttc");
+    let helvetica: &'static [u8] = include_bytes!("/System/Library/Fonts/Helvetica.ttc");
 
-    let fira_code = ab_glyph::FontArc::try_from_slice(include_bytes!(
-        "/System/Library/Fonts/Helvetica.ttc"
-    ))
-    .expect("Error Loading Font");
+    // FontId(0) is the regular face text is drawn in, and FontId(1) the status line's.
+    // The index of each style in a collection is particular to the file,
+    // these are Menlo's and Helvetica's, which also holds light faces.
+    let mut faces = FontFaces::default();
+    let mut fonts = Vec::new();
+    for (data, index, typeface, bold, italic) in [
+        (menlo, 0, TypeFace::Monospace, false, false),
+        (helvetica, 0, TypeFace::SansSerif, false, false),
+        (menlo, 1, TypeFace::Monospace, true, false),
+        (menlo, 2, TypeFace::Monospace, false, true),
+        (menlo, 3, TypeFace::Monospace, true, true),
+        (helvetica, 1, TypeFace::SansSerif, true, false),
+        (helvetica, 2, TypeFace::SansSerif, false, true),
+        (helvetica, 3, TypeFace::SansSerif, true, true),
+    ] {
+        match ab_glyph::FontRef::try_from_slice_and_index(data, index) {
+            Ok(font) => {
+                fonts.push(ab_glyph::FontArc::new(font));
+                faces.add(typeface, bold, italic);
+            }
+            Err(_) if !bold && !italic => panic!("Error Loading Font"),
+            // a missing style is faked from the regular face.
+            Err(_) => {}
+        }
+    }
 
-    let mut glyph_brush = GlyphBrushBuilder::using_fonts(vec![bookerly, fira_code])
+    let mut glyph_brush = GlyphBrushBuilder::using_fonts(fonts)
         .build(&context.device, context.texture_format);
 
 
     let mut editor_state = EditorState::new();
+    let mut input = InputTranslator::new();
+    // without a system clipboard, `+` and `*` only last as long as the editor.
+    match SystemClipboard::new() {
+        Ok(clipboard) => editor_state.registers.set_clipboard(Box::new(clipboard)),
+        Err(e) => editor_state.message = format!("No system clipboard: {}", e),
+    }
+    editor_state.load_user_config();
+    if let Some(path) = std::env::args().nth(1) {
+        editor_state.open_file(path);
+    }
+
+    event_loop.run(move |event, _, control_flow| {
+    // wake up to settle a half typed key sequence, if one is waiting.
+    *control_flow = match editor_state.key_deadline {
+        Some(deadline) => ControlFlow::WaitUntil(deadline),
+        None => ControlFlow::Wait,
+    };
 
-    event_loop.run(move |event, _, control_flow| { match event {
+    match event {
+        Event::NewEvents(StartCause::ResumeTimeReached { .. }) if editor_state.check_key_timeout(Instant::now()) => {
+            if editor_state.should_quit {
+                *control_flow = ControlFlow::Exit;
+            }
+            window.request_redraw();
+        }
         Event::WindowEvent {
             event, // this was `ref event` for some reason...
             window_id,
         } if window_id == window.id() => match event {
             WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::ModifiersChanged(modifiers) => input.modifiers_changed(modifiers),
+            WindowEvent::KeyboardInput { input: keyboard_input, .. } => {
+                if let Some(key) = input.keyboard_input(&keyboard_input) {
+                    editor_state.process_keystroke(key);
+                    if editor_state.should_quit {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    window.request_redraw();
+                }
+            }
             WindowEvent::ReceivedCharacter(character) => {
-                editor_state.process_keystroke(character);
-                window.request_redraw();
+                if let Some(key) = input.received_character(character) {
+                    editor_state.process_keystroke(key);
+                    if editor_state.should_quit {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    window.request_redraw();
+                }
             }
             WindowEvent::Resized(PhysicalSize { width, height }) => {
                 pixels.resize_buffer(width, height);
@@ -136,38 +169,66 @@ fn main() {
             _ => {}
         },
         Event::RedrawRequested(_) => {
-            fn pixel_buffer_thing(p: &mut [u8]) {
-                p.copy_from_slice(&[0, 0, 0, 255]);
-            }
-            pixels.get_frame().chunks_mut(4).for_each(pixel_buffer_thing);
-
             let PhysicalSize { width, height } = window.inner_size();
+            let ui_colors = editor_state.ui_colors;
+            let frame_size = (width as f32, height as f32);
+            let status_scale = 20.0;
+            let status_height = editor::graphics::line_height(&glyph_brush, FontId(1), status_scale);
+            let status_top = frame_size.1 - status_height;
+            editor::graphics::fill_rect(pixels.get_frame(), width, (0.0, 0.0, frame_size.0, frame_size.1), ui_colors.background);
+            editor::graphics::fill_rect(
+                pixels.get_frame(),
+                width,
+                (0.0, status_top, frame_size.0, frame_size.1),
+                ui_colors.status_line_background,
+            );
+
             editor_state.update();
+            // a large file is highlighted a chunk per frame, between keystrokes.
+            if editor_state.active_buffer().is_highlighting() {
+                window.request_redraw();
+            }
 
-            use wgpu_glyph::FontId;
             let status_text = Text::new(editor_state.status_line.as_str())
-                .with_scale(20.0)
-                .with_color([1.0, 1.0, 1.0, 1.0])
+                .with_scale(status_scale)
+                .with_color(ui_colors.status_line)
                 .with_font_id(FontId(1));
 
             glyph_brush.queue(Section {
-                screen_position: (0.0, height as f32 - 20.0),
-                bounds: (width as f32, 25.0),
+                screen_position: (0.0, status_top),
+                bounds: (frame_size.0, status_height),
                 text: vec![status_text],
                 ..Section::default()
             });
 
+            // only the lines which fit above the status line are laid out.
+            let text_size = editor_state.theme[&editor::fonts::Font::Normal].size;
+            let rows = (status_top / editor::graphics::line_height(&glyph_brush, FontId(0), text_size)) as usize;
+            let visible_lines = editor_state.active_buffer_mut().scroll_to_cursor(rows);
             let display_buffer = editor_state.get_display_buffer();
 
+            let (section_text, decorations) = editor_state.get_section_text(&display_buffer, visible_lines, &faces);
             let buffer_section = OwnedSection {
                 screen_position: (0.0, 0.0),
-                bounds: (width as f32, height as f32),
-                text: editor_state.get_section_text(&display_buffer),
+                bounds: (frame_size.0, status_top),
+                text: section_text,
                 ..Default::default()
             };
-            println!("{:?}", buffer_section.text);
 
+            editor::graphics::draw_decorations(
+                &mut glyph_brush,
+                &buffer_section.to_borrowed(),
+                &decorations,
+                pixels.get_frame(),
+                width,
+            );
+            let synthetic = editor::graphics::synthetic_runs(&mut glyph_brush, &buffer_section.to_borrowed(), &decorations);
             glyph_brush.queue(buffer_section.to_borrowed());
+            // faked bold is drawn with everything else, faked italics a line at a time after it.
+            let (oblique, upright): (Vec<_>, Vec<_>) = synthetic.into_iter().partition(|run| run.italic);
+            for run in &upright {
+                run.queue(&mut glyph_brush);
+            }
 
             pixels
                 .render_with(|encoder, render_target, context| {
@@ -184,6 +245,21 @@ fn main() {
                         )
                         .expect("Draw queued");
 
+                    for line in oblique.chunk_by(|a, b| a.baseline == b.baseline) {
+                        for run in line {
+                            run.queue(&mut glyph_brush);
+                        }
+                        glyph_brush
+                            .draw_queued_with_transform(
+                                &context.device,
+                                &mut staging_belt,
+                                encoder,
+                                render_target,
+                                editor::graphics::oblique_transform(width, height, line[0].baseline),
+                            )
+                            .expect("Draw queued");
+                    }
+
                     staging_belt.finish();
                     Result::Ok(())
                 })
@@ -198,8 +274,6 @@ fn main() {
         Event::MainEventsCleared => {
             //window.request_redraw();
         }
-        _ => {
-            *control_flow = winit::event_loop::ControlFlow::Wait;
-        }
+        _ => {}
     }});
 }